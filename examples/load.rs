@@ -0,0 +1,207 @@
+//! Load test for a running judge: pollers hammer `GET /jobs/{id}` while submitters keep
+//! the judge busy with `POST /jobs`, then the latency of both is reported.
+//!
+//! ```bash
+//! $ cargo run --release --example load -- --url http://127.0.0.1:12345 --problem-id 0 --language Rust --source main.rs
+//! ```
+
+use clap::Arg;
+use serde_json::json;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Stats {
+    name: &'static str,
+    latencies: Vec<Duration>,
+    errors: u32,
+}
+
+impl Stats {
+    fn report(mut self, seconds: f64) {
+        self.latencies.sort();
+        let quantile = |q: f64| -> f64 {
+            if self.latencies.is_empty() {
+                return 0.0;
+            }
+            let index = ((self.latencies.len() - 1) as f64 * q).round() as usize;
+            self.latencies[index].as_secs_f64() * 1000.0
+        };
+        println!(
+            "{:<8} {:>7} requests {:>8.1}/s  errors {:>4}  p50 {:>7.2}ms  p95 {:>7.2}ms  p99 {:>7.2}ms  max {:>7.2}ms",
+            self.name,
+            self.latencies.len(),
+            self.latencies.len() as f64 / seconds,
+            self.errors,
+            quantile(0.5),
+            quantile(0.95),
+            quantile(0.99),
+            quantile(1.0)
+        );
+    }
+}
+
+fn main() {
+    let matches = clap::App::new("load")
+        .about("Measure API latency while the judge is busy.")
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .takes_value(true)
+                .default_value("http://127.0.0.1:12345"),
+        )
+        .arg(
+            Arg::with_name("problem_id")
+                .long("problem-id")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("language")
+                .long("language")
+                .takes_value(true)
+                .default_value("Rust"),
+        )
+        .arg(
+            Arg::with_name("source")
+                .long("source")
+                .takes_value(true)
+                .help("The source file to submit."),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .help("The token of the user to submit as, from `POST /login`."),
+        )
+        .arg(
+            Arg::with_name("pollers")
+                .long("pollers")
+                .takes_value(true)
+                .default_value("32"),
+        )
+        .arg(
+            Arg::with_name("submitters")
+                .long("submitters")
+                .takes_value(true)
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("seconds")
+                .long("seconds")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .get_matches();
+    let url = matches
+        .value_of("url")
+        .unwrap()
+        .trim_end_matches('/')
+        .to_string();
+    let problem_id: u32 = matches.value_of("problem_id").unwrap().parse().unwrap();
+    let language = matches.value_of("language").unwrap().to_string();
+    let source_code = match matches.value_of("source") {
+        Some(path) => std::fs::read_to_string(path).expect("Cannot read the source file"),
+        None => "fn main() {}".to_string(),
+    };
+    let pollers: u32 = matches.value_of("pollers").unwrap().parse().unwrap();
+    let submitters: u32 = matches.value_of("submitters").unwrap().parse().unwrap();
+    let duration = Duration::from_secs(matches.value_of("seconds").unwrap().parse().unwrap());
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(token) = matches.value_of("token") {
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+    }
+    let client = reqwest::blocking::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap();
+    let body = json!({
+        "source_code": source_code,
+        "language": language,
+        "contest_id": 0,
+        "problem_id": problem_id,
+    });
+    // The newest job id, which the pollers ask for.
+    let latest = match client.post(format!("{}/jobs", url)).json(&body).send() {
+        Ok(resp) if resp.status().is_success() => {
+            let job: serde_json::Value = resp.json().unwrap();
+            Arc::new(AtomicU32::new(job["id"].as_u64().unwrap() as u32))
+        }
+        Ok(resp) => panic!("The first submission failed: {}", resp.text().unwrap()),
+        Err(e) => panic!("Cannot reach {}: {}", url, e),
+    };
+
+    let start = Instant::now();
+    let mut handles = vec![];
+    for _ in 0..submitters {
+        let (client, url, body, latest) =
+            (client.clone(), url.clone(), body.clone(), latest.clone());
+        handles.push(thread::spawn(move || {
+            let mut stats = Stats {
+                name: "submit",
+                latencies: vec![],
+                errors: 0,
+            };
+            while start.elapsed() < duration {
+                let begin = Instant::now();
+                match client.post(format!("{}/jobs", url)).json(&body).send() {
+                    Ok(resp) if resp.status().is_success() => {
+                        stats.latencies.push(begin.elapsed());
+                        let job: serde_json::Value = resp.json().unwrap();
+                        latest.fetch_max(job["id"].as_u64().unwrap() as u32, Ordering::Relaxed);
+                    }
+                    _ => stats.errors += 1,
+                }
+            }
+            stats
+        }));
+    }
+    for _ in 0..pollers {
+        let (client, url, latest) = (client.clone(), url.clone(), latest.clone());
+        handles.push(thread::spawn(move || {
+            let mut stats = Stats {
+                name: "poll",
+                latencies: vec![],
+                errors: 0,
+            };
+            while start.elapsed() < duration {
+                let begin = Instant::now();
+                let id = latest.load(Ordering::Relaxed);
+                match client.get(format!("{}/jobs/{}", url, id)).send() {
+                    Ok(resp) if resp.status().is_success() => stats.latencies.push(begin.elapsed()),
+                    _ => stats.errors += 1,
+                }
+            }
+            stats
+        }));
+    }
+
+    let mut submit = Stats {
+        name: "submit",
+        latencies: vec![],
+        errors: 0,
+    };
+    let mut poll = Stats {
+        name: "poll",
+        latencies: vec![],
+        errors: 0,
+    };
+    for handle in handles {
+        let stats = handle.join().unwrap();
+        let total = if stats.name == "submit" {
+            &mut submit
+        } else {
+            &mut poll
+        };
+        total.latencies.extend(stats.latencies);
+        total.errors += stats.errors;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    submit.report(seconds);
+    poll.report(seconds);
+}
@@ -0,0 +1,338 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::config::Problem;
+use crate::contests::SerdeContest;
+use crate::groups::SerdeGroup;
+use crate::handler::jobs::JobsFilter;
+use crate::migrations;
+use crate::runner::SerdeJob;
+use crate::storage::Storage;
+use crate::teams::SerdeTeam;
+use crate::users::{Role, SerdeUser};
+
+/// Bump when a record changes in a way older builds cannot read.
+/// 2: team records.
+/// 3: group records.
+pub const FORMAT_VERSION: u32 = 3;
+const FORMAT_NAME: &str = "mroj-export";
+/// Jobs are read in pages of this size, so that exports never load every job at once.
+const PAGE_SIZE: u32 = 500;
+
+/// One line of an export. The header comes first, then users, teams, groups, contests, problems
+/// and jobs.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+pub enum Record {
+    Header {
+        format: String,
+        version: u32,
+        schema_version: u32,
+        exported_time: String,
+    },
+    User(SerdeUser),
+    Team(SerdeTeam),
+    Group(SerdeGroup),
+    Contest(SerdeContest),
+    Problem(Problem),
+    Job(SerdeJob),
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub users: u32,
+    pub teams: u32,
+    pub groups: u32,
+    pub contests: u32,
+    pub problems: u32,
+    pub jobs: u32,
+    /// Records that were merged into existing data or skipped, one line each.
+    pub conflicts: Vec<String>,
+}
+
+fn write_record(out: &mut dyn Write, record: &Record) -> Result<(), String> {
+    let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    writeln!(out, "{}", line).map_err(|e| format!("Cannot write the export: {}", e))
+}
+
+/// Write every user, team, group, contest, problem and job as JSON lines.
+pub async fn export(storage: &dyn Storage, out: &mut dyn Write) -> Result<Summary, String> {
+    let db_error = |e| format!("Database Error: {}", e);
+    let mut summary = Summary::default();
+    write_record(
+        out,
+        &Record::Header {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            schema_version: storage.schema_version().await.map_err(db_error)?,
+            exported_time: Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        },
+    )?;
+    for user in storage.list_users().await.map_err(db_error)? {
+        write_record(out, &Record::User(user))?;
+        summary.users += 1;
+    }
+    for team in storage.list_teams().await.map_err(db_error)? {
+        write_record(out, &Record::Team(team))?;
+        summary.teams += 1;
+    }
+    for group in storage.list_groups().await.map_err(db_error)? {
+        write_record(out, &Record::Group(group))?;
+        summary.groups += 1;
+    }
+    for contest in storage.list_contests().await.map_err(db_error)? {
+        write_record(out, &Record::Contest(contest))?;
+        summary.contests += 1;
+    }
+    for content in storage.list_problems().await.map_err(db_error)? {
+        let problem: Problem = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        write_record(out, &Record::Problem(problem))?;
+        summary.problems += 1;
+    }
+    let mut filter = JobsFilter {
+        limit: Some(PAGE_SIZE),
+        ..JobsFilter::default()
+    };
+    loop {
+        let jobs = storage.list_jobs(&filter).await.map_err(db_error)?;
+        for job in &jobs {
+            write_record(out, &Record::Job(job.clone()))?;
+            summary.jobs += 1;
+        }
+        match jobs.last() {
+            Some(job) if jobs.len() == PAGE_SIZE as usize => filter.cursor = Some(job.id),
+            _ => break,
+        }
+    }
+    out.flush()
+        .map_err(|e| format!("Cannot write the export: {}", e))?;
+    Ok(summary)
+}
+
+/// Read an export into the database. Users, teams and groups are matched by name, every
+/// contest and job gets a new id, and problems keep theirs. The database is migrated first.
+pub async fn import(storage: &dyn Storage, input: &mut dyn BufRead) -> Result<Summary, String> {
+    let db_error = |e| format!("Database Error: {}", e);
+    migrations::migrate(storage).await?;
+    let mut summary = Summary::default();
+    // Ids in the export to ids in this database.
+    let mut user_ids: HashMap<u32, u32> = HashMap::new();
+    let mut team_ids: HashMap<u32, u32> = HashMap::new();
+    let mut group_ids: HashMap<u32, u32> = HashMap::new();
+    let mut contest_ids: HashMap<u32, u32> = HashMap::new();
+    let mut header = false;
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("Cannot read the export: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        match record {
+            Record::Header {
+                format, version, ..
+            } => {
+                if format != FORMAT_NAME {
+                    return Err(format!("line {}: not an export of this judge", index + 1));
+                }
+                if version > FORMAT_VERSION {
+                    return Err(format!(
+                        "The export has format version {}, but this build only reads up to version {}.",
+                        version, FORMAT_VERSION
+                    ));
+                }
+                header = true;
+            }
+            _ if !header => return Err("The export does not start with a header.".to_string()),
+            Record::User(user) => {
+                match storage
+                    .get_user_by_name(&user.name)
+                    .await
+                    .map_err(db_error)?
+                {
+                    Some(existing) => {
+                        summary.conflicts.push(format!(
+                            "user {} '{}' already exists as user {}, merged",
+                            user.id, user.name, existing.id
+                        ));
+                        user_ids.insert(user.id, existing.id);
+                    }
+                    None => {
+                        let id = storage.insert_user(&user.name).await.map_err(db_error)?;
+                        if user.role != Role::default() {
+                            storage.set_role(id, user.role).await.map_err(db_error)?;
+                        }
+                        if let Some(display_name) = &user.display_name {
+                            storage
+                                .set_display_name(id, Some(display_name))
+                                .await
+                                .map_err(db_error)?;
+                        }
+                        if let Some(time) = &user.deactivated_time {
+                            storage
+                                .set_deactivated(id, Some(time))
+                                .await
+                                .map_err(db_error)?;
+                        }
+                        user_ids.insert(user.id, id);
+                        summary.users += 1;
+                    }
+                }
+            }
+            Record::Team(mut team) => {
+                match storage
+                    .get_team_by_name(&team.name)
+                    .await
+                    .map_err(db_error)?
+                {
+                    // The members of the existing team are kept.
+                    Some(existing) => {
+                        summary.conflicts.push(format!(
+                            "team {} '{}' already exists as team {}, merged",
+                            team.id, team.name, existing.id
+                        ));
+                        team_ids.insert(team.id, existing.id);
+                    }
+                    None => {
+                        let old_id = team.id;
+                        team.member_ids = team
+                            .member_ids
+                            .iter()
+                            .filter_map(|id| user_ids.get(id).copied())
+                            .collect();
+                        let id = storage.insert_team(&team).await.map_err(db_error)?;
+                        team_ids.insert(old_id, id);
+                        summary.teams += 1;
+                    }
+                }
+            }
+            Record::Group(mut group) => {
+                let old_id = group.id;
+                group.member_ids = group
+                    .member_ids
+                    .iter()
+                    .filter_map(|id| user_ids.get(id).copied())
+                    .collect();
+                match storage
+                    .get_group_by_name(&group.name)
+                    .await
+                    .map_err(db_error)?
+                {
+                    // Members are added to the existing group.
+                    Some(existing) => {
+                        summary.conflicts.push(format!(
+                            "group {} '{}' already exists as group {}, merged",
+                            old_id, group.name, existing.id
+                        ));
+                        storage
+                            .add_group_members(existing.id, &group.member_ids)
+                            .await
+                            .map_err(db_error)?;
+                        group_ids.insert(old_id, existing.id);
+                    }
+                    None => {
+                        let id = storage.insert_group(&group).await.map_err(db_error)?;
+                        group_ids.insert(old_id, id);
+                        summary.groups += 1;
+                    }
+                }
+            }
+            Record::Contest(mut contest) => {
+                let old_id = contest.id;
+                let mut missing = vec![];
+                contest.user_ids = contest
+                    .user_ids
+                    .iter()
+                    .filter_map(|id| {
+                        let mapped = user_ids.get(id).copied();
+                        if mapped.is_none() {
+                            missing.push(*id);
+                        }
+                        mapped
+                    })
+                    .collect();
+                if !missing.is_empty() {
+                    summary.conflicts.push(format!(
+                        "contest {} lists users {:?} that are not in the export, dropped them",
+                        old_id, missing
+                    ));
+                }
+                contest.team_ids = contest
+                    .team_ids
+                    .iter()
+                    .filter_map(|id| team_ids.get(id).copied())
+                    .collect();
+                contest.group_ids = contest
+                    .group_ids
+                    .iter()
+                    .filter_map(|id| group_ids.get(id).copied())
+                    .collect();
+                let id = storage.insert_contest(&contest).await.map_err(db_error)?;
+                contest_ids.insert(old_id, id);
+                summary.contests += 1;
+            }
+            Record::Problem(problem) => {
+                let content = serde_json::to_string(&problem).unwrap();
+                let existing = storage.list_problems().await.map_err(db_error)?;
+                let same_id = existing
+                    .iter()
+                    .filter_map(|other| serde_json::from_str::<Problem>(other).ok())
+                    .find(|other| other.id == problem.id);
+                match same_id {
+                    Some(other) if serde_json::to_string(&other).unwrap() == content => {}
+                    Some(_) => summary.conflicts.push(format!(
+                        "problem {} differs from the one in the database, kept the existing one",
+                        problem.id
+                    )),
+                    None => {
+                        storage
+                            .save_problem(problem.id, &content)
+                            .await
+                            .map_err(db_error)?;
+                        summary.problems += 1;
+                    }
+                }
+            }
+            Record::Job(mut job) => {
+                let user_id = match user_ids.get(&job.submission.user_id) {
+                    Some(id) => *id,
+                    None => {
+                        summary.conflicts.push(format!(
+                            "job {} belongs to user {} who is not in the export, skipped",
+                            job.id, job.submission.user_id
+                        ));
+                        continue;
+                    }
+                };
+                let contest_id = match job.submission.contest_id {
+                    0 => 0,
+                    id => match contest_ids.get(&id) {
+                        Some(id) => *id,
+                        None => {
+                            summary.conflicts.push(format!(
+                                "job {} belongs to contest {} which is not in the export, skipped",
+                                job.id, id
+                            ));
+                            continue;
+                        }
+                    },
+                };
+                job.submission.user_id = user_id;
+                job.submission.contest_id = contest_id;
+                job.submission.team_id = job
+                    .submission
+                    .team_id
+                    .and_then(|id| team_ids.get(&id).copied());
+                storage.insert_job(&job).await.map_err(db_error)?;
+                summary.jobs += 1;
+            }
+        }
+    }
+    if !header {
+        return Err("The export is empty.".to_string());
+    }
+    Ok(summary)
+}
@@ -0,0 +1,70 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error_log;
+use crate::storage::Storage;
+
+/// An admin action on a user, kept in the database.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: u32,
+    pub time: String,
+    /// The admin who did it.
+    pub actor_id: u32,
+    pub action: String,
+    /// The user it was done to.
+    pub user_id: u32,
+    #[serde(default)]
+    pub detail: String,
+}
+
+/// An entry for an action done now, not yet stored.
+pub fn entry(actor_id: u32, action: &str, user_id: u32, detail: &str) -> AuditEntry {
+    AuditEntry {
+        id: 0,
+        time: Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        actor_id,
+        action: action.to_string(),
+        user_id,
+        detail: detail.to_string(),
+    }
+}
+
+pub fn log(entry: &AuditEntry) {
+    log::info!(
+        "Audit: user {} did {} on user {} {}",
+        entry.actor_id,
+        entry.action,
+        entry.user_id,
+        entry.detail
+    );
+}
+
+/// Log an action and store it in the audit log.
+pub async fn record(
+    storage: Data<dyn Storage>,
+    actor_id: u32,
+    action: &str,
+    user_id: u32,
+    detail: &str,
+) -> Result<AuditEntry, HttpResponse> {
+    let mut entry = entry(actor_id, action, user_id, detail);
+    log(&entry);
+    match storage.insert_audit(&entry).await {
+        Ok(id) => entry.id = id,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+    Ok(entry)
+}
+
+pub async fn get_entries(
+    storage: Data<dyn Storage>,
+    limit: u32,
+) -> Result<Vec<AuditEntry>, HttpResponse> {
+    match storage.list_audit(limit).await {
+        Ok(entries) => Ok(entries),
+        Err(_) => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
@@ -0,0 +1,296 @@
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Extensions, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::HeaderMap;
+use actix_web::web::Data;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use crate::error_log;
+use crate::storage::Storage;
+use crate::users::{Role, SerdeUser};
+
+pub mod policy;
+pub mod rate_limit;
+
+use self::policy::Access;
+
+/// Passwords shorter than this are refused.
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// A login, as stored. The token is only known to the client.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub token_hash: String,
+    pub user_id: u32,
+    pub created_time: String,
+    pub expires_time: String,
+}
+
+/// API keys start with this, so that they are told apart from session tokens.
+pub const KEY_PREFIX: &str = "mroj_";
+
+/// What an API key may be used for, on top of what the role of its user allows.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// `POST /jobs`.
+    Submit,
+    /// `GET /jobs` and `GET /jobs/{id}`.
+    ReadJobs,
+    /// Everything else that changes data.
+    Admin,
+}
+
+/// A key for scripts, as stored. The key itself is only shown when it is created.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ApiKey {
+    pub id: u32,
+    pub user_id: u32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_time: String,
+    /// Never expires if `None`.
+    pub expires_time: Option<String>,
+    /// To the minute.
+    pub last_used_time: Option<String>,
+    #[serde(skip)]
+    pub key_hash: String,
+}
+
+/// The user a request was made by.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user: SerdeUser,
+    /// The scopes of the API key the request was made with, `None` for a login session,
+    /// which may do everything the role allows.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Caller {
+    pub fn is_admin(&self) -> bool {
+        self.user.role == Role::Admin
+    }
+}
+
+/// What the middleware found in the `Authorization` header.
+#[derive(Debug, Clone)]
+enum Resolved {
+    Caller(Caller),
+    /// A token was given, but it is unknown or expired.
+    Invalid,
+}
+
+/// Salted argon2id hash of a password, in PHC string format. Slow on purpose,
+/// call it from a blocking thread.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Slow on purpose like `hash_password`.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A new random bearer token, 32 bytes in hex.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A new random API key.
+pub fn new_api_key() -> String {
+    format!("{}{}", KEY_PREFIX, new_token())
+}
+
+/// What is stored of a token. Tokens are random, so a fast hash is enough.
+pub fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The token of `Authorization: Bearer <token>`.
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    token_from(req.headers())
+}
+
+fn token_from(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("Authorization")?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+/// The caller of a request, or `UNAUTHORIZED` if there is no valid token. The response is boxed
+/// to keep the `Result` small.
+pub fn caller(req: &HttpRequest) -> Result<Caller, Box<HttpResponse>> {
+    resolved(&req.extensions())
+}
+
+fn resolved(extensions: &Extensions) -> Result<Caller, Box<HttpResponse>> {
+    let message = match extensions.get::<Resolved>() {
+        Some(Resolved::Caller(caller)) => return Ok(caller.clone()),
+        Some(Resolved::Invalid) => "The token is invalid or has expired.",
+        None => "Login required.",
+    };
+    Err(Box::new(error_log::UNAUTHORIZED::webmsg(message)))
+}
+
+async fn resolve(req: &ServiceRequest) {
+    let token = match token_from(req.headers()) {
+        Some(token) => token,
+        None => return,
+    };
+    let storage = match req.app_data::<Data<dyn Storage>>() {
+        Some(storage) => storage.clone(),
+        None => return,
+    };
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let resolved = if token.starts_with(KEY_PREFIX) {
+        resolve_key(storage.as_ref(), &token, &now).await
+    } else {
+        match storage.get_session_user(&token_hash(&token), &now).await {
+            Ok(Some(user)) => Resolved::Caller(Caller { user, scopes: None }),
+            Ok(None) => Resolved::Invalid,
+            Err(e) => {
+                log::error!("Cannot look up a session: {}", e);
+                Resolved::Invalid
+            }
+        }
+    };
+    req.extensions_mut().insert(resolved);
+}
+
+async fn resolve_key(storage: &dyn Storage, token: &str, now: &str) -> Resolved {
+    match storage.get_api_key_user(&token_hash(token), now).await {
+        Ok(Some((key, user))) => {
+            // Written at most once a minute, not on every request.
+            let minute = &now[..16];
+            if key.last_used_time.as_deref().map(|time| &time[..16]) != Some(minute) {
+                if let Err(e) = storage.touch_api_key(key.id, now).await {
+                    log::warn!("Cannot record the use of API key {}: {}", key.id, e);
+                }
+            }
+            Resolved::Caller(Caller {
+                user,
+                scopes: Some(key.scopes),
+            })
+        }
+        Ok(None) => Resolved::Invalid,
+        Err(e) => {
+            log::error!("Cannot look up an API key: {}", e);
+            Resolved::Invalid
+        }
+    }
+}
+
+/// Refuse callers that `policy` does not allow on the endpoint. Unknown routes pass, to get a 404.
+fn authorize(req: &ServiceRequest) -> Result<(), Box<HttpResponse>> {
+    let pattern = match req.match_pattern() {
+        Some(pattern) => pattern,
+        None => return Ok(()),
+    };
+    let caller = match policy::access(req.method(), &pattern) {
+        Access::Public => return Ok(()),
+        Access::Login => resolved(&req.extensions())?,
+        Access::Roles(roles) => {
+            let caller = resolved(&req.extensions())?;
+            if !roles.contains(&caller.user.role) {
+                return Err(Box::new(error_log::FORBIDDEN::webmsg(&format!(
+                    "The {} role cannot do this.",
+                    caller.user.role.as_str()
+                ))));
+            }
+            caller
+        }
+    };
+    if let (Some(scopes), Some(scope)) = (&caller.scopes, policy::scope(req.method(), &pattern)) {
+        if !scopes.contains(&scope) && !scopes.contains(&Scope::Admin) {
+            return Err(Box::new(error_log::FORBIDDEN::webmsg(&format!(
+                "The API key does not have the {} scope.",
+                serde_json::to_string(&scope).unwrap().trim_matches('"')
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the session or API key of every request with a bearer token, for `caller`, and refuses
+/// the request if the endpoint is not open to the caller's role or they are over the rate limit.
+pub struct Authenticate;
+
+impl<S, B> Transform<S, ServiceRequest> for Authenticate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticateMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthenticateMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            resolve(&req).await;
+            if let Err(e) = rate_limit::check_request(&req, false).await {
+                return Ok(req.into_response(e).map_into_right_body());
+            }
+            if let Err(e) = authorize(&req) {
+                return Ok(req.into_response(*e).map_into_right_body());
+            }
+            if let Err(e) = rate_limit::check_request(&req, true).await {
+                return Ok(req.into_response(e).map_into_right_body());
+            }
+            service.call(req).await.map(|res| res.map_into_left_body())
+        })
+    }
+}
@@ -0,0 +1,100 @@
+use actix_web::http::Method;
+
+use super::Scope;
+use crate::users::Role;
+
+/// Who may call an endpoint. Handlers may narrow it further, e.g. to the owner of a problem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Public,
+    /// Any logged in user.
+    Login,
+    Roles(&'static [Role]),
+}
+
+const ADMIN: Access = Access::Roles(&[Role::Admin]);
+const SETTERS: Access = Access::Roles(&[Role::Admin, Role::Setter]);
+const SUBMITTERS: Access = Access::Roles(&[Role::Admin, Role::Setter, Role::Contestant]);
+
+/// Endpoints by method and route pattern. Everything not listed needs a login.
+const RULES: &[(Method, &str, Access)] = &[
+    (Method::POST, "/login", Access::Public),
+    // Problems of contests that have not started stay hidden, see `problems::hidden_problems`.
+    (Method::GET, "/problems", Access::Public),
+    (Method::GET, "/problems/{problemid}", Access::Public),
+    (
+        Method::GET,
+        "/problems/{problemid}/statement",
+        Access::Public,
+    ),
+    (
+        Method::GET,
+        "/problems/{problemid}/images/{name}",
+        Access::Public,
+    ),
+    (Method::GET, "/contests", Access::Public),
+    (Method::GET, "/contests/{contestid}", Access::Public),
+    (
+        Method::GET,
+        "/contests/{contestid}/ranklist",
+        Access::Public,
+    ),
+    (Method::POST, "/internal/exit", ADMIN),
+    (Method::POST, "/internal/reload", ADMIN),
+    (Method::GET, "/internal/disk", ADMIN),
+    (Method::GET, "/internal/audit", ADMIN),
+    (Method::POST, "/jobs", SUBMITTERS),
+    (Method::PUT, "/jobs/{jobid}", ADMIN),
+    (Method::POST, "/users/import", ADMIN),
+    (Method::POST, "/users/{userid}/deactivate", ADMIN),
+    (Method::POST, "/users/{userid}/activate", ADMIN),
+    (Method::POST, "/users/{userid}/anonymize", ADMIN),
+    (Method::POST, "/teams", ADMIN),
+    (Method::POST, "/groups", ADMIN),
+    (Method::POST, "/groups/{groupid}/members", ADMIN),
+    (Method::DELETE, "/groups/{groupid}/members/{userid}", ADMIN),
+    (Method::POST, "/contests", ADMIN),
+    (
+        Method::POST,
+        "/contests/{contestid}/registration",
+        SUBMITTERS,
+    ),
+    (
+        Method::DELETE,
+        "/contests/{contestid}/registration",
+        SUBMITTERS,
+    ),
+    (Method::GET, "/contests/{contestid}/registrations", ADMIN),
+    (
+        Method::POST,
+        "/contests/{contestid}/registrations/{userid}",
+        ADMIN,
+    ),
+    (Method::POST, "/problems", SETTERS),
+    (Method::PUT, "/problems/{problemid}", SETTERS),
+    (Method::DELETE, "/problems/{problemid}", SETTERS),
+    (Method::POST, "/problems/{problemid}/data", SETTERS),
+    (Method::POST, "/problems/{problemid}/images/{name}", SETTERS),
+    (
+        Method::DELETE,
+        "/problems/{problemid}/images/{name}",
+        SETTERS,
+    ),
+];
+
+pub fn access(method: &Method, pattern: &str) -> Access {
+    RULES
+        .iter()
+        .find(|(m, p, _)| m == method && *p == pattern)
+        .map_or(Access::Login, |(_, _, access)| *access)
+}
+
+/// The scope an API key needs for an endpoint. Other reads are open to every key.
+pub fn scope(method: &Method, pattern: &str) -> Option<Scope> {
+    match (method, pattern) {
+        (&Method::POST, "/jobs") => Some(Scope::Submit),
+        (&Method::GET, "/jobs") | (&Method::GET, "/jobs/{jobid}") => Some(Scope::ReadJobs),
+        (&Method::GET, _) => None,
+        _ => Some(Scope::Admin),
+    }
+}
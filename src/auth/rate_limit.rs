@@ -0,0 +1,133 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+use actix_web::http::Method;
+use actix_web::web::Data;
+use actix_web::{HttpMessage, HttpResponse};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, RateLimit};
+use crate::error_log;
+
+/// Endpoints that cost a judging run, by method and route pattern.
+const LIMITED: &[(Method, &str)] = &[(Method::POST, "/jobs")];
+
+/// When there are this many buckets, the full ones are dropped, then the least recently used
+/// ones until a quarter of the room is free again.
+pub const MAX_BUCKETS: usize = 4096;
+
+pub fn limited(method: &Method, pattern: &str) -> bool {
+    LIMITED.iter().any(|(m, p)| m == method && *p == pattern)
+}
+
+/// Who a bucket belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Client {
+    User(u32),
+    /// Requests without a valid login.
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant, per_second: f64, burst: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * per_second).min(burst)
+    }
+}
+
+/// Token buckets on the endpoints that start judging, so that one caller cannot fill the
+/// queue for everyone else. One bucket for each client, shared by the workers.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Client, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Take a token from the bucket of a client, or return how long it has to wait for one.
+    pub fn check(&self, client: Client, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        if limit.burst == 0 || limit.per_minute == 0 {
+            return Ok(());
+        }
+        let burst = limit.burst as f64;
+        let per_second = limit.per_minute as f64 / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| bucket.tokens_at(now, per_second, burst) < burst);
+            let keep = MAX_BUCKETS - MAX_BUCKETS / 4;
+            if buckets.len() > keep {
+                let mut updated: Vec<(Instant, Client)> = buckets
+                    .iter()
+                    .map(|(client, bucket)| (bucket.updated, *client))
+                    .collect();
+                updated.sort_unstable_by_key(|(updated, _)| *updated);
+                for (_, client) in &updated[..buckets.len() - keep] {
+                    buckets.remove(client);
+                }
+            }
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.tokens_at(now, per_second, burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// Refuse a request to a limited endpoint with `RATE_LIMIT` and a `Retry-After` header when
+/// its client is out of tokens. Requests without a valid login are counted by IP address
+/// before `authorize`, so that anonymous floods still cost a token. Logged in callers are
+/// counted once `authorize` lets them through, so that requests refused for their role or
+/// API key scope cost nothing.
+pub(super) async fn check_request(
+    req: &ServiceRequest,
+    authorized: bool,
+) -> Result<(), HttpResponse> {
+    match req.match_pattern() {
+        Some(pattern) if limited(req.method(), &pattern) => {}
+        _ => return Ok(()),
+    }
+    let (limiter, config) = match (
+        req.app_data::<Data<RateLimiter>>(),
+        req.app_data::<Data<Arc<tokio::sync::Mutex<Config>>>>(),
+    ) {
+        (Some(limiter), Some(config)) => (limiter.clone(), config.clone()),
+        _ => return Ok(()),
+    };
+    let client = match (super::resolved(&req.extensions()), authorized) {
+        (Ok(caller), _) if caller.is_admin() => return Ok(()),
+        (Ok(caller), true) => Client::User(caller.user.id),
+        (Err(_), false) => match req.peer_addr() {
+            Some(addr) => Client::Ip(addr.ip()),
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+    let limit = config.lock().await.rate_limit.clone();
+    let wait = match limiter.check(client, &limit, Instant::now()) {
+        Ok(()) => return Ok(()),
+        Err(wait) => wait,
+    };
+    let seconds = (wait.as_secs_f64().ceil() as u64).max(1);
+    let mut res = error_log::RATE_LIMIT::webmsg(&format!(
+        "Too many requests, retry in {} second(s).",
+        seconds
+    ));
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(seconds));
+    Err(res)
+}
@@ -1,21 +1,25 @@
 use actix_web::web::Data;
 use actix_web::{web, HttpResponse};
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, Result};
 use serde::{Deserialize, Serialize};
 // use web::{Json, Path};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::config::{self, Config, Ids};
+use crate::config;
 use crate::error_log;
-use crate::handler::jobs::{PostContest, RankFilter, ScoringRule, TieBreaker};
+use crate::groups;
+use crate::handler::jobs::{JobsFilter, PostContest, RankFilter, ScoringRule, TieBreaker};
 use crate::runner::{self, SerdeJob};
+use crate::storage::Storage;
+use crate::teams::{self, SerdeTeam};
 use crate::users::{self, SerdeUser};
+use registration::RegistrationMode;
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+pub mod registration;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct SerdeContest {
     pub id: u32,
     pub name: String,
@@ -24,108 +28,113 @@ pub struct SerdeContest {
     pub problem_ids: Vec<u32>,
     pub user_ids: Vec<u32>,
     pub submission_limit: u32,
+    /// Teams take part with one entry for all their members.
+    #[serde(default)]
+    pub team_ids: Vec<u32>,
+    /// Every member of these groups takes part, as the members are when they submit.
+    #[serde(default)]
+    pub group_ids: Vec<u32>,
+    #[serde(default)]
+    pub registration: RegistrationMode,
+    /// Users cannot register or leave after this time, the end of the contest if left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_deadline: Option<String>,
+    /// Hash of the password for `password` registration. Never shown or exported.
+    #[serde(skip)]
+    pub registration_password: Option<String>,
 }
 
+/// One row of a ranklist, for either a user or a team.
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SerdeRank {
-    pub user: SerdeUser,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<SerdeUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team: Option<SerdeTeam>,
     pub rank: u32,
     pub scores: Vec<f32>,
 }
 
+/// Who a ranklist row is for. Users come before teams when ordered by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Participant {
+    User(u32),
+    Team(u32),
+}
+
+impl Default for Participant {
+    fn default() -> Self {
+        Participant::User(0)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct TieBreakerStatus {
     pub submission_time: String,
     pub submission_count: u32,
-    pub user_id: u32,
+    pub participant: Participant,
     pub score: f32,
 }
 
-pub async fn job_exists(pool: Data<Mutex<Pool<SqliteConnectionManager>>>, contest_id: u32) -> bool {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt = data
-        .prepare(&format!(
-            "SELECT * FROM contests WHERE id = {};",
-            contest_id
-        ))
-        .expect("Database Error.");
-    match stmt.exists([]) {
-        Ok(true) => true,
-        _ => false,
-    }
-}
-
 pub async fn get_contest(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
     contest_id: u32,
 ) -> Result<SerdeContest, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM contests WHERE id = :id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
-        }
-    }
-    if !stmt
-        .exists(&[(":id", contest_id.to_string().as_str())])
-        .unwrap()
-    {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
+    match storage.get_contest(contest_id).await {
+        Ok(Some(contest)) => Ok(contest),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
             "Contest {} not found.",
             contest_id
-        )));
-    }
-    let iter = stmt.query_map(&[(":id", contest_id.to_string().as_str())], |row| {
-        Ok(SerdeContest {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            from: row.get(2)?,
-            to: row.get(3)?,
-            problem_ids: serde_json::from_str(&row.get::<_, String>(4)?).unwrap(),
-            user_ids: serde_json::from_str(&row.get::<_, String>(5)?).unwrap(),
-            submission_limit: row.get(6)?,
-        })
-    });
-    match iter {
-        Ok(mut ans) => Ok(ans.next().unwrap().expect("Unknown Error.")),
+        ))),
         _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
 }
 
-pub async fn contest_exists(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    contest_id: u32,
-) -> bool {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM contests WHERE id = :id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return true;
-        }
-    };
-    stmt.exists(&[(":id", contest_id.to_string().as_str())])
-        .unwrap()
+pub async fn contest_exists(storage: Data<dyn Storage>, contest_id: u32) -> bool {
+    match storage.get_contest(contest_id).await {
+        Ok(contest) => contest.is_some(),
+        _ => true,
+    }
 }
 
 pub async fn update_contest(
     body: web::Json<PostContest>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
 ) -> HttpResponse {
     println!("Contests: Updating contest...");
-    let mut contest: SerdeContest;
-    match get_contest(pool.clone(), body.id.unwrap()).await {
-        Ok(ans) => {
-            contest = ans;
-        }
+    let contest = match get_contest(storage.clone(), body.id.unwrap()).await {
+        Ok(ans) => ans,
         Err(e) => {
             return e;
         }
     };
-    let data = pool.lock().await.get().unwrap();
-    if let Err(e) = data.execute("UPDATE contests SET (name, from_time, to_time, problem_ids, user_ids, submission_limit) = (?1, ?2, ?3, ?4, ?5, ?6) WHERE id = ?7;", 
-                            params![body.name, body.from, body.to, format!("{:?}", body.problem_ids).to_string(), format!("{:?}", body.user_ids).to_string(), body.submission_limit as i32, body.id.unwrap() as i32]) {
+    let body = body.into_inner();
+    // The password is kept unless a new one is given.
+    let registration_password = match body.registration_password {
+        Some(password) => match registration::hash_password(password).await {
+            Ok(hash) => Some(hash),
+            Err(e) => return e,
+        },
+        None => contest.registration_password.clone(),
+    };
+    if body.registration == RegistrationMode::Password && registration_password.is_none() {
+        return error_log::INVALID_ARGUMENT::webmsg("Password registration needs a password.");
+    }
+    let updated = SerdeContest {
+        id: body.id.unwrap(),
+        name: body.name,
+        from: body.from,
+        to: body.to,
+        problem_ids: body.problem_ids,
+        user_ids: body.user_ids,
+        submission_limit: body.submission_limit,
+        team_ids: body.team_ids,
+        group_ids: body.group_ids,
+        registration: body.registration,
+        registration_deadline: body.registration_deadline,
+        registration_password,
+    };
+    if storage.update_contest(&updated).await.is_err() {
         return error_log::EXTERNAL::webmsg("Database Error.");
     }
     HttpResponse::Ok().body(serde_json::to_string_pretty(&contest).unwrap())
@@ -133,61 +142,51 @@ pub async fn update_contest(
 
 pub async fn create_contest(
     body: PostContest,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    storage: Data<dyn Storage>,
 ) -> Result<SerdeContest, HttpResponse> {
     println!("Contests: Creating Contest...");
-
-    let contest_id: u32 = ids.lock().await.contestsid;
-    ids.lock().await.contestsid += 1;
-    println!("contest ID: {}", contest_id);
-
-    let data = pool.lock().await.get().unwrap();
-    if let Err(e) = data.execute("INSERT INTO contests (id, name, from_time, to_time, problem_ids, user_ids, submission_limit) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);", 
-                            params![contest_id as i32, body.name, body.from, body.to, format!("{:?}", body.problem_ids).to_string(), format!("{:?}", body.user_ids).to_string(), body.submission_limit as i32]) {
-        return Err( error_log::EXTERNAL::webmsg(&format!("Database Error: {}", e)));
+    let registration_password = match body.registration_password {
+        Some(password) => Some(registration::hash_password(password).await?),
+        None => None,
+    };
+    if body.registration == RegistrationMode::Password && registration_password.is_none() {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(
+            "Password registration needs a password.",
+        ));
     }
 
-    Ok(SerdeContest {
-        id: contest_id,
+    let mut contest = SerdeContest {
+        id: 0,
         name: body.name,
         from: body.from,
         to: body.to,
         problem_ids: body.problem_ids,
         user_ids: body.user_ids,
         submission_limit: body.submission_limit,
-    })
-}
-
-pub async fn get_contests(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-) -> Result<Vec<SerdeContest>, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM contests ORDER BY id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+        team_ids: body.team_ids,
+        group_ids: body.group_ids,
+        registration: body.registration,
+        registration_deadline: body.registration_deadline,
+        registration_password,
+    };
+    match storage.insert_contest(&contest).await {
+        Ok(contest_id) => contest.id = contest_id,
+        Err(e) => {
+            return Err(error_log::EXTERNAL::webmsg(&format!(
+                "Database Error: {}",
+                e
+            )))
         }
     }
-    let iter = stmt
-        .query_map([], |row| {
-            Ok(SerdeContest {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                from: row.get(2)?,
-                to: row.get(3)?,
-                problem_ids: serde_json::from_str(&row.get::<_, String>(4)?).unwrap(),
-                user_ids: serde_json::from_str(&row.get::<_, String>(5)?).unwrap(),
-                submission_limit: row.get(6)?,
-            })
-        })
-        .expect("Unknown Error.");
-    let mut contests: Vec<SerdeContest> = vec![];
-    for user in iter {
-        contests.push(user.unwrap());
+    println!("contest ID: {}", contest.id);
+    Ok(contest)
+}
+
+pub async fn get_contests(storage: Data<dyn Storage>) -> Result<Vec<SerdeContest>, HttpResponse> {
+    match storage.list_contests().await {
+        Ok(contests) => Ok(contests),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
-    Ok(contests)
 }
 
 pub fn eq(s1: &TieBreakerStatus, s2: &TieBreakerStatus, filter: &RankFilter) -> bool {
@@ -197,23 +196,22 @@ pub fn eq(s1: &TieBreakerStatus, s2: &TieBreakerStatus, filter: &RankFilter) ->
     match filter.tie_breaker {
         TieBreaker::submission_time => s1.submission_time.eq(&s2.submission_time),
         TieBreaker::submission_count => s1.submission_count.eq(&s2.submission_count),
-        TieBreaker::user_id => s1.user_id.eq(&s2.user_id),
+        TieBreaker::user_id => s1.participant.eq(&s2.participant),
         _ => true,
     }
 }
 
 pub async fn get_ranklist(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    config: Data<Config>,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
     mut filter: RankFilter,
     contest_id: u32,
-    ids: Data<Arc<Mutex<Ids>>>,
 ) -> Result<Vec<SerdeRank>, HttpResponse> {
     println!("Getting ranklist...");
     let mut ans: Vec<SerdeJob> = vec![];
     let mut contest: SerdeContest = Default::default();
     if contest_id != 0 {
-        if let Ok(con) = get_contest(pool.clone(), contest_id).await {
+        if let Ok(con) = get_contest(storage.clone(), contest_id).await {
             contest = con;
         } else {
             return Err(error_log::NOT_FOUND::webmsg(&format!(
@@ -222,34 +220,49 @@ pub async fn get_ranklist(
             )));
         }
     } else {
-        for prob in &config.problems {
-            contest.problem_ids.push(prob.id);
-        }
+        contest.problem_ids = prob_map.lock().await.keys().copied().collect();
+        contest.problem_ids.sort();
     }
 
     let zero_time = "0001-01-01T00:00:01.000Z".to_string();
     let inf_time = "9999-12-31T23:59:59.000Z".to_string();
-    let mut user_score: HashMap<u32, Vec<f32>> = HashMap::new();
+    let mut user_score: HashMap<Participant, Vec<f32>> = HashMap::new();
     let mut prob_id_map: HashMap<u32, u32> = HashMap::new();
-    let mut user_id_map: HashMap<u32, usize> = HashMap::new();
+    let mut user_id_map: HashMap<Participant, usize> = HashMap::new();
     let prob_tot = contest.problem_ids.len();
 
     let mut tbstatus: Vec<TieBreakerStatus> = vec![];
     for (index, prob_id) in contest.problem_ids.iter().enumerate() {
         prob_id_map.insert(*prob_id, index as u32);
     }
-    let tot = ids.lock().await.jobsid as i32;
-    let user_tot = ids.lock().await.usersid;
-    for id in 0..tot {
-        let job;
-        match runner::get_a_job(pool.clone(), id as u32).await {
-            Ok(ans) => {
-                job = ans;
-            }
-            Err(e) => {
-                return Err(e);
+    // Members of registered groups take part alone unless they are in a registered team.
+    let mut team_members: HashSet<u32> = HashSet::new();
+    for team_id in &contest.team_ids {
+        team_members.extend(teams::get_team(storage.clone(), *team_id).await?.member_ids);
+    }
+    let mut group_users: Vec<u32> = vec![];
+    for group_id in &contest.group_ids {
+        let group = groups::get_group(storage.clone(), *group_id).await?;
+        for user_id in group.member_ids {
+            if !team_members.contains(&user_id) && !contest.user_ids.contains(&user_id) {
+                group_users.push(user_id);
             }
         }
+    }
+    let jobs_filter = JobsFilter {
+        contest_id: if contest_id != 0 {
+            Some(contest_id)
+        } else {
+            None
+        },
+        state: Some("Finished".to_string()),
+        ..JobsFilter::default()
+    };
+    let jobs = match runner::get_jobs(storage.clone(), jobs_filter).await {
+        Ok((jobs, _)) => jobs,
+        Err(e) => return Err(e),
+    };
+    for job in jobs {
         let prob_id = job.submission.problem_id;
         let user_id = job.submission.user_id;
         println!("prob{} user{}", prob_id, user_id);
@@ -257,9 +270,17 @@ pub async fn get_ranklist(
         if !contest.problem_ids.contains(&prob_id) {
             continue;
         }
-        if contest_id != 0 && !contest.user_ids.contains(&user_id) {
-            continue;
-        }
+        // Jobs of team members count for the team they were submitted for.
+        let participant = match job.submission.team_id {
+            Some(team_id) if contest.team_ids.contains(&team_id) => Participant::Team(team_id),
+            _ if contest_id == 0
+                || contest.user_ids.contains(&user_id)
+                || group_users.contains(&user_id) =>
+            {
+                Participant::User(user_id)
+            }
+            _ => continue,
+        };
         if contest_id != 0 && contest.id != job.submission.contest_id {
             continue;
         }
@@ -268,28 +289,28 @@ pub async fn get_ranklist(
         }
         println!("cur: {} , get {:?}", contest_id, job);
         let prob_index = *prob_id_map.get(&prob_id).unwrap();
-        if !user_score.contains_key(&user_id) {
-            user_score.insert(user_id, vec![0.0; prob_tot]);
+        if !user_score.contains_key(&participant) {
+            user_score.insert(participant, vec![0.0; prob_tot]);
             tbstatus.push(TieBreakerStatus {
                 submission_time: zero_time.clone(),
                 submission_count: 0,
-                user_id: user_id,
+                participant,
                 score: 0.0,
             });
-            user_id_map.insert(user_id, tbstatus.len() - 1);
+            user_id_map.insert(participant, tbstatus.len() - 1);
         }
-        let mut status = &mut tbstatus[*user_id_map.get(&user_id).unwrap()];
+        let mut status = &mut tbstatus[*user_id_map.get(&participant).unwrap()];
         status.submission_count += 1;
         match filter.scoring_rule {
             ScoringRule::highest => {
-                let score = user_score.get(&user_id).unwrap()[prob_index as usize];
+                let score = user_score.get(&participant).unwrap()[prob_index as usize];
                 if job.score > score {
-                    user_score.get_mut(&user_id).unwrap()[prob_index as usize] = job.score;
+                    user_score.get_mut(&participant).unwrap()[prob_index as usize] = job.score;
                     status.submission_time = String::from(&job.created_time);
                 }
             }
             ScoringRule::latest => {
-                user_score.get_mut(&user_id).unwrap()[prob_index as usize] = job.score;
+                user_score.get_mut(&participant).unwrap()[prob_index as usize] = job.score;
                 status.submission_time = String::from(&job.created_time);
             }
         };
@@ -300,37 +321,63 @@ pub async fn get_ranklist(
         if status.submission_time.eq(&zero_time) {
             status.submission_time = String::from(&inf_time);
         }
-        for score in &user_score[&status.user_id] {
+        for score in &user_score[&status.participant] {
             status.score += score;
         }
         println!("{:?}", status);
     }
-    if contest_id != 0 {
-        for user_id in contest.user_ids {
-            if !user_score.contains_key(&user_id) {
-                user_score.insert(user_id, vec![0.0; prob_tot]);
-                tbstatus.push(TieBreakerStatus {
-                    submission_time: inf_time.clone(),
-                    submission_count: 0,
-                    user_id: user_id,
-                    score: 0.0,
-                });
-            }
-        }
+    let all_users = users::get_users(storage.clone()).await?;
+    let participants: Vec<Participant> = if contest_id != 0 {
+        let users = contest.user_ids.iter().map(|id| Participant::User(*id));
+        let group_users = group_users.iter().map(|id| Participant::User(*id));
+        let teams = contest.team_ids.iter().map(|id| Participant::Team(*id));
+        users.chain(group_users).chain(teams).collect()
     } else {
-        for user_id in 0..user_tot {
-            if !user_score.contains_key(&user_id) {
-                user_score.insert(user_id, vec![0.0; prob_tot]);
-                tbstatus.push(TieBreakerStatus {
-                    submission_time: inf_time.clone(),
-                    submission_count: 0,
-                    user_id: user_id,
-                    score: 0.0,
-                });
+        all_users
+            .iter()
+            .map(|user| Participant::User(user.id))
+            .collect()
+    };
+    for participant in participants {
+        if let Entry::Vacant(entry) = user_score.entry(participant) {
+            entry.insert(vec![0.0; prob_tot]);
+            tbstatus.push(TieBreakerStatus {
+                submission_time: inf_time.clone(),
+                submission_count: 0,
+                participant,
+                score: 0.0,
+            });
+        }
+    }
+    // Deactivated users are left out, their jobs still count for their teams.
+    let deactivated: HashSet<u32> = all_users
+        .iter()
+        .filter(|user| user.deactivated_time.is_some())
+        .map(|user| user.id)
+        .collect();
+    tbstatus.retain(|status| match status.participant {
+        Participant::User(user_id) => !deactivated.contains(&user_id),
+        Participant::Team(_) => true,
+    });
+    if let Some(group_id) = filter.group_id {
+        let members: HashSet<u32> = groups::get_group(storage.clone(), group_id)
+            .await?
+            .member_ids
+            .into_iter()
+            .collect();
+        let mut teams_in_group: HashSet<u32> = HashSet::new();
+        for team_id in &contest.team_ids {
+            let team = teams::get_team(storage.clone(), *team_id).await?;
+            if team.member_ids.iter().any(|id| members.contains(id)) {
+                teams_in_group.insert(team.id);
             }
         }
+        tbstatus.retain(|status| match status.participant {
+            Participant::User(user_id) => members.contains(&user_id),
+            Participant::Team(team_id) => teams_in_group.contains(&team_id),
+        });
     }
-    tbstatus.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+    tbstatus.sort_by_key(|status| status.participant);
     match filter.tie_breaker {
         TieBreaker::submission_time => {
             tbstatus.sort_by(|a, b| a.submission_time.cmp(&b.submission_time));
@@ -349,24 +396,24 @@ pub async fn get_ranklist(
     let mut rank: u32 = 0;
     let mut las: &TieBreakerStatus = &Default::default();
     for (index, status) in tbstatus.iter().enumerate() {
-        let user;
-        match users::get_user(pool.clone(), status.user_id).await {
-            Ok(ans) => {
-                user = ans;
+        let (user, team) = match status.participant {
+            Participant::User(user_id) => {
+                (Some(users::get_user(storage.clone(), user_id).await?), None)
             }
-            Err(e) => {
-                return Err(e);
+            Participant::Team(team_id) => {
+                (None, Some(teams::get_team(storage.clone(), team_id).await?))
             }
-        }
-        if index == 0 || (index != 0 && !eq(&status, las, &filter)) {
+        };
+        if index == 0 || !eq(&status, las, &filter) {
             rank = (index as u32) + 1;
         }
         let mut cur = SerdeRank {
-            user: user,
+            user,
+            team,
             rank: rank,
             scores: vec![],
         };
-        for score in user_score.get(&status.user_id).unwrap() {
+        for score in user_score.get(&status.participant).unwrap() {
             cur.scores.push(*score);
         }
         las = &status;
@@ -0,0 +1,305 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{get_contest, SerdeContest};
+use crate::auth;
+use crate::error_log;
+use crate::groups;
+use crate::handler::jobs::JobsFilter;
+use crate::storage::Storage;
+use crate::teams;
+
+/// How users may register for a contest. Admins can always register anyone.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    /// Only admins register users.
+    #[default]
+    Closed,
+    /// Anyone can register.
+    Open,
+    /// Anyone with the contest password can register.
+    Password,
+    /// Registrations wait for an admin to approve them.
+    Approval,
+}
+
+impl RegistrationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistrationMode::Closed => "closed",
+            RegistrationMode::Open => "open",
+            RegistrationMode::Password => "password",
+            RegistrationMode::Approval => "approval",
+        }
+    }
+
+    /// Unknown names are read as `Closed`.
+    pub fn parse(name: &str) -> RegistrationMode {
+        match name {
+            "open" => RegistrationMode::Open,
+            "password" => RegistrationMode::Password,
+            "approval" => RegistrationMode::Approval,
+            _ => RegistrationMode::Closed,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationState {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl RegistrationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistrationState::Pending => "pending",
+            RegistrationState::Approved => "approved",
+            RegistrationState::Rejected => "rejected",
+        }
+    }
+
+    /// Unknown names are read as `Pending`, which grants nothing.
+    pub fn parse(name: &str) -> RegistrationState {
+        match name {
+            "approved" => RegistrationState::Approved,
+            "rejected" => RegistrationState::Rejected,
+            _ => RegistrationState::Pending,
+        }
+    }
+}
+
+/// A user signing up for a contest themselves. An approved registration puts the user in
+/// `user_ids` of the contest, which is what `post_job` checks.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Registration {
+    pub contest_id: u32,
+    pub user_id: u32,
+    pub state: RegistrationState,
+    pub created_time: String,
+    pub updated_time: String,
+}
+
+fn now() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Hash a contest password like a user password.
+pub async fn hash_password(password: String) -> Result<String, HttpResponse> {
+    if password.is_empty() {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(
+            "The registration password is empty.",
+        ));
+    }
+    match actix_web::web::block(move || auth::hash_password(&password)).await {
+        Ok(Ok(hash)) => Ok(hash),
+        _ => Err(error_log::INTERNAL::webmsg("Cannot hash the password.")),
+    }
+}
+
+/// Users can only register or leave themselves while the contest takes registrations,
+/// up to the deadline or the end of the contest.
+fn check_open(contest: &SerdeContest) -> Result<(), String> {
+    if contest.registration == RegistrationMode::Closed {
+        return Err(format!(
+            "Contest {} does not take registrations.",
+            contest.id
+        ));
+    }
+    let deadline = contest
+        .registration_deadline
+        .as_ref()
+        .unwrap_or(&contest.to);
+    if now() > *deadline {
+        return Err(format!(
+            "Registration for contest {} closed at {}.",
+            contest.id, deadline
+        ));
+    }
+    Ok(())
+}
+
+fn db_error<E>(_: E) -> HttpResponse {
+    error_log::EXTERNAL::webmsg("Database Error.")
+}
+
+/// Register a user for a contest. Approval contests keep the registration pending,
+/// the others take the user in right away.
+pub async fn register(
+    storage: Data<dyn Storage>,
+    contest_id: u32,
+    user_id: u32,
+    password: Option<String>,
+) -> Result<Registration, HttpResponse> {
+    let contest = get_contest(storage.clone(), contest_id).await?;
+    if let Err(e) = check_open(&contest) {
+        return Err(error_log::INVALID_STATE::webmsg(&e));
+    }
+    if contest.user_ids.contains(&user_id) {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} is already registered in contest {}.",
+            user_id, contest_id
+        )));
+    }
+    if let Some(team_id) = teams::contest_team(storage.clone(), &contest, user_id).await? {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} takes part in contest {} with team {}.",
+            user_id, contest_id, team_id
+        )));
+    }
+    if groups::in_contest_group(storage.clone(), &contest, user_id).await? {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} takes part in contest {} through a group.",
+            user_id, contest_id
+        )));
+    }
+    let existing = storage
+        .get_registration(contest_id, user_id)
+        .await
+        .map_err(db_error)?;
+    match existing.as_ref().map(|registration| registration.state) {
+        Some(RegistrationState::Pending) => {
+            return Err(error_log::INVALID_STATE::webmsg(&format!(
+                "The registration of user {} for contest {} is waiting for approval.",
+                user_id, contest_id
+            )))
+        }
+        Some(RegistrationState::Rejected) => {
+            return Err(error_log::FORBIDDEN::webmsg(&format!(
+                "The registration of user {} for contest {} was rejected.",
+                user_id, contest_id
+            )))
+        }
+        _ => {}
+    }
+    if contest.registration == RegistrationMode::Password {
+        let hash = contest.registration_password.unwrap_or_default();
+        let password = password.unwrap_or_default();
+        let valid = actix_web::web::block(move || auth::verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        if !valid {
+            return Err(error_log::FORBIDDEN::webmsg(&format!(
+                "Wrong password for contest {}.",
+                contest_id
+            )));
+        }
+    }
+
+    let state = match contest.registration {
+        RegistrationMode::Approval => RegistrationState::Pending,
+        _ => RegistrationState::Approved,
+    };
+    let registration = Registration {
+        contest_id,
+        user_id,
+        state,
+        created_time: now(),
+        updated_time: now(),
+    };
+    storage
+        .save_registration(&registration)
+        .await
+        .map_err(db_error)?;
+    if state == RegistrationState::Approved {
+        storage
+            .add_contest_user(contest_id, user_id)
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(registration)
+}
+
+/// Take a user out of a contest, or withdraw a pending registration. Users who have
+/// already submitted stay, so that their jobs keep counting.
+pub async fn leave(
+    storage: Data<dyn Storage>,
+    contest_id: u32,
+    user_id: u32,
+) -> Result<(), HttpResponse> {
+    let contest = get_contest(storage.clone(), contest_id).await?;
+    if let Err(e) = check_open(&contest) {
+        return Err(error_log::INVALID_STATE::webmsg(&e));
+    }
+    let filter = JobsFilter {
+        user_id: Some(user_id),
+        contest_id: Some(contest_id),
+        ..JobsFilter::default()
+    };
+    if storage.count_jobs(&filter).await.map_err(db_error)? > 0 {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} has submitted in contest {} and cannot leave it.",
+            user_id, contest_id
+        )));
+    }
+    let removed = storage
+        .remove_contest_user(contest_id, user_id)
+        .await
+        .map_err(db_error)?;
+    let deleted = storage
+        .delete_registration(contest_id, user_id)
+        .await
+        .map_err(db_error)?;
+    if !removed && !deleted {
+        return Err(error_log::NOT_FOUND::webmsg(&format!(
+            "User {} is not registered in contest {}.",
+            user_id, contest_id
+        )));
+    }
+    Ok(())
+}
+
+pub async fn list(
+    storage: Data<dyn Storage>,
+    contest_id: u32,
+    state: Option<RegistrationState>,
+) -> Result<Vec<Registration>, HttpResponse> {
+    get_contest(storage.clone(), contest_id).await?;
+    storage
+        .list_registrations(contest_id, state)
+        .await
+        .map_err(db_error)
+}
+
+/// Approve or reject a registration. Approving a rejected one or rejecting an approved
+/// one is allowed, and moves the user in or out of the contest.
+pub async fn decide(
+    storage: Data<dyn Storage>,
+    contest_id: u32,
+    user_id: u32,
+    state: RegistrationState,
+) -> Result<Registration, HttpResponse> {
+    if state == RegistrationState::Pending {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(
+            "A registration can only be approved or rejected.",
+        ));
+    }
+    get_contest(storage.clone(), contest_id).await?;
+    let mut registration = match storage.get_registration(contest_id, user_id).await {
+        Ok(Some(registration)) => registration,
+        Ok(None) => {
+            return Err(error_log::NOT_FOUND::webmsg(&format!(
+                "User {} has not registered for contest {}.",
+                user_id, contest_id
+            )))
+        }
+        Err(e) => return Err(db_error(e)),
+    };
+    registration.state = state;
+    registration.updated_time = now();
+    storage
+        .save_registration(&registration)
+        .await
+        .map_err(db_error)?;
+    let changed = match state {
+        RegistrationState::Approved => storage.add_contest_user(contest_id, user_id).await,
+        _ => storage.remove_contest_user(contest_id, user_id).await,
+    };
+    changed.map_err(db_error)?;
+    Ok(registration)
+}
@@ -139,3 +139,49 @@ impl INTERNAL {
         HttpResponse::InternalServerError().body(INTERNAL::msg(message))
     }
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct UNAUTHORIZED {
+    code: u32,
+    reason: &'static str,
+    message: String,
+}
+
+impl UNAUTHORIZED {
+    pub fn new(message: &str) -> UNAUTHORIZED {
+        UNAUTHORIZED {
+            code: 7,
+            reason: "ERR_UNAUTHORIZED",
+            message: message.to_string(),
+        }
+    }
+    pub fn msg(message: &str) -> String {
+        to_string_pretty(&UNAUTHORIZED::new(message)).unwrap()
+    }
+    pub fn webmsg(message: &str) -> HttpResponse {
+        HttpResponse::Unauthorized().body(UNAUTHORIZED::msg(message))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FORBIDDEN {
+    code: u32,
+    reason: &'static str,
+    message: String,
+}
+
+impl FORBIDDEN {
+    pub fn new(message: &str) -> FORBIDDEN {
+        FORBIDDEN {
+            code: 8,
+            reason: "ERR_FORBIDDEN",
+            message: message.to_string(),
+        }
+    }
+    pub fn msg(message: &str) -> String {
+        to_string_pretty(&FORBIDDEN::new(message)).unwrap()
+    }
+    pub fn webmsg(message: &str) -> HttpResponse {
+        HttpResponse::Forbidden().body(FORBIDDEN::msg(message))
+    }
+}
@@ -0,0 +1,150 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::contests::SerdeContest;
+use crate::error_log;
+use crate::handler::groups::PostGroup;
+use crate::storage::Storage;
+use crate::users;
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct SerdeGroup {
+    pub id: u32,
+    pub name: String,
+    pub member_ids: Vec<u32>,
+}
+
+pub async fn get_group(
+    storage: Data<dyn Storage>,
+    group_id: u32,
+) -> Result<SerdeGroup, HttpResponse> {
+    match storage.get_group(group_id).await {
+        Ok(Some(group)) => Ok(group),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Group {} not found.",
+            group_id
+        ))),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+pub async fn get_groups(storage: Data<dyn Storage>) -> Result<Vec<SerdeGroup>, HttpResponse> {
+    match storage.list_groups().await {
+        Ok(groups) => Ok(groups),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+async fn check_users(storage: Data<dyn Storage>, user_ids: &[u32]) -> Result<(), HttpResponse> {
+    for user_id in user_ids {
+        if !users::user_exists(storage.clone(), *user_id).await {
+            return Err(error_log::NOT_FOUND::webmsg(&format!(
+                "User {} not found.",
+                user_id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Create a group, or rename one if `id` is given. Members of an existing group are
+/// changed through `add_members` and `remove_member`.
+pub async fn save_group(
+    storage: Data<dyn Storage>,
+    body: PostGroup,
+) -> Result<SerdeGroup, HttpResponse> {
+    if body.name.trim().is_empty() {
+        return Err(error_log::INVALID_ARGUMENT::webmsg("Group name is empty."));
+    }
+    let same_name = match storage.get_group_by_name(&body.name).await {
+        Ok(group) => group,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    if same_name.is_some_and(|group| Some(group.id) != body.id) {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+            "Group name '{}' already exists.",
+            body.name
+        )));
+    }
+    match body.id {
+        Some(id) => {
+            get_group(storage.clone(), id).await?;
+            if storage.rename_group(id, &body.name).await.is_err() {
+                return Err(error_log::EXTERNAL::webmsg("Database Error."));
+            }
+            get_group(storage, id).await
+        }
+        None => {
+            let mut member_ids = body.member_ids;
+            member_ids.sort_unstable();
+            member_ids.dedup();
+            check_users(storage.clone(), &member_ids).await?;
+            let mut group = SerdeGroup {
+                id: 0,
+                name: body.name,
+                member_ids,
+            };
+            match storage.insert_group(&group).await {
+                Ok(id) => group.id = id,
+                Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+            }
+            Ok(group)
+        }
+    }
+}
+
+pub async fn add_members(
+    storage: Data<dyn Storage>,
+    group_id: u32,
+    user_ids: &[u32],
+) -> Result<SerdeGroup, HttpResponse> {
+    get_group(storage.clone(), group_id).await?;
+    check_users(storage.clone(), user_ids).await?;
+    if storage.add_group_members(group_id, user_ids).await.is_err() {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    get_group(storage, group_id).await
+}
+
+pub async fn remove_member(
+    storage: Data<dyn Storage>,
+    group_id: u32,
+    user_id: u32,
+) -> Result<SerdeGroup, HttpResponse> {
+    get_group(storage.clone(), group_id).await?;
+    match storage.remove_group_member(group_id, user_id).await {
+        Ok(true) => get_group(storage, group_id).await,
+        Ok(false) => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "User {} is not in group {}.",
+            user_id, group_id
+        ))),
+        Err(_) => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+pub async fn check_groups(
+    storage: Data<dyn Storage>,
+    group_ids: &[u32],
+) -> Result<(), HttpResponse> {
+    for group_id in group_ids {
+        get_group(storage.clone(), *group_id).await?;
+    }
+    Ok(())
+}
+
+/// Whether a user is in a group registered for the contest. Membership is looked up on
+/// every submission, so users added to a group later can take part right away.
+pub async fn in_contest_group(
+    storage: Data<dyn Storage>,
+    contest: &SerdeContest,
+    user_id: u32,
+) -> Result<bool, HttpResponse> {
+    if contest.group_ids.is_empty() {
+        return Ok(false);
+    }
+    match storage.list_user_groups(user_id).await {
+        Ok(group_ids) => Ok(group_ids.iter().any(|id| contest.group_ids.contains(id))),
+        Err(_) => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
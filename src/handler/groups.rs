@@ -0,0 +1,73 @@
+use actix_web::web::Data;
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::error_log;
+use crate::groups;
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PostGroup {
+    pub id: Option<u32>,
+    pub name: String,
+    /// Only used when the group is created.
+    #[serde(default)]
+    pub member_ids: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostMembers {
+    pub user_ids: Vec<u32>,
+}
+
+#[post("/groups")]
+pub async fn post_group(body: web::Json<PostGroup>, storage: Data<dyn Storage>) -> HttpResponse {
+    match groups::save_group(storage, body.into_inner()).await {
+        Ok(group) => HttpResponse::Ok().body(serde_json::to_string_pretty(&group).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[post("/groups/{groupid}/members")]
+pub async fn post_members(
+    path: web::Path<u32>,
+    body: web::Json<PostMembers>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    match groups::add_members(storage, path.into_inner(), &body.user_ids).await {
+        Ok(group) => HttpResponse::Ok().body(serde_json::to_string_pretty(&group).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[delete("/groups/{groupid}/members/{userid}")]
+pub async fn delete_member(
+    path: web::Path<(u32, u32)>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let (group_id, user_id) = path.into_inner();
+    match groups::remove_member(storage, group_id, user_id).await {
+        Ok(group) => HttpResponse::Ok().body(serde_json::to_string_pretty(&group).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[get("/groups/{groupid}")]
+pub async fn get_group_by_id(path: web::Path<String>, storage: Data<dyn Storage>) -> HttpResponse {
+    let group_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Group {} not found.", path)),
+    };
+    match groups::get_group(storage, group_id).await {
+        Ok(group) => HttpResponse::Ok().body(serde_json::to_string_pretty(&group).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[get("/groups")]
+pub async fn get_groups(storage: Data<dyn Storage>) -> HttpResponse {
+    match groups::get_groups(storage).await {
+        Ok(groups) => HttpResponse::Ok().body(serde_json::to_string_pretty(&groups).unwrap()),
+        Err(e) => e,
+    }
+}
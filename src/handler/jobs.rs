@@ -1,7 +1,5 @@
 use actix_web::web::Data;
 use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
 use serde::{Deserialize, Serialize};
 // use web::{Json, Path};
 use chrono::prelude::*;
@@ -9,25 +7,47 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::config::{self, Config, Ids};
+use crate::auth;
+use crate::config::{self, Config};
 use crate::contests;
+use crate::contests::registration::RegistrationMode;
 use crate::error_log;
+use crate::groups;
 use crate::runner;
-use crate::users;
+use crate::runner::retention;
+use crate::storage::Storage;
+use crate::teams;
+use crate::users::{self, import};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PostJob {
     pub source_code: String,
     pub language: String,
+    /// Ignored in requests, the job belongs to the caller.
+    #[serde(default)]
     pub user_id: u32,
     pub contest_id: u32,
     pub problem_id: u32,
+    /// Ignored in requests, set to the caller's team in the contest.
+    #[serde(default)]
+    pub team_id: Option<u32>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct PostUser {
     pub id: Option<u32>,
     pub name: String,
+    pub password: Option<String>,
+    /// Only admins may set roles.
+    pub role: Option<users::Role>,
+    /// An empty name removes it.
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -39,11 +59,21 @@ pub struct PostContest {
     pub problem_ids: Vec<u32>,
     pub user_ids: Vec<u32>,
     pub submission_limit: u32,
+    #[serde(default)]
+    pub team_ids: Vec<u32>,
+    #[serde(default)]
+    pub group_ids: Vec<u32>,
+    #[serde(default)]
+    pub registration: RegistrationMode,
+    pub registration_deadline: Option<String>,
+    /// Needed for `password` registration, kept when left out on an update.
+    pub registration_password: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct JobsFilter {
     pub user_id: Option<u32>,
+    pub team_id: Option<u32>,
     pub user_name: Option<String>,
     pub contest_id: Option<u32>,
     pub problem_id: Option<u32>,
@@ -52,6 +82,31 @@ pub struct JobsFilter {
     pub to: Option<String>,
     pub state: Option<String>,
     pub result: Option<String>,
+    /// At most this many jobs are returned, along with the cursor of the next page.
+    pub limit: Option<u32>,
+    /// The `next` value of the previous page.
+    pub cursor: Option<u32>,
+    pub order: Option<SortOrder>,
+    pub count_only: Option<bool>,
+}
+
+#[allow(warnings)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    #[default]
+    asc,
+    desc,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsPage {
+    pub jobs: Vec<runner::SerdeJob>,
+    pub next: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsCount {
+    pub count: u32,
 }
 
 #[allow(warnings)]
@@ -76,24 +131,34 @@ pub enum TieBreaker {
 pub struct RankFilter {
     pub scoring_rule: ScoringRule,
     pub tie_breaker: TieBreaker,
+    /// Only rank users in this group, and teams with a member in it.
+    pub group_id: Option<u32>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SerdeRankFilter {
     pub scoring_rule: Option<ScoringRule>,
     pub tie_breaker: Option<TieBreaker>,
+    pub group_id: Option<u32>,
 }
 
 #[post("/jobs")]
 pub async fn post_job(
-    body: web::Json<PostJob>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    config: Data<Config>,
-    prob_map: Data<HashMap<u32, config::Problem>>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    req: HttpRequest,
+    mut body: web::Json<PostJob>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
+    match auth::caller(&req) {
+        Ok(caller) => body.user_id = caller.user.id,
+        Err(e) => return *e,
+    }
+    body.team_id = None;
     // check request
     if !config
+        .lock()
+        .await
         .languages
         .iter()
         .map(|x| x.name.to_string())
@@ -102,32 +167,35 @@ pub async fn post_job(
     {
         return error_log::NOT_FOUND::webmsg(&format!("Language {} no found.", body.language));
     }
-    if !config
-        .problems
-        .iter()
-        .map(|x| x.id)
-        .collect::<Vec<u32>>()
-        .contains(&body.problem_id)
-    {
+    if !prob_map.lock().await.contains_key(&body.problem_id) {
         return error_log::NOT_FOUND::webmsg(&format!(
             "Problem with id({}) not found.",
             body.problem_id
         ));
     }
-    if let Err(_) = users::get_user(pool.clone(), body.user_id).await {
+    if let Err(_) = users::get_user(storage.clone(), body.user_id).await {
         return error_log::NOT_FOUND::webmsg(&format!("User with id({}) not found.", body.user_id));
     }
-    if body.contest_id != 0 && !contests::contest_exists(pool.clone(), body.contest_id).await {
+    if body.contest_id != 0 && !contests::contest_exists(storage.clone(), body.contest_id).await {
         return error_log::NOT_FOUND::webmsg(&format!(
             "Contest with id({}) not found.",
             body.user_id
         ));
     }
     if body.contest_id != 0 {
-        let contest = contests::get_contest(pool.clone(), body.contest_id)
+        let contest = contests::get_contest(storage.clone(), body.contest_id)
             .await
             .unwrap();
-        if !contest.user_ids.contains(&body.user_id) {
+        body.team_id = match teams::contest_team(storage.clone(), &contest, body.user_id).await {
+            Ok(team_id) => team_id,
+            Err(e) => return e,
+        };
+        let in_group = match groups::in_contest_group(storage.clone(), &contest, body.user_id).await
+        {
+            Ok(in_group) => in_group,
+            Err(e) => return e,
+        };
+        if body.team_id.is_none() && !contest.user_ids.contains(&body.user_id) && !in_group {
             return error_log::INVALID_ARGUMENT::webmsg(&format!(
                 "User {} is not registered in contest {}.",
                 body.user_id, body.contest_id
@@ -140,24 +208,21 @@ pub async fn post_job(
             ));
         }
         if contest.submission_limit != 0 {
-            let data = pool.lock().await.get().unwrap();
-            let mut stmt;
-            match data.prepare(&format!("SELECT COUNT(*) FROM submission WHERE user_id = {} AND problem_id = {} AND contest_id = {};", body.user_id, body.problem_id, body.contest_id)) {
-                Ok(s) => stmt = s,
-                _ => { return error_log::EXTERNAL::webmsg("Database Error."); }
-            };
-            let mut submission_count: u32 = 0;
-            match stmt.exists([]) {
-                Ok(true) => {
-                    submission_count = stmt
-                        .query([])
-                        .unwrap()
-                        .next()
-                        .unwrap()
-                        .unwrap()
-                        .get(0)
-                        .unwrap();
+            // Members of a team share its limit.
+            let count = match body.team_id {
+                Some(team_id) => {
+                    storage
+                        .count_team_submissions(team_id, body.problem_id, body.contest_id)
+                        .await
+                }
+                None => {
+                    storage
+                        .count_submissions(body.user_id, body.problem_id, body.contest_id)
+                        .await
                 }
+            };
+            let submission_count = match count {
+                Ok(count) => count,
                 _ => {
                     return error_log::EXTERNAL::webmsg("Database Error.");
                 }
@@ -175,15 +240,18 @@ pub async fn post_job(
         }
     }
 
-    runner::start(body, pool, config, prob_map, ids.clone())
+    runner::start(body, storage, config, prob_map)
         .await
         .unwrap()
 }
 
 #[get("/jobs/{jobid}")]
 pub async fn get_job_by_id(
+    req: HttpRequest,
     path: web::Path<String>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
     let mut job_id: u32 = 0;
     match path.parse::<u32>() {
@@ -192,15 +260,23 @@ pub async fn get_job_by_id(
             return error_log::NOT_FOUND::webmsg(&format!("Job {} not found.", path));
         }
     };
-    runner::get_job(pool, job_id).await
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    runner::get_job_with_samples(&caller, storage, config, prob_map, job_id).await
 }
 
 #[get("/jobs")]
 pub async fn get_jobs(
     req: HttpRequest,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
     let mut filter;
     let reqstr = str::replace(req.query_string(), "+", "🜔");
     println!("{:?}", reqstr);
@@ -226,8 +302,40 @@ pub async fn get_jobs(
         }
     }
 
-    match runner::get_jobs(pool, filter.into_inner(), ids).await {
-        Ok(jobs) => HttpResponse::Ok().body(serde_json::to_string_pretty(&jobs).unwrap()),
+    if filter.limit == Some(0) {
+        return error_log::INVALID_ARGUMENT::webmsg("Invalid argument limit.");
+    }
+    let filter = filter.into_inner();
+    if filter.count_only == Some(true) {
+        return match runner::count_jobs(storage, filter).await {
+            Ok(count) => {
+                HttpResponse::Ok().body(serde_json::to_string_pretty(&JobsCount { count }).unwrap())
+            }
+            Err(e) => e,
+        };
+    }
+    // Without paging arguments the response is the plain list it has always been.
+    let paged = filter.limit.is_some() || filter.cursor.is_some();
+    match runner::get_jobs(storage.clone(), filter).await {
+        Ok((mut jobs, next)) => {
+            let caller_teams = storage
+                .list_user_teams(caller.user.id)
+                .await
+                .unwrap_or_default();
+            let map = prob_map.lock().await;
+            for job in &mut jobs {
+                let prob = map.get(&job.submission.problem_id);
+                if !runner::can_see_details(&caller, &caller_teams, job, prob) {
+                    job.redact();
+                }
+            }
+            if paged {
+                HttpResponse::Ok()
+                    .body(serde_json::to_string_pretty(&JobsPage { jobs, next }).unwrap())
+            } else {
+                HttpResponse::Ok().body(serde_json::to_string_pretty(&jobs).unwrap())
+            }
+        }
         Err(e) => e,
     }
 }
@@ -235,10 +343,9 @@ pub async fn get_jobs(
 #[put("/jobs/{jobid}")]
 pub async fn rejudge_job_by_id(
     path: web::Path<String>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    ids: Data<Arc<Mutex<Ids>>>,
-    config: Data<Config>,
-    prob_map: Data<HashMap<u32, config::Problem>>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
     println!("Rejuding...");
     let mut job_id: u32 = 0;
@@ -248,81 +355,251 @@ pub async fn rejudge_job_by_id(
             return error_log::NOT_FOUND::webmsg(&format!("Job {} not found.", path));
         }
     };
-    if job_id >= ids.lock().await.jobsid {
-        return error_log::NOT_FOUND::webmsg(&format!("Job {} not found.", path));
-    }
-    match runner::reset_job(pool.clone(), job_id, prob_map.clone()).await {
+    // Rejudged with the problem and language as they are now.
+    let task = match runner::get_a_job(storage.clone(), job_id).await {
+        Ok(job) => match runner::Task::new(job.get_post(), &config, &prob_map).await {
+            Ok(task) => task,
+            Err(e) => return e,
+        },
+        Err(e) => return e,
+    };
+    match runner::reset_job(storage.clone(), job_id, prob_map.clone()).await {
         Err(e) => {
             return e;
         }
         _ => {}
     }
-    let res = runner::get_a_job(pool.clone(), job_id).await;
-    let ans;
-    let post;
-    match res {
-        Ok(job) => {
-            post = job.get_post();
-            ans = HttpResponse::Ok().body(serde_json::to_string_pretty(&job).unwrap());
-        }
-        Err(e) => {
-            return e;
-        }
-    }
-    let _ = tokio::spawn(async move {
-        runner::run(post, pool.clone(), config.clone(), prob_map.clone(), job_id).await;
-    }); //.await;
+    let ans = match runner::get_a_job(storage.clone(), job_id).await {
+        Ok(job) => HttpResponse::Ok().body(serde_json::to_string_pretty(&job).unwrap()),
+        Err(e) => return e,
+    };
+    drop(tokio::spawn(async move {
+        runner::run(task, storage.clone(), config.clone(), job_id).await;
+    }));
     ans
 }
 
 #[post("/users")]
 pub async fn post_user(
+    req: HttpRequest,
     body: web::Json<PostUser>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    storage: Data<dyn Storage>,
 ) -> HttpResponse {
+    if let Some(password) = &body.password {
+        if let Err(e) = users::check_password(password) {
+            return error_log::INVALID_ARGUMENT::webmsg(&e);
+        }
+    }
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if body.role.is_some() && !caller.is_admin() {
+        return error_log::FORBIDDEN::webmsg("Only admins can set roles.");
+    }
     if let Some(id) = body.id {
-        users::update_user(pool, id, &body.name).await
-    } else {
-        match users::create_user(pool, &body.name, ids.clone()).await {
+        // Users may change their own name and password, admins may change anyone.
+        if caller.user.id != id && !caller.is_admin() {
+            return error_log::FORBIDDEN::webmsg(&format!("Cannot change user {}.", id));
+        }
+        if caller.user.id == id && body.role.is_some_and(|role| role != users::Role::Admin) {
+            return error_log::INVALID_STATE::webmsg("Admins cannot give up their own role.");
+        }
+        let ans = users::update_user(storage.clone(), id, &body.name).await;
+        if !ans.status().is_success() {
+            return ans;
+        }
+        if let Some(password) = &body.password {
+            if let Err(e) = users::set_password(storage.clone(), id, password).await {
+                return e;
+            }
+        }
+        if let Some(role) = body.role {
+            if let Err(e) = users::set_role(storage.clone(), id, role).await {
+                return e;
+            }
+        }
+        if body.role.is_none() && body.display_name.is_none() {
+            return ans;
+        }
+        let updated = match &body.display_name {
+            Some(display_name) => users::set_display_name(storage, id, display_name).await,
+            None => users::get_user(storage, id).await,
+        };
+        match updated {
             Ok(user) => HttpResponse::Ok().body(serde_json::to_string_pretty(&user).unwrap()),
             Err(e) => e,
         }
+    } else {
+        if !caller.is_admin() {
+            return error_log::FORBIDDEN::webmsg("Only admins can create users.");
+        }
+        match users::create_user(storage.clone(), &body.name).await {
+            Ok(mut user) => {
+                if let Some(password) = &body.password {
+                    if let Err(e) = users::set_password(storage.clone(), user.id, password).await {
+                        return e;
+                    }
+                }
+                if let Some(role) = body.role {
+                    match users::set_role(storage.clone(), user.id, role).await {
+                        Ok(updated) => user = updated,
+                        Err(e) => return e,
+                    }
+                }
+                if let Some(display_name) = &body.display_name {
+                    match users::set_display_name(storage, user.id, display_name).await {
+                        Ok(updated) => user = updated,
+                        Err(e) => return e,
+                    }
+                }
+                HttpResponse::Ok().body(serde_json::to_string_pretty(&user).unwrap())
+            }
+            Err(e) => e,
+        }
+    }
+}
+
+/// Create users from CSV with the columns `name,display_name,group,password`. Nothing is
+/// created if a row has an error.
+#[post("/users/import")]
+pub async fn import_users(
+    query: web::Query<ImportQuery>,
+    body: String,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let rows = match import::parse(&body) {
+        Ok(rows) => rows,
+        Err(e) => return error_log::INVALID_ARGUMENT::webmsg(&e),
+    };
+    match import::import(&**storage, &rows, query.dry_run).await {
+        Ok(report) => {
+            let body = serde_json::to_string_pretty(&report).unwrap();
+            if report.errors == 0 {
+                HttpResponse::Ok().body(body)
+            } else {
+                HttpResponse::BadRequest().body(body)
+            }
+        }
+        Err(e) => error_log::EXTERNAL::webmsg(&e),
     }
 }
 
 #[get("/users")]
-pub async fn get_users(pool: Data<Mutex<Pool<SqliteConnectionManager>>>) -> HttpResponse {
-    match users::get_users(pool).await {
+pub async fn get_users(storage: Data<dyn Storage>) -> HttpResponse {
+    match users::get_users(storage).await {
         Ok(users) => HttpResponse::Ok().body(serde_json::to_string_pretty(&users).unwrap()),
         Err(e) => e,
     }
 }
 
+#[get("/users/{userid}")]
+pub async fn get_user_by_id(
+    path: web::Path<String>,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let user_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("User {} not found.", path)),
+    };
+    match users::get_profile(storage, prob_map, user_id).await {
+        Ok(profile) => HttpResponse::Ok().body(serde_json::to_string_pretty(&profile).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[post("/users/{userid}/deactivate")]
+pub async fn deactivate_user(
+    req: HttpRequest,
+    path: web::Path<u32>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    match users::deactivate(storage, caller.user.id, path.into_inner()).await {
+        Ok(user) => HttpResponse::Ok().body(serde_json::to_string_pretty(&user).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[post("/users/{userid}/activate")]
+pub async fn activate_user(
+    req: HttpRequest,
+    path: web::Path<u32>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    match users::activate(storage, caller.user.id, path.into_inner()).await {
+        Ok(user) => HttpResponse::Ok().body(serde_json::to_string_pretty(&user).unwrap()),
+        Err(e) => e,
+    }
+}
+
+/// Anonymize a user and remove what is left of their jobs in the runtime directory.
+#[post("/users/{userid}/anonymize")]
+pub async fn anonymize_user(
+    req: HttpRequest,
+    path: web::Path<u32>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let (user, job_ids) = match users::anonymize(storage, caller.user.id, path.into_inner()).await {
+        Ok(ans) => ans,
+        Err(e) => return e,
+    };
+    let runtime_dir = config.lock().await.runtime.runtime_dir.clone();
+    retention::remove_jobs(&runtime_dir, job_ids).await;
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&user).unwrap())
+}
+
 #[post("/contests")]
 pub async fn post_contest(
     body: web::Json<PostContest>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    ids: Data<Arc<Mutex<Ids>>>,
-    prob_map: Data<HashMap<u32, config::Problem>>,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
     for prob_id in &body.problem_ids {
-        if !prob_map.contains_key(&prob_id) {
+        if !prob_map.lock().await.contains_key(&prob_id) {
             // return message to be determined
             return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found", prob_id));
         }
     }
     for user_id in &body.user_ids {
-        if !users::user_exists(pool.clone(), *user_id).await {
+        if !users::user_exists(storage.clone(), *user_id).await {
             // return message to be determined
             return error_log::NOT_FOUND::webmsg(&format!("User {} not found", user_id));
         }
     }
+    if let Err(e) =
+        teams::check_contest_teams(storage.clone(), &body.team_ids, &body.user_ids).await
+    {
+        return e;
+    }
+    if let Err(e) = groups::check_groups(storage.clone(), &body.group_ids).await {
+        return e;
+    }
+    if let Some(deadline) = &body.registration_deadline {
+        if NaiveDateTime::parse_from_str(deadline, "%Y-%m-%dT%H:%M:%S%.3fZ").is_err() {
+            return error_log::INVALID_ARGUMENT::webmsg("Invalid argument registration_deadline.");
+        }
+    }
     if let Some(id) = body.id {
-        if id == 0 { return error_log::INVALID_ARGUMENT::webmsg("Cannot change contest 0."); }
-        contests::update_contest(body, pool.clone()).await
+        if id == 0 {
+            return error_log::INVALID_ARGUMENT::webmsg("Cannot change contest 0.");
+        }
+        contests::update_contest(body, storage.clone()).await
     } else {
-        match contests::create_contest(body.into_inner(), pool.clone(), ids.clone()).await {
+        match contests::create_contest(body.into_inner(), storage.clone()).await {
             Ok(contest) => HttpResponse::Ok().body(serde_json::to_string_pretty(&contest).unwrap()),
             Err(e) => e,
         }
@@ -332,7 +609,7 @@ pub async fn post_contest(
 #[get("/contests/{contestid}")]
 pub async fn get_contest_by_id(
     path: web::Path<String>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
 ) -> HttpResponse {
     let mut contest_id: u32 = 0;
     match path.parse::<u32>() {
@@ -341,15 +618,15 @@ pub async fn get_contest_by_id(
             return error_log::NOT_FOUND::webmsg(&format!("Contest {} not found.", path));
         }
     };
-    match contests::get_contest(pool, contest_id).await {
+    match contests::get_contest(storage, contest_id).await {
         Ok(contest) => HttpResponse::Ok().body(serde_json::to_string_pretty(&contest).unwrap()),
         Err(e) => e,
     }
 }
 
 #[get("/contests")]
-pub async fn get_contests(pool: Data<Mutex<Pool<SqliteConnectionManager>>>) -> HttpResponse {
-    match contests::get_contests(pool).await {
+pub async fn get_contests(storage: Data<dyn Storage>) -> HttpResponse {
+    match contests::get_contests(storage).await {
         Ok(contests) => HttpResponse::Ok().body(serde_json::to_string_pretty(&contests).unwrap()),
         Err(e) => e,
     }
@@ -359,9 +636,8 @@ pub async fn get_contests(pool: Data<Mutex<Pool<SqliteConnectionManager>>>) -> H
 pub async fn get_ranklist(
     path: web::Path<String>,
     req: HttpRequest,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    config: Data<Config>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> HttpResponse {
     // get contest id
     let mut contest_id: u32 = 0;
@@ -382,13 +658,14 @@ pub async fn get_ranklist(
             if let Some(tb) = flt.tie_breaker {
                 filter.tie_breaker = tb;
             }
+            filter.group_id = flt.group_id;
         }
         _ => {
             return error_log::INVALID_ARGUMENT::webmsg("Invalid argument.");
         }
     };
     println!("{:?}", filter);
-    match contests::get_ranklist(pool.clone(), config, filter, contest_id, ids.clone()).await {
+    match contests::get_ranklist(storage.clone(), prob_map, filter, contest_id).await {
         Ok(ans) => HttpResponse::Ok().body(serde_json::to_string_pretty(&ans).unwrap()),
         Err(e) => e,
     }
@@ -0,0 +1,121 @@
+use actix_web::web::Data;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{self, ApiKey, Scope};
+use crate::error_log;
+use crate::storage::Storage;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostKey {
+    #[serde(default)]
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Never expires if left out.
+    pub expires_time: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewKey {
+    /// Send it as `Authorization: Bearer <key>`. It cannot be shown again.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeysFilter {
+    /// Admins may list the keys of other users.
+    pub user_id: Option<u32>,
+}
+
+/// Create an API key for the caller.
+#[post("/keys")]
+pub async fn post_key(
+    req: HttpRequest,
+    body: web::Json<PostKey>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if body.scopes.is_empty() {
+        return error_log::INVALID_ARGUMENT::webmsg("A key needs at least one scope.");
+    }
+    if body.scopes.contains(&Scope::Admin) && !caller.is_admin() {
+        return error_log::FORBIDDEN::webmsg("Only admins can create keys with the admin scope.");
+    }
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    if let Some(expires_time) = &body.expires_time {
+        if NaiveDateTime::parse_from_str(expires_time, "%Y-%m-%dT%H:%M:%S%.3fZ").is_err() {
+            return error_log::INVALID_ARGUMENT::webmsg("Invalid argument expires_time.");
+        }
+        if *expires_time <= now {
+            return error_log::INVALID_ARGUMENT::webmsg("The expires_time is in the past.");
+        }
+    }
+
+    let key = auth::new_api_key();
+    let mut api_key = ApiKey {
+        id: 0,
+        user_id: caller.user.id,
+        name: body.name.to_string(),
+        scopes: body.scopes.clone(),
+        created_time: now,
+        expires_time: body.expires_time.clone(),
+        last_used_time: None,
+        key_hash: auth::token_hash(&key),
+    };
+    api_key.id = match storage.insert_api_key(&api_key).await {
+        Ok(id) => id,
+        Err(_) => return error_log::EXTERNAL::webmsg("Database Error."),
+    };
+    log::info!("User {} created API key {}", caller.user.id, api_key.id);
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&NewKey { key, api_key }).unwrap())
+}
+
+#[get("/keys")]
+pub async fn get_keys(
+    req: HttpRequest,
+    filter: web::Query<KeysFilter>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let user_id = filter.user_id.unwrap_or(caller.user.id);
+    if user_id != caller.user.id && !caller.is_admin() {
+        return error_log::FORBIDDEN::webmsg("Only admins can list the keys of other users.");
+    }
+    match storage.list_api_keys(user_id).await {
+        Ok(keys) => HttpResponse::Ok().body(serde_json::to_string_pretty(&keys).unwrap()),
+        Err(_) => error_log::EXTERNAL::webmsg("Database Error."),
+    }
+}
+
+/// Revoke a key of the caller, or of anyone for admins.
+#[delete("/keys/{keyid}")]
+pub async fn delete_key(
+    req: HttpRequest,
+    path: web::Path<u32>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let key_id = path.into_inner();
+    let api_key = match storage.get_api_key(key_id).await {
+        Ok(Some(api_key)) if api_key.user_id == caller.user.id || caller.is_admin() => api_key,
+        Ok(_) => return error_log::NOT_FOUND::webmsg(&format!("API key {} not found.", key_id)),
+        Err(_) => return error_log::EXTERNAL::webmsg("Database Error."),
+    };
+    if storage.delete_api_key(key_id).await.is_err() {
+        return error_log::EXTERNAL::webmsg("Database Error.");
+    }
+    log::info!("User {} revoked API key {}", caller.user.id, key_id);
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&api_key).unwrap())
+}
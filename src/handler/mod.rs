@@ -4,7 +4,25 @@ use actix_web::Responder;
 use actix_web::{delete, get, post, web};
 use serde::{Deserialize, Serialize};
 
+pub mod groups;
 pub mod jobs;
+pub mod keys;
+pub mod problems;
+pub mod registrations;
+pub mod sessions;
+pub mod teams;
+
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::audit;
+use crate::config::{Backend, Config, Problem};
+use crate::reload::{self as config_reload, ConfigSource};
+use crate::runner::retention;
+use crate::storage::Storage;
 
 #[post("/internal/exit")]
 #[allow(unreachable_code)]
@@ -14,17 +32,143 @@ async fn exit() -> impl Responder {
     format!("Exited")
 }
 
+#[post("/internal/reload")]
+async fn reload(
+    source: Data<ConfigSource>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let report = config_reload::reload(&source, config, prob_map, storage).await;
+    let body = serde_json::to_string_pretty(&report).unwrap();
+    if report.success {
+        HttpResponse::Ok().body(body)
+    } else {
+        HttpResponse::BadRequest().body(body)
+    }
+}
+
+#[derive(Serialize)]
+struct DirUsage {
+    path: String,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+struct DiskUsage {
+    runtime_dir: DirUsage,
+    /// Number of `job_{id}` directories in `runtime_dir`.
+    job_dirs: u32,
+    problem_data_dir: DirUsage,
+    /// Only for SQLite, with its WAL files.
+    database: Option<DirUsage>,
+}
+
+#[get("/internal/disk")]
+async fn disk_usage(config: Data<Arc<Mutex<Config>>>) -> HttpResponse {
+    let (runtime, storage) = {
+        let config = config.lock().await;
+        (config.runtime.clone(), config.storage.clone())
+    };
+    let usage = web::block(move || {
+        let runtime_dir = &runtime.runtime_dir;
+        let job_dirs = std::fs::read_dir(runtime_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("job_"))
+                    .count() as u32
+            })
+            .unwrap_or(0);
+        let database = match storage.backend {
+            Backend::Sqlite => {
+                let path = &storage.database_path;
+                let bytes = ["", "-wal", "-shm"]
+                    .iter()
+                    .map(|suffix| retention::disk_usage(format!("{}{}", path, suffix).as_ref()))
+                    .sum();
+                Some(DirUsage {
+                    path: path.to_string(),
+                    bytes,
+                })
+            }
+            Backend::Postgres => None,
+        };
+        DiskUsage {
+            runtime_dir: DirUsage {
+                path: runtime_dir.to_string(),
+                bytes: retention::disk_usage(runtime_dir.as_ref()),
+            },
+            job_dirs,
+            problem_data_dir: DirUsage {
+                path: storage.problem_data_dir.to_string(),
+                bytes: retention::disk_usage(storage.problem_data_dir.as_ref()),
+            },
+            database,
+        }
+    })
+    .await
+    .unwrap();
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&usage).unwrap())
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    limit: Option<u32>,
+}
+
+/// The latest admin actions on users, newest first.
+#[get("/internal/audit")]
+async fn get_audit(query: web::Query<AuditQuery>, storage: Data<dyn Storage>) -> HttpResponse {
+    match audit::get_entries(storage, query.limit.unwrap_or(100)).await {
+        Ok(entries) => HttpResponse::Ok().body(serde_json::to_string_pretty(&entries).unwrap()),
+        Err(e) => e,
+    }
+}
+
 pub fn route(config: &mut web::ServiceConfig) {
+    config.service(sessions::login);
+    config.service(sessions::logout);
+    config.service(keys::post_key);
+    config.service(keys::get_keys);
+    config.service(keys::delete_key);
     config.service(jobs::post_job);
     config.service(jobs::get_job_by_id);
     config.service(jobs::get_jobs);
     config.service(jobs::rejudge_job_by_id);
     config.service(jobs::post_user);
+    config.service(jobs::import_users);
     config.service(jobs::get_users);
+    config.service(jobs::get_user_by_id);
+    config.service(jobs::deactivate_user);
+    config.service(jobs::activate_user);
+    config.service(jobs::anonymize_user);
     config.service(jobs::get_ranklist);
+    config.service(teams::post_team);
+    config.service(teams::get_team_by_id);
+    config.service(teams::get_teams);
+    config.service(groups::post_group);
+    config.service(groups::post_members);
+    config.service(groups::delete_member);
+    config.service(groups::get_group_by_id);
+    config.service(groups::get_groups);
     config.service(jobs::post_contest);
     config.service(jobs::get_contest_by_id);
     config.service(jobs::get_contests);
+    config.service(registrations::register);
+    config.service(registrations::leave);
+    config.service(registrations::get_registrations);
+    config.service(registrations::decide);
+    config.service(problems::get_problems);
+    config.service(problems::get_problem_by_id);
+    config.service(problems::post_problem);
+    config.service(problems::put_problem);
+    config.service(problems::delete_problem);
+    config.service(problems::post_problem_data);
+    config.service(problems::get_problem_statement);
+    config.service(problems::get_problem_image);
+    config.service(problems::post_problem_image);
+    config.service(problems::delete_problem_image);
 }
 
 pub async fn default_route() -> impl Responder {
@@ -0,0 +1,363 @@
+use actix_web::web::Data;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::auth;
+use crate::config::{self, Case, Config, Misc, ProbType, Statement};
+use crate::contests;
+use crate::error_log;
+use crate::problems::{self, SerdeProblem, SerdeStatement};
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PostProblem {
+    pub id: Option<u32>,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: ProbType,
+    #[serde(default)]
+    pub misc: Misc,
+    pub cases: Vec<Case>,
+    #[serde(default)]
+    pub statement: Option<Statement>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProblemsFilter {
+    pub contest_id: Option<u32>,
+}
+
+impl PostProblem {
+    pub fn into_problem(self, id: u32) -> config::Problem {
+        config::Problem {
+            id,
+            name: self.name,
+            ty: self.ty,
+            misc: self.misc,
+            cases: self.cases,
+            statement: self.statement,
+            owner_id: None,
+        }
+    }
+}
+
+#[get("/problems")]
+pub async fn get_problems(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let filter = match web::Query::<ProblemsFilter>::from_query(req.query_string()) {
+        Ok(flt) => flt,
+        _ => return error_log::INVALID_ARGUMENT::webmsg("Invalid argument."),
+    };
+    let mut prob_ids: Vec<u32>;
+    if let Some(contest_id) = filter.contest_id.filter(|id| *id != 0) {
+        let contest = match contests::get_contest(storage.clone(), contest_id).await {
+            Ok(con) => con,
+            Err(e) => return e,
+        };
+        let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        if time < contest.from {
+            return error_log::INVALID_STATE::webmsg("The contest has not started yet.");
+        }
+        prob_ids = contest.problem_ids;
+    } else {
+        let hidden = match problems::hidden_problems(storage.clone()).await {
+            Ok(h) => h,
+            Err(e) => return e,
+        };
+        prob_ids = prob_map
+            .lock()
+            .await
+            .keys()
+            .filter(|id| !hidden.contains(id))
+            .copied()
+            .collect();
+        prob_ids.sort();
+    }
+    let languages: Vec<String> = config
+        .lock()
+        .await
+        .languages
+        .iter()
+        .map(|x| x.name.to_string())
+        .collect();
+    let map = prob_map.lock().await;
+    let ans: Vec<SerdeProblem> = prob_ids
+        .iter()
+        .filter_map(|id| map.get(id))
+        .map(|prob| SerdeProblem::new(prob, languages.clone()))
+        .collect();
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&ans).unwrap())
+}
+
+#[get("/problems/{problemid}")]
+pub async fn get_problem_by_id(
+    path: web::Path<String>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let languages: Vec<String> = config
+        .lock()
+        .await
+        .languages
+        .iter()
+        .map(|x| x.name.to_string())
+        .collect();
+    match visible_problem(&path, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok()
+            .body(serde_json::to_string_pretty(&SerdeProblem::new(&prob, languages)).unwrap()),
+        Err(e) => e,
+    }
+}
+
+/// Problems hidden by a contest that has not started are reported as missing.
+async fn visible_problem(
+    path: &str,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> Result<config::Problem, HttpResponse> {
+    let prob_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => {
+            return Err(error_log::NOT_FOUND::webmsg(&format!(
+                "Problem {} not found.",
+                path
+            )));
+        }
+    };
+    if problems::hidden_problems(storage).await?.contains(&prob_id) {
+        return Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Problem {} not found.",
+            prob_id
+        )));
+    }
+    problems::get_problem(prob_map, prob_id).await
+}
+
+#[get("/problems/{problemid}/statement")]
+pub async fn get_problem_statement(
+    path: web::Path<String>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    match visible_problem(&path, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok()
+            .body(serde_json::to_string_pretty(&SerdeStatement::new(&data_root, &prob)).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[get("/problems/{problemid}/images/{name}")]
+pub async fn get_problem_image(
+    path: web::Path<(String, String)>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let (prob_id, name) = path.into_inner();
+    let prob = match visible_problem(&prob_id, storage, prob_map.clone()).await {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let content_type = match name.rsplit('.').next().map(|x| x.to_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    };
+    match problems::get_image(prob.id, &name, &data_root, prob_map).await {
+        Ok(image) => HttpResponse::Ok().content_type(content_type).body(image),
+        Err(e) => e,
+    }
+}
+
+/// The request body is the raw image file.
+#[post("/problems/{problemid}/images/{name}")]
+pub async fn post_problem_image(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let (prob_id, name) = path.into_inner();
+    let prob_id: u32 = match prob_id.parse::<u32>() {
+        Ok(id) => id,
+        _ => {
+            return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found.", prob_id));
+        }
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if let Err(e) = problems::check_owner(&caller, prob_map.clone(), prob_id).await {
+        return e;
+    }
+    match problems::upload_image(prob_id, &name, &body, &data_root, prob_map.clone()).await {
+        Ok(_) => match problems::get_problem(prob_map, prob_id).await {
+            Ok(prob) => HttpResponse::Ok().body(
+                serde_json::to_string_pretty(&SerdeStatement::new(&data_root, &prob)).unwrap(),
+            ),
+            Err(e) => e,
+        },
+        Err(e) => e,
+    }
+}
+
+#[delete("/problems/{problemid}/images/{name}")]
+pub async fn delete_problem_image(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let (prob_id, name) = path.into_inner();
+    let prob_id: u32 = match prob_id.parse::<u32>() {
+        Ok(id) => id,
+        _ => {
+            return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found.", prob_id));
+        }
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if let Err(e) = problems::check_owner(&caller, prob_map.clone(), prob_id).await {
+        return e;
+    }
+    match problems::delete_image(prob_id, &name, &data_root, prob_map.clone()).await {
+        Ok(_) => match problems::get_problem(prob_map, prob_id).await {
+            Ok(prob) => HttpResponse::Ok().body(
+                serde_json::to_string_pretty(&SerdeStatement::new(&data_root, &prob)).unwrap(),
+            ),
+            Err(e) => e,
+        },
+        Err(e) => e,
+    }
+}
+
+#[post("/problems")]
+pub async fn post_problem(
+    req: HttpRequest,
+    body: web::Json<PostProblem>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let body = body.into_inner();
+    let id = body.id;
+    let mut prob = body.into_problem(0);
+    prob.owner_id = Some(caller.user.id);
+    match problems::create_problem(prob, id, &data_root, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok().body(serde_json::to_string_pretty(&prob).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[put("/problems/{problemid}")]
+pub async fn put_problem(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PostProblem>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let prob_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found.", path)),
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if let Err(e) = problems::check_owner(&caller, prob_map.clone(), prob_id).await {
+        return e;
+    }
+    if let Some(id) = body.id {
+        if id != prob_id {
+            return error_log::INVALID_ARGUMENT::webmsg("Problem id cannot be changed.");
+        }
+    }
+    let prob = body.into_inner().into_problem(prob_id);
+    match problems::update_problem(prob, &data_root, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok().body(serde_json::to_string_pretty(&prob).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[delete("/problems/{problemid}")]
+pub async fn delete_problem(
+    req: HttpRequest,
+    path: web::Path<String>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let prob_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found.", path)),
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if let Err(e) = problems::check_owner(&caller, prob_map.clone(), prob_id).await {
+        return e;
+    }
+    match problems::delete_problem(prob_id, &data_root, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok().body(serde_json::to_string_pretty(&prob).unwrap()),
+        Err(e) => e,
+    }
+}
+
+/// The request body is the raw zip archive of the test data.
+#[post("/problems/{problemid}/data")]
+pub async fn post_problem_data(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+) -> HttpResponse {
+    let data_root = config.lock().await.storage.problem_data_dir.to_string();
+    let prob_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Problem {} not found.", path)),
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    if let Err(e) = problems::check_owner(&caller, prob_map.clone(), prob_id).await {
+        return e;
+    }
+    match problems::upload_data(prob_id, &body, &data_root, storage, prob_map).await {
+        Ok(prob) => HttpResponse::Ok().body(serde_json::to_string_pretty(&prob).unwrap()),
+        Err(e) => e,
+    }
+}
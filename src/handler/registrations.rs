@@ -0,0 +1,115 @@
+use actix_web::web::Data;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+use crate::contests::registration::{self, RegistrationState};
+use crate::error_log;
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PostRegistration {
+    /// Only used by `password` contests.
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostDecision {
+    pub state: RegistrationState,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationsFilter {
+    pub state: Option<RegistrationState>,
+}
+
+/// Register the caller for a contest.
+#[post("/contests/{contestid}/registration")]
+pub async fn register(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: Option<web::Json<PostRegistration>>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let contest_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Contest {} not found.", path)),
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let password = body.and_then(|body| body.into_inner().password);
+    match registration::register(storage, contest_id, caller.user.id, password).await {
+        Ok(registration) => {
+            HttpResponse::Ok().body(serde_json::to_string_pretty(&registration).unwrap())
+        }
+        Err(e) => e,
+    }
+}
+
+/// Take the caller out of a contest.
+#[delete("/contests/{contestid}/registration")]
+pub async fn leave(
+    req: HttpRequest,
+    path: web::Path<String>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let contest_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Contest {} not found.", path)),
+    };
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    match registration::leave(storage, contest_id, caller.user.id).await {
+        Ok(()) => {
+            log::info!("User {} left contest {}", caller.user.id, contest_id);
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => e,
+    }
+}
+
+#[get("/contests/{contestid}/registrations")]
+pub async fn get_registrations(
+    path: web::Path<String>,
+    filter: web::Query<RegistrationsFilter>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let contest_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Contest {} not found.", path)),
+    };
+    match registration::list(storage, contest_id, filter.state).await {
+        Ok(registrations) => {
+            HttpResponse::Ok().body(serde_json::to_string_pretty(&registrations).unwrap())
+        }
+        Err(e) => e,
+    }
+}
+
+/// Approve or reject the registration of a user.
+#[post("/contests/{contestid}/registrations/{userid}")]
+pub async fn decide(
+    path: web::Path<(String, String)>,
+    body: web::Json<PostDecision>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    let (contest_id, user_id) = path.into_inner();
+    let contest_id = match contest_id.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Contest {} not found.", contest_id)),
+    };
+    let user_id = match user_id.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("User {} not found.", user_id)),
+    };
+    match registration::decide(storage, contest_id, user_id, body.state).await {
+        Ok(registration) => {
+            HttpResponse::Ok().body(serde_json::to_string_pretty(&registration).unwrap())
+        }
+        Err(e) => e,
+    }
+}
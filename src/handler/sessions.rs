@@ -0,0 +1,94 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::auth::{self, Session};
+use crate::config::Config;
+use crate::error_log;
+use crate::storage::Storage;
+use crate::users::SerdeUser;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostLogin {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginToken {
+    /// Send it back as `Authorization: Bearer <token>`.
+    pub token: String,
+    pub expires_time: String,
+    pub user: SerdeUser,
+}
+
+#[post("/login")]
+pub async fn login(
+    body: web::Json<PostLogin>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+) -> HttpResponse {
+    let wrong = || error_log::UNAUTHORIZED::webmsg("Wrong user name or password.");
+    let user = match storage.get_user_by_name(&body.name).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return wrong(),
+        Err(_) => return error_log::EXTERNAL::webmsg("Database Error."),
+    };
+    // Users without a password cannot log in until one is set with `passwd`.
+    let hash = match storage.get_password_hash(user.id).await {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return wrong(),
+        Err(_) => return error_log::EXTERNAL::webmsg("Database Error."),
+    };
+    let password = body.password.to_string();
+    match web::block(move || auth::verify_password(&password, &hash)).await {
+        Ok(true) => {}
+        _ => return wrong(),
+    }
+    if user.deactivated_time.is_some() {
+        return error_log::FORBIDDEN::webmsg(&format!("User {} is deactivated.", user.id));
+    }
+
+    let hours = config.lock().await.auth.session_hours;
+    let now = Utc::now();
+    let token = auth::new_token();
+    let session = Session {
+        token_hash: auth::token_hash(&token),
+        user_id: user.id,
+        created_time: now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        expires_time: (now + chrono::Duration::hours(hours as i64))
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string(),
+    };
+    if storage.insert_session(&session).await.is_err() {
+        return error_log::EXTERNAL::webmsg("Database Error.");
+    }
+    log::info!("User {} logged in", user.id);
+    let ans = LoginToken {
+        token,
+        expires_time: session.expires_time,
+        user,
+    };
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&ans).unwrap())
+}
+
+/// End the session of the token the request was made with.
+#[post("/logout")]
+pub async fn logout(req: HttpRequest, storage: Data<dyn Storage>) -> HttpResponse {
+    let caller = match auth::caller(&req) {
+        Ok(caller) => caller,
+        Err(e) => return *e,
+    };
+    let token = auth::bearer_token(&req).unwrap_or_default();
+    if storage
+        .delete_session(&auth::token_hash(&token))
+        .await
+        .is_err()
+    {
+        return error_log::EXTERNAL::webmsg("Database Error.");
+    }
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&caller.user).unwrap())
+}
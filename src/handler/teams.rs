@@ -0,0 +1,44 @@
+use actix_web::web::Data;
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::error_log;
+use crate::storage::Storage;
+use crate::teams;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PostTeam {
+    pub id: Option<u32>,
+    pub name: String,
+    /// Replaces the members of an existing team.
+    #[serde(default)]
+    pub member_ids: Vec<u32>,
+}
+
+#[post("/teams")]
+pub async fn post_team(body: web::Json<PostTeam>, storage: Data<dyn Storage>) -> HttpResponse {
+    match teams::save_team(storage, body.into_inner()).await {
+        Ok(team) => HttpResponse::Ok().body(serde_json::to_string_pretty(&team).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[get("/teams/{teamid}")]
+pub async fn get_team_by_id(path: web::Path<String>, storage: Data<dyn Storage>) -> HttpResponse {
+    let team_id = match path.parse::<u32>() {
+        Ok(id) => id,
+        _ => return error_log::NOT_FOUND::webmsg(&format!("Team {} not found.", path)),
+    };
+    match teams::get_team(storage, team_id).await {
+        Ok(team) => HttpResponse::Ok().body(serde_json::to_string_pretty(&team).unwrap()),
+        Err(e) => e,
+    }
+}
+
+#[get("/teams")]
+pub async fn get_teams(storage: Data<dyn Storage>) -> HttpResponse {
+    match teams::get_teams(storage).await {
+        Ok(teams) => HttpResponse::Ok().body(serde_json::to_string_pretty(&teams).unwrap()),
+        Err(e) => e,
+    }
+}
@@ -1,6 +1,15 @@
-mod config;
-mod contests;
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod config;
+pub mod contests;
 mod error_log;
-mod handler;
-mod runner;
-mod users;
+pub mod groups;
+pub mod handler;
+pub mod migrations;
+pub mod problems;
+mod reload;
+pub mod runner;
+pub mod storage;
+pub mod teams;
+pub mod users;
@@ -1,28 +1,36 @@
 use actix_web::web::{self, route, Data};
 use actix_web::{middleware::Logger, App, HttpServer};
 use clap::Arg;
-use config::Ids;
 use env_logger;
 use log;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
 use std::collections::HashMap;
 use std::error::Error;
 use std::result::Result as StdResult;
 use std::sync::Arc;
+use storage::Storage;
 use tokio::sync::Mutex;
 
+mod archive;
+mod audit;
+mod auth;
 mod config;
 mod contests;
 mod error_log;
+mod groups;
 mod handler;
+mod migrations;
+mod problems;
+mod reload;
 mod runner;
+mod storage;
+mod teams;
 mod users;
 
 pub type Result<T = (), E = Box<dyn Error>> = StdResult<T, E>;
 
 #[actix_web::main]
 async fn main() -> Result {
+    dotenv::dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
     // Get args
@@ -35,7 +43,57 @@ async fn main() -> Result {
                 .short('c')
                 .long("config")
                 .takes_value(true)
-                .help("The config file path."),
+                .global(true)
+                .help("The config file path (JSON, TOML or YAML)."),
+        )
+        .arg(
+            Arg::with_name("bind_address")
+                .long("bind-address")
+                .takes_value(true)
+                .global(true)
+                .help("Override server.bind_address in the config."),
+        )
+        .arg(
+            Arg::with_name("bind_port")
+                .long("bind-port")
+                .takes_value(true)
+                .global(true)
+                .help("Override server.bind_port in the config."),
+        )
+        .arg(
+            Arg::with_name("database_path")
+                .long("database")
+                .takes_value(true)
+                .global(true)
+                .help("Override storage.database_path in the config."),
+        )
+        .arg(
+            Arg::with_name("database_url")
+                .long("database-url")
+                .takes_value(true)
+                .global(true)
+                .help("Use the PostgreSQL database at this URL instead of SQLite."),
+        )
+        .arg(
+            Arg::with_name("problem_data_dir")
+                .long("problem-data-dir")
+                .takes_value(true)
+                .global(true)
+                .help("Override storage.problem_data_dir in the config."),
+        )
+        .arg(
+            Arg::with_name("runtime_dir")
+                .long("runtime-dir")
+                .takes_value(true)
+                .global(true)
+                .help("Override runtime.runtime_dir in the config."),
+        )
+        .arg(
+            Arg::with_name("compile_timeout")
+                .long("compile-timeout")
+                .takes_value(true)
+                .global(true)
+                .help("Override runtime.compile_timeout (seconds) in the config."),
         )
         .arg(
             Arg::with_name("flush_data")
@@ -44,11 +102,75 @@ async fn main() -> Result {
                 .takes_value(false)
                 .help("Toggle to flush OJ data in database."),
         )
+        .subcommand(
+            clap::App::new("validate").about("Check the config file and report every problem."),
+        )
+        .subcommand(
+            clap::App::new("migrate")
+                .about("Show the database schema version and pending migrations.")
+                .arg(
+                    Arg::with_name("apply")
+                        .long("apply")
+                        .takes_value(false)
+                        .help("Apply the pending migrations."),
+                ),
+        )
+        .subcommand(
+            clap::App::new("export")
+                .about("Write users, contests, problems and jobs to a JSON lines file.")
+                .arg(
+                    Arg::with_name("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .help("The file to write, standard output if not given."),
+                ),
+        )
+        .subcommand(
+            clap::App::new("import")
+                .about("Add the data of an export to the database, with new ids.")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .help("The export file to read."),
+                ),
+        )
+        .subcommand(
+            clap::App::new("backup")
+                .about("Copy the SQLite database, safe to run while the server is up.")
+                .arg(
+                    Arg::with_name("output")
+                        .required(true)
+                        .help("The file to write, which must not exist."),
+                ),
+        )
+        .subcommand(
+            clap::App::new("import-users")
+                .about("Create users from a CSV file with the columns name,display_name,group,password.")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .help("The CSV file to read."),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("Only check the rows."),
+                ),
+        )
+        .subcommand(
+            clap::App::new("passwd")
+                .about("Set the password of a user, read from standard input.")
+                .arg(
+                    Arg::with_name("user")
+                        .required(true)
+                        .help("The name of the user."),
+                ),
+        )
         .get_matches();
-    if matches.is_present("flush_data") {
-        let _ = std::fs::remove_file("data.db");
-    }
-    let mut config_path: String = "config.json".to_string();
+    let mut config_path: String =
+        std::env::var("MROJ_CONFIG").unwrap_or_else(|_| "config.json".to_string());
     if matches.is_present("config_path") {
         if let Some(path) = matches.value_of("config_path") {
             config_path = String::from(path);
@@ -57,22 +179,247 @@ async fn main() -> Result {
         }
     }
 
-    let manager = SqliteConnectionManager::file("data.db");
-    let pool = Pool::new(manager).unwrap();
+    // Command line flags win over `MROJ_*` environment variables, which win over the file.
+    let overrides = config::Overrides::from_env().merge(config::Overrides {
+        bind_address: matches.value_of("bind_address").map(String::from),
+        bind_port: matches.value_of("bind_port").map(String::from),
+        database_path: matches.value_of("database_path").map(String::from),
+        database_url: matches.value_of("database_url").map(String::from),
+        problem_data_dir: matches.value_of("problem_data_dir").map(String::from),
+        runtime_dir: matches.value_of("runtime_dir").map(String::from),
+        compile_timeout: matches.value_of("compile_timeout").map(String::from),
+    });
+    let config: config::Config = match config::parse_from_file(config_path.to_string())
+        .and_then(|mut config| overrides.apply(&mut config).map(|_| config))
+    {
+        Ok(config) => config,
+        Err(issue) => {
+            log::error!("{}", issue);
+            std::process::exit(1);
+        }
+    };
+    let issues = config::validate(&config);
+    let fatal = issues
+        .iter()
+        .filter(|issue| issue.severity == config::Severity::Error)
+        .count();
+    if matches.subcommand_matches("validate").is_some() {
+        for issue in &issues {
+            println!("{}", issue);
+        }
+        println!(
+            "{}: {} error(s), {} warning(s)",
+            config_path,
+            fatal,
+            issues.len() - fatal
+        );
+        std::process::exit(if fatal > 0 { 1 } else { 0 });
+    }
+    if let Some(sub) = matches.subcommand_matches("migrate") {
+        let storage = open_storage(&config.storage).await;
+        let pending = match migrations::pending(storage.as_ref()).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let location = match config.storage.backend {
+            config::Backend::Sqlite => config.storage.database_path.to_string(),
+            config::Backend::Postgres => "postgres".to_string(),
+        };
+        println!(
+            "{}: schema version {}, latest {}",
+            location,
+            storage.schema_version().await?,
+            migrations::latest_version()
+        );
+        for migration in &pending {
+            println!("pending: {} {}", migration.version, migration.name);
+        }
+        if sub.is_present("apply") {
+            if let Err(e) = migrations::migrate(storage.as_ref()).await {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+            println!("applied {} migration(s)", pending.len());
+        }
+        std::process::exit(0);
+    }
+    if let Some(sub) = matches.subcommand_matches("export") {
+        let storage = open_storage(&config.storage).await;
+        refuse_pending_migrations(storage.as_ref()).await;
+        let result = match sub.value_of("output") {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(file) => {
+                    archive::export(storage.as_ref(), &mut std::io::BufWriter::new(file)).await
+                }
+                Err(e) => Err(format!("Cannot create {}: {}", path, e)),
+            },
+            None => archive::export(storage.as_ref(), &mut std::io::stdout().lock()).await,
+        };
+        match result {
+            Ok(summary) => eprintln!(
+                "exported {} user(s), {} team(s), {} group(s), {} contest(s), {} problem(s), {} job(s)",
+                summary.users,
+                summary.teams,
+                summary.groups,
+                summary.contests,
+                summary.problems,
+                summary.jobs
+            ),
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+    if let Some(sub) = matches.subcommand_matches("import") {
+        let storage = open_storage(&config.storage).await;
+        refuse_pending_migrations(storage.as_ref()).await;
+        let path = sub.value_of("input").unwrap();
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Cannot open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        match archive::import(storage.as_ref(), &mut std::io::BufReader::new(file)).await {
+            Ok(summary) => {
+                for conflict in &summary.conflicts {
+                    println!("conflict: {}", conflict);
+                }
+                println!(
+                    "imported {} user(s), {} team(s), {} group(s), {} contest(s), {} problem(s), {} job(s), {} conflict(s)",
+                    summary.users,
+                    summary.teams,
+                    summary.groups,
+                    summary.contests,
+                    summary.problems,
+                    summary.jobs,
+                    summary.conflicts.len()
+                );
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+    if let Some(sub) = matches.subcommand_matches("backup") {
+        let storage = open_storage(&config.storage).await;
+        let path = sub.value_of("output").unwrap();
+        if let Err(e) = storage.backup(path).await {
+            log::error!("Cannot back up the database: {}", e);
+            std::process::exit(1);
+        }
+        println!("backed up to {}", path);
+        std::process::exit(0);
+    }
+    if let Some(sub) = matches.subcommand_matches("import-users") {
+        let storage = open_storage(&config.storage).await;
+        refuse_pending_migrations(storage.as_ref()).await;
+        let path = sub.value_of("input").unwrap();
+        let rows = match std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path, e))
+            .and_then(|input| users::import::parse(&input))
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let dry_run = sub.is_present("dry_run");
+        let report = match users::import::import(storage.as_ref(), &rows, dry_run).await {
+            Ok(report) => report,
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        for row in &report.rows {
+            match (&row.error, row.user_id, &row.password) {
+                (Some(error), _, _) => println!("line {}: {}", row.line, error),
+                (None, Some(id), Some(password)) => {
+                    println!("created {} as user {}, password {}", row.name, id, password)
+                }
+                (None, Some(id), None) => println!("created {} as user {}", row.name, id),
+                (None, None, _) => {}
+            }
+        }
+        if report.errors > 0 {
+            println!("{} error(s), no user created", report.errors);
+            std::process::exit(1);
+        }
+        if report.dry_run {
+            println!("{} user(s) to create", report.rows.len());
+        } else {
+            println!("created {} user(s)", report.created);
+        }
+        std::process::exit(0);
+    }
+    if let Some(sub) = matches.subcommand_matches("passwd") {
+        let storage = open_storage(&config.storage).await;
+        refuse_pending_migrations(storage.as_ref()).await;
+        let name = sub.value_of("user").unwrap();
+        let user = match storage.get_user_by_name(name).await? {
+            Some(user) => user,
+            None => {
+                log::error!("User {} not found.", name);
+                std::process::exit(1);
+            }
+        };
+        eprint!("New password for {}: ", name);
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        let password = password.trim_end_matches(&['\r', '\n'][..]);
+        if password.chars().count() < auth::MIN_PASSWORD_LEN {
+            log::error!(
+                "Password must have at least {} characters.",
+                auth::MIN_PASSWORD_LEN
+            );
+            std::process::exit(1);
+        }
+        storage
+            .set_password_hash(user.id, &auth::hash_password(password)?)
+            .await?;
+        storage.delete_sessions(user.id).await?;
+        println!("password of {} set", name);
+        std::process::exit(0);
+    }
+    for issue in &issues {
+        match issue.severity {
+            config::Severity::Error => log::error!("{}", issue),
+            config::Severity::Warning => log::warn!("{}", issue),
+        }
+    }
+    if fatal > 0 {
+        log::error!(
+            "Refusing to start: {} has {} error(s), run `validate` for details.",
+            config_path,
+            fatal
+        );
+        std::process::exit(1);
+    }
 
-    // Create a database table if not exists.
-    let conn = pool.get().unwrap();
-    conn.execute("CREATE TABLE IF NOT EXISTS jobs (id INT, created_time VARCHAR, updated_time VARCHAR, submission_id INT, state VARCHAR, result VARCHAR, score FLOAT, cases INT);", [])?;
-    conn.execute("CREATE TABLE IF NOT EXISTS submission (id INT, source_code VARCHAR, language VARCHAR, user_id INT, contest_id INT, problem_id INT);", [])?;
-    conn.execute("CREATE TABLE IF NOT EXISTS cases (jobid INT, caseid INT, result VARCHAR, time INT, memory INT, info VARCHAR)", [])?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (id INT, name VARCHAR)",
-        [],
-    )?;
-    conn.execute("CREATE TABLE IF NOT EXISTS contests (id INT, name VARCHAR, from_time VARCHAR, to_time VARCHAR, problem_ids VARCHAR, user_ids VARCHAR, submission_limit INT)", [])?;
+    let storage = match storage::open(&config.storage, matches.is_present("flush_data")).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            log::error!("Cannot open the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Bring the database schema up to date.
+    if let Err(e) = migrations::migrate(storage.as_ref()).await {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+    let storage: Data<dyn Storage> = Data::from(storage);
 
-    let config: config::Config =
-        config::parse_from_file(config_path).expect("Config file format error.");
     let (address, port) = (
         config.server.bind_address.to_string(),
         config.server.bind_port,
@@ -82,52 +429,77 @@ async fn main() -> Result {
     for prob in &config.problems {
         prob_map.insert(prob.id, prob.clone());
     }
+    let prob_map = Data::new(Arc::new(Mutex::new(prob_map)));
+    if problems::load_problems(storage.clone(), prob_map.clone())
+        .await
+        .is_err()
+    {
+        panic!("Cannot load problems from database.");
+    }
 
-    // get the maximal job, user and contest id
-    let mut stmt = conn.prepare("SELECT * FROM jobs ORDER BY id DESC LIMIT 1;")?;
-    let jobsid: i32 = match stmt.exists([]) {
-        Ok(true) => stmt.query([])?.next()?.unwrap().get(0)?,
-        _ => -1,
-    } + 1;
-    println!("Max Job ID: {}", jobsid);
-
-    let mut stmt = conn.prepare("SELECT * FROM users ORDER BY id DESC LIMIT 1;")?;
-    let usersid: i32 = match stmt.exists([]) {
-        Ok(true) => stmt.query([])?.next()?.unwrap().get(0)?,
-        _ => -1,
-    } + 1;
-    println!("Max User ID: {}", usersid);
-    let flag = usersid == 0;
-
-    let mut stmt = conn.prepare("SELECT * FROM contests ORDER BY id DESC LIMIT 1;")?;
-    let contestsid: i32 = match stmt.exists([]) {
-        Ok(true) => stmt.query([])?.next()?.unwrap().get(0)?,
-        _ => 0,
-    } + 1;
-    println!("Max Contest ID: {}", contestsid);
+    // Ids are given out by the database, the first user is root, the admin.
+    if storage.list_users().await?.is_empty() {
+        if let Ok(root) = users::create_user(storage.clone(), "root").await {
+            storage.set_role(root.id, users::Role::Admin).await?;
+        }
+        log::warn!("Created user root, set its password with `passwd root` to log in.");
+    }
 
-    let ids = Data::new(Arc::new(Mutex::new(Ids {
-        jobsid: jobsid as u32,
-        usersid: usersid as u32,
-        contestsid: contestsid as u32,
-    })));
+    let config = Data::new(Arc::new(Mutex::new(config)));
+    let source = Data::new(reload::ConfigSource {
+        path: config_path.to_string(),
+        overrides,
+    });
 
-    if flag {
-        let _ = users::create_user(Data::new(Mutex::new(pool.clone())), "root", ids.clone()).await;
+    // Reload the config on SIGHUP, like `POST /internal/reload` does.
+    #[cfg(unix)]
+    {
+        let (source, config, prob_map) = (source.clone(), config.clone(), prob_map.clone());
+        let storage = storage.clone();
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                reload::reload(&source, config.clone(), prob_map.clone(), storage.clone()).await;
+            }
+        });
     }
 
-    println!("{:?}", config);
+    // Apply the retention policy to job directories left over by earlier runs or kept for a while.
+    {
+        let (config, prob_map) = (config.clone(), prob_map.clone());
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            loop {
+                let interval = config
+                    .lock()
+                    .await
+                    .runtime
+                    .retention
+                    .cleanup_interval
+                    .max(1);
+                tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+                runner::retention::cleanup(storage.clone(), config.clone(), prob_map.clone()).await;
+            }
+        });
+    }
 
+    let limiter = Data::new(auth::rate_limit::RateLimiter::default());
     log::info!("starting HTTP server at http://{}:{}", address, port); //config.server.bind_address, config.server.bind_port);
     HttpServer::new(move || {
         App::new()
+            .wrap(auth::Authenticate)
             .wrap(Logger::default())
-            .app_data(ids.clone())
-            .app_data(Data::new(config.clone()))
-            .app_data(Data::new(prob_map.clone()))
-            .app_data(Data::new(Mutex::new(pool.clone())))
+            .app_data(config.clone())
+            .app_data(source.clone())
+            .app_data(prob_map.clone())
+            .app_data(web::PayloadConfig::new(64 * 1024 * 1024))
+            .app_data(storage.clone())
+            .app_data(limiter.clone())
             .configure(handler::route)
             .service(handler::exit)
+            .service(handler::reload)
+            .service(handler::disk_usage)
+            .service(handler::get_audit)
             .default_service(route().to(handler::default_route))
     })
     .bind((address, port))? //(config.server.bind_address, config.server.bind_port))?
@@ -135,3 +507,29 @@ async fn main() -> Result {
     .await?;
     Ok(())
 }
+
+/// Open the database for a subcommand, or exit.
+async fn open_storage(config: &config::Storage) -> Arc<dyn Storage> {
+    match storage::open(config, false).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            log::error!("Cannot open the database: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exports are read with the latest schema, so an older database must be migrated first.
+async fn refuse_pending_migrations(storage: &dyn Storage) {
+    match migrations::pending(storage).await {
+        Ok(pending) if pending.is_empty() => {}
+        Ok(_) => {
+            log::error!("The database has pending migrations, run `migrate --apply` first.");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::storage::Storage;
+
+/// One step of the database schema. Migrations are applied in order of `version`
+/// and each one runs in its own transaction.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sqlite: &'static str,
+    pub postgres: &'static str,
+}
+
+/// Never edit a migration once it is released, add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        // `IF NOT EXISTS`, so that databases created before versioning are adopted as they are.
+        sqlite: "
+        CREATE TABLE IF NOT EXISTS jobs (id INT, created_time VARCHAR, updated_time VARCHAR, submission_id INT, state VARCHAR, result VARCHAR, score FLOAT, cases INT);
+        CREATE TABLE IF NOT EXISTS submission (id INT, source_code VARCHAR, language VARCHAR, user_id INT, contest_id INT, problem_id INT);
+        CREATE TABLE IF NOT EXISTS cases (jobid INT, caseid INT, result VARCHAR, time INT, memory INT, info VARCHAR);
+        CREATE TABLE IF NOT EXISTS users (id INT, name VARCHAR);
+        CREATE TABLE IF NOT EXISTS contests (id INT, name VARCHAR, from_time VARCHAR, to_time VARCHAR, problem_ids VARCHAR, user_ids VARCHAR, submission_limit INT);
+        CREATE TABLE IF NOT EXISTS problems (id INT, content VARCHAR);
+    ",
+        // PostgreSQL support came with version 2 of the SQLite schema, so its tables start keyed.
+        postgres: "
+        CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE contests (id INTEGER PRIMARY KEY, name VARCHAR, from_time VARCHAR, to_time VARCHAR, problem_ids VARCHAR, user_ids VARCHAR, submission_limit INTEGER);
+        CREATE TABLE submission (id INTEGER PRIMARY KEY, source_code VARCHAR, language VARCHAR, user_id INTEGER, contest_id INTEGER, problem_id INTEGER);
+        CREATE TABLE jobs (id INTEGER PRIMARY KEY, created_time VARCHAR, updated_time VARCHAR, submission_id INTEGER NOT NULL REFERENCES submission (id) ON DELETE CASCADE, state VARCHAR, result VARCHAR, score REAL, cases INTEGER);
+        CREATE TABLE cases (jobid INTEGER NOT NULL REFERENCES jobs (id) ON DELETE CASCADE, caseid INTEGER NOT NULL, result VARCHAR, time INTEGER, memory INTEGER, info VARCHAR, PRIMARY KEY (jobid, caseid));
+        CREATE TABLE problems (id INTEGER PRIMARY KEY, content VARCHAR);
+    ",
+    },
+    Migration {
+        version: 2,
+        name: "primary keys, foreign keys and indices",
+        // The old tables are renamed aside and copied over. Rows that would break a key
+        // (duplicate ids or names, cases without a job, jobs without a submission) make the
+        // migration fail rather than being dropped, so that they can be fixed by hand.
+        sqlite: "
+        ALTER TABLE users RENAME TO users_v1;
+        ALTER TABLE contests RENAME TO contests_v1;
+        ALTER TABLE submission RENAME TO submission_v1;
+        ALTER TABLE jobs RENAME TO jobs_v1;
+        ALTER TABLE cases RENAME TO cases_v1;
+        ALTER TABLE problems RENAME TO problems_v1;
+
+        CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE contests (id INTEGER PRIMARY KEY, name VARCHAR, from_time VARCHAR, to_time VARCHAR, problem_ids VARCHAR, user_ids VARCHAR, submission_limit INT);
+        CREATE TABLE submission (id INTEGER PRIMARY KEY, source_code VARCHAR, language VARCHAR, user_id INT, contest_id INT, problem_id INT);
+        CREATE TABLE jobs (id INTEGER PRIMARY KEY, created_time VARCHAR, updated_time VARCHAR, submission_id INT NOT NULL REFERENCES submission (id) ON DELETE CASCADE, state VARCHAR, result VARCHAR, score FLOAT, cases INT);
+        CREATE TABLE cases (jobid INT NOT NULL REFERENCES jobs (id) ON DELETE CASCADE, caseid INT NOT NULL, result VARCHAR, time INT, memory INT, info VARCHAR, PRIMARY KEY (jobid, caseid));
+        CREATE TABLE problems (id INTEGER PRIMARY KEY, content VARCHAR);
+
+        INSERT INTO users (id, name)
+            SELECT id, name FROM users_v1 ORDER BY id;
+        INSERT INTO contests (id, name, from_time, to_time, problem_ids, user_ids, submission_limit)
+            SELECT id, name, from_time, to_time, problem_ids, user_ids, submission_limit FROM contests_v1;
+        INSERT INTO submission (id, source_code, language, user_id, contest_id, problem_id)
+            SELECT id, source_code, language, user_id, contest_id, problem_id FROM submission_v1;
+        INSERT INTO jobs (id, created_time, updated_time, submission_id, state, result, score, cases)
+            SELECT id, created_time, updated_time, submission_id, state, result, score, cases FROM jobs_v1;
+        INSERT INTO cases (jobid, caseid, result, time, memory, info)
+            SELECT jobid, caseid, result, time, memory, info FROM cases_v1;
+        INSERT INTO problems (id, content)
+            SELECT id, content FROM problems_v1;
+
+        DROP TABLE users_v1;
+        DROP TABLE contests_v1;
+        DROP TABLE submission_v1;
+        DROP TABLE jobs_v1;
+        DROP TABLE cases_v1;
+        DROP TABLE problems_v1;
+
+        CREATE INDEX submission_user_id ON submission (user_id);
+        CREATE INDEX submission_problem_id ON submission (problem_id);
+        CREATE INDEX submission_contest_id ON submission (contest_id);
+        CREATE INDEX jobs_submission_id ON jobs (submission_id);
+        CREATE INDEX jobs_created_time ON jobs (created_time);
+    ",
+        postgres: "
+        CREATE INDEX submission_user_id ON submission (user_id);
+        CREATE INDEX submission_problem_id ON submission (problem_id);
+        CREATE INDEX submission_contest_id ON submission (contest_id);
+        CREATE INDEX jobs_submission_id ON jobs (submission_id);
+        CREATE INDEX jobs_created_time ON jobs (created_time);
+    ",
+    },
+    Migration {
+        version: 3,
+        name: "passwords and sessions",
+        // Only the SHA-256 of a session token is stored, the token itself is given to the client once.
+        sqlite: "
+        ALTER TABLE users ADD COLUMN password_hash VARCHAR;
+        CREATE TABLE sessions (token_hash VARCHAR PRIMARY KEY, user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE, created_time VARCHAR, expires_time VARCHAR);
+        CREATE INDEX sessions_user_id ON sessions (user_id);
+    ",
+        postgres: "
+        ALTER TABLE users ADD COLUMN password_hash VARCHAR;
+        CREATE TABLE sessions (token_hash VARCHAR PRIMARY KEY, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, created_time VARCHAR, expires_time VARCHAR);
+        CREATE INDEX sessions_user_id ON sessions (user_id);
+    ",
+    },
+    Migration {
+        version: 4,
+        name: "user roles",
+        // The root user created on first start becomes the admin.
+        sqlite: "
+        ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'contestant';
+        UPDATE users SET role = 'admin' WHERE id = 0 AND name = 'root';
+    ",
+        postgres: "
+        ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'contestant';
+        UPDATE users SET role = 'admin' WHERE id = 0 AND name = 'root';
+    ",
+    },
+    Migration {
+        version: 5,
+        name: "api keys",
+        // Like sessions, only the SHA-256 of a key is stored. Scopes are a JSON list.
+        sqlite: "
+        CREATE TABLE api_keys (id INTEGER PRIMARY KEY, user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE, name VARCHAR, key_hash VARCHAR NOT NULL UNIQUE, scopes VARCHAR, created_time VARCHAR, expires_time VARCHAR, last_used_time VARCHAR);
+        CREATE INDEX api_keys_user_id ON api_keys (user_id);
+    ",
+        postgres: "
+        CREATE TABLE api_keys (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, name VARCHAR, key_hash VARCHAR NOT NULL UNIQUE, scopes VARCHAR, created_time VARCHAR, expires_time VARCHAR, last_used_time VARCHAR);
+        CREATE INDEX api_keys_user_id ON api_keys (user_id);
+    ",
+    },
+    Migration {
+        version: 6,
+        name: "teams",
+        // A submission keeps the team it was made for, even if the members change later.
+        sqlite: "
+        CREATE TABLE teams (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE team_members (team_id INT NOT NULL REFERENCES teams (id) ON DELETE CASCADE, user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE, PRIMARY KEY (team_id, user_id));
+        CREATE INDEX team_members_user_id ON team_members (user_id);
+        ALTER TABLE contests ADD COLUMN team_ids VARCHAR NOT NULL DEFAULT '[]';
+        ALTER TABLE submission ADD COLUMN team_id INT;
+        CREATE INDEX submission_team_id ON submission (team_id);
+    ",
+        postgres: "
+        CREATE TABLE teams (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE team_members (team_id INTEGER NOT NULL REFERENCES teams (id) ON DELETE CASCADE, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, PRIMARY KEY (team_id, user_id));
+        CREATE INDEX team_members_user_id ON team_members (user_id);
+        ALTER TABLE contests ADD COLUMN team_ids VARCHAR NOT NULL DEFAULT '[]';
+        ALTER TABLE submission ADD COLUMN team_id INTEGER;
+        CREATE INDEX submission_team_id ON submission (team_id);
+    ",
+    },
+    Migration {
+        version: 7,
+        name: "user groups",
+        sqlite: "
+        CREATE TABLE user_groups (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE group_members (group_id INT NOT NULL REFERENCES user_groups (id) ON DELETE CASCADE, user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE, PRIMARY KEY (group_id, user_id));
+        CREATE INDEX group_members_user_id ON group_members (user_id);
+        ALTER TABLE contests ADD COLUMN group_ids VARCHAR NOT NULL DEFAULT '[]';
+    ",
+        postgres: "
+        CREATE TABLE user_groups (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL UNIQUE);
+        CREATE TABLE group_members (group_id INTEGER NOT NULL REFERENCES user_groups (id) ON DELETE CASCADE, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, PRIMARY KEY (group_id, user_id));
+        CREATE INDEX group_members_user_id ON group_members (user_id);
+        ALTER TABLE contests ADD COLUMN group_ids VARCHAR NOT NULL DEFAULT '[]';
+    ",
+    },
+    Migration {
+        version: 8,
+        name: "contest registration",
+        sqlite: "
+        ALTER TABLE contests ADD COLUMN registration VARCHAR NOT NULL DEFAULT 'closed';
+        ALTER TABLE contests ADD COLUMN registration_password VARCHAR;
+        ALTER TABLE contests ADD COLUMN registration_deadline VARCHAR;
+        CREATE TABLE registrations (contest_id INT NOT NULL REFERENCES contests (id) ON DELETE CASCADE, user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE, state VARCHAR NOT NULL, created_time VARCHAR, updated_time VARCHAR, PRIMARY KEY (contest_id, user_id));
+    ",
+        postgres: "
+        ALTER TABLE contests ADD COLUMN registration VARCHAR NOT NULL DEFAULT 'closed';
+        ALTER TABLE contests ADD COLUMN registration_password VARCHAR;
+        ALTER TABLE contests ADD COLUMN registration_deadline VARCHAR;
+        CREATE TABLE registrations (contest_id INTEGER NOT NULL REFERENCES contests (id) ON DELETE CASCADE, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, state VARCHAR NOT NULL, created_time VARCHAR, updated_time VARCHAR, PRIMARY KEY (contest_id, user_id));
+    ",
+    },
+    Migration {
+        version: 9,
+        name: "user deactivation and audit log",
+        // Audit entries keep plain ids, so that they outlive what they are about.
+        sqlite: "
+        ALTER TABLE users ADD COLUMN deactivated_time VARCHAR;
+        CREATE TABLE audit_log (id INTEGER PRIMARY KEY, time VARCHAR NOT NULL, actor_id INT NOT NULL, action VARCHAR NOT NULL, user_id INT NOT NULL, detail VARCHAR NOT NULL DEFAULT '');
+    ",
+        postgres: "
+        ALTER TABLE users ADD COLUMN deactivated_time VARCHAR;
+        CREATE TABLE audit_log (id INTEGER PRIMARY KEY, time VARCHAR NOT NULL, actor_id INTEGER NOT NULL, action VARCHAR NOT NULL, user_id INTEGER NOT NULL, detail VARCHAR NOT NULL DEFAULT '');
+    ",
+    },
+    Migration {
+        version: 10,
+        name: "user display names",
+        sqlite: "
+        ALTER TABLE users ADD COLUMN display_name VARCHAR;
+    ",
+        postgres: "
+        ALTER TABLE users ADD COLUMN display_name VARCHAR;
+    ",
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// The migrations that `migrate` would apply. Fails if the database is newer than this binary.
+pub async fn pending(storage: &dyn Storage) -> Result<Vec<&'static Migration>, String> {
+    let current = storage
+        .schema_version()
+        .await
+        .map_err(|e| format!("Cannot read the schema version: {}", e))?;
+    if current > latest_version() {
+        return Err(format!(
+            "The database has schema version {}, but this build only knows up to version {}. \
+             Refusing to downgrade, please run a newer build.",
+            current,
+            latest_version()
+        ));
+    }
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Bring the database up to the latest schema version, returning the versions applied.
+pub async fn migrate(storage: &dyn Storage) -> Result<Vec<u32>, String> {
+    let mut applied = vec![];
+    for migration in pending(storage).await? {
+        log::info!(
+            "Applying migration {}: {}",
+            migration.version,
+            migration.name
+        );
+        storage
+            .apply_migration(migration)
+            .await
+            .map_err(|e| format!("Migration {} failed: {}", migration.version, e))?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
@@ -0,0 +1,544 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::auth::Caller;
+use crate::config::{self, ProbType, Problem, Severity, StatementFormat};
+use crate::contests;
+use crate::error_log;
+use crate::storage::Storage;
+
+/// What everyone may know about a problem: never file paths or answers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SerdeProblem {
+    pub id: u32,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: ProbType,
+    pub time_limit: u64,
+    pub memory_limit: u32,
+    pub case_count: u32,
+    pub subtasks: Vec<SerdeSubtask>,
+    pub languages: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SerdeSubtask {
+    pub cases: Vec<u32>,
+    pub score: f32,
+}
+
+impl SerdeProblem {
+    pub fn new(prob: &Problem, languages: Vec<String>) -> SerdeProblem {
+        let packing: Vec<Vec<u32>> = match &prob.misc.packing {
+            Some(pack) => pack.clone(),
+            None => (1..=prob.cases.len() as u32).map(|i| vec![i]).collect(),
+        };
+        let subtasks = packing
+            .into_iter()
+            .map(|cases| SerdeSubtask {
+                score: cases
+                    .iter()
+                    .filter_map(|i| (*i as usize).checked_sub(1).and_then(|i| prob.cases.get(i)))
+                    .map(|case| case.score)
+                    .sum(),
+                cases,
+            })
+            .collect();
+        SerdeProblem {
+            id: prob.id,
+            name: prob.name.to_string(),
+            ty: prob.ty.clone(),
+            time_limit: prob.cases.iter().map(|c| c.time_limit).max().unwrap_or(0),
+            memory_limit: prob.cases.iter().map(|c| c.memory_limit).max().unwrap_or(0),
+            case_count: prob.cases.len() as u32,
+            subtasks,
+            languages,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SerdeStatement {
+    pub id: u32,
+    pub name: String,
+    pub format: StatementFormat,
+    pub content: String,
+    pub images: Vec<String>,
+    pub samples: Vec<SerdeSample>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SerdeSample {
+    pub input: String,
+    pub answer: String,
+}
+
+/// Sample files are shown in full up to this many bytes.
+const SAMPLE_LIMIT: u64 = 64 * 1024;
+
+/// Read a sample file for display, truncated to `SAMPLE_LIMIT` bytes.
+pub fn read_sample(path: &str) -> String {
+    let mut buf = vec![];
+    if let Ok(file) = std::fs::File::open(path) {
+        let _ = file.take(SAMPLE_LIMIT).read_to_end(&mut buf);
+    }
+    String::from_utf8_lossy(&buf).to_string()
+}
+
+impl SerdeStatement {
+    pub fn new(data_root: &str, prob: &Problem) -> SerdeStatement {
+        let (format, content) = match &prob.statement {
+            Some(st) => (st.format.clone(), st.content.to_string()),
+            None => (StatementFormat::default(), String::new()),
+        };
+        let mut images: Vec<String> = match std::fs::read_dir(image_dir(data_root, prob.id)) {
+            Ok(dir) => dir
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect(),
+            Err(_) => vec![],
+        };
+        images.sort();
+        SerdeStatement {
+            id: prob.id,
+            name: prob.name.to_string(),
+            format,
+            content,
+            images,
+            samples: prob
+                .cases
+                .iter()
+                .filter(|case| case.sample)
+                .map(|case| SerdeSample {
+                    input: read_sample(&case.input_file),
+                    answer: read_sample(&case.answer_file),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Everything uploaded for problem `id` lives under `{data_root}/problem_{id}`,
+/// where `data_root` is `storage.problem_data_dir` in the config.
+pub fn problem_dir(data_root: &str, prob_id: u32) -> String {
+    format!("{}/problem_{}", data_root, prob_id)
+}
+
+pub fn data_dir(data_root: &str, prob_id: u32) -> String {
+    format!("{}/data", problem_dir(data_root, prob_id))
+}
+
+pub fn image_dir(data_root: &str, prob_id: u32) -> String {
+    format!("{}/images", problem_dir(data_root, prob_id))
+}
+
+/// Point case files at the uploaded test data. They name files inside the archive, so that
+/// setters cannot make samples or judging read other files of the server. Returns the
+/// file that is not.
+fn resolve_case_files(data_root: &str, prob: &mut Problem) -> Result<(), String> {
+    let dir = data_dir(data_root, prob.id);
+    let root = std::fs::canonicalize(&dir).ok();
+    for case in &mut prob.cases {
+        for file in [&mut case.input_file, &mut case.answer_file] {
+            // Files resolved before are given again by updates.
+            let name = Path::new(file.as_str())
+                .strip_prefix(&dir)
+                .unwrap_or_else(|_| Path::new(file.as_str()));
+            let inside = !name.as_os_str().is_empty()
+                && name
+                    .components()
+                    .all(|part| matches!(part, Component::Normal(_) | Component::CurDir));
+            let path = Path::new(&dir).join(name);
+            let escapes = match (&root, std::fs::canonicalize(&path)) {
+                (Some(root), Ok(real)) => !real.starts_with(root),
+                _ => false,
+            };
+            if !inside || escapes {
+                return Err(file.clone());
+            }
+            *file = path.to_string_lossy().to_string();
+        }
+    }
+    Ok(())
+}
+
+fn check_problem(prob: &Problem) -> Result<(), HttpResponse> {
+    let errors: Vec<String> = config::validate_problem(prob, "", false)
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .map(|issue| format!("{}: {}", issue.path.trim_start_matches('.'), issue.message))
+        .collect();
+    if !errors.is_empty() {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&errors.join("; ")));
+    }
+    Ok(())
+}
+
+/// Load the problems created through the API. They take precedence over `config.json`.
+pub async fn load_problems(
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<(), HttpResponse> {
+    let contents = match storage.list_problems().await {
+        Ok(contents) => contents,
+        _ => {
+            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+        }
+    };
+    let mut map = prob_map.lock().await;
+    for content in contents {
+        match serde_json::from_str::<Problem>(&content) {
+            Ok(prob) => {
+                map.insert(prob.id, prob);
+            }
+            Err(e) => {
+                return Err(error_log::INTERNAL::webmsg(&format!(
+                    "Broken problem in database: {}",
+                    e
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_problem(
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    prob_id: u32,
+) -> Result<Problem, HttpResponse> {
+    match prob_map.lock().await.get(&prob_id) {
+        Some(prob) => Ok(prob.clone()),
+        None => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Problem {} not found.",
+            prob_id
+        ))),
+    }
+}
+
+/// Problems that only appear in contests which have not started yet.
+pub async fn hidden_problems(storage: Data<dyn Storage>) -> Result<HashSet<u32>, HttpResponse> {
+    let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut hidden = HashSet::new();
+    let mut visible = HashSet::new();
+    for contest in contests::get_contests(storage).await? {
+        for prob_id in contest.problem_ids {
+            if time < contest.from {
+                hidden.insert(prob_id);
+            } else {
+                visible.insert(prob_id);
+            }
+        }
+    }
+    Ok(hidden.difference(&visible).copied().collect())
+}
+
+async fn save_problem(storage: Data<dyn Storage>, prob: &Problem) -> Result<(), HttpResponse> {
+    if let Err(e) = storage
+        .save_problem(prob.id, &serde_json::to_string(prob).unwrap())
+        .await
+    {
+        return Err(error_log::EXTERNAL::webmsg(&format!(
+            "Database Error: {}",
+            e
+        )));
+    }
+    Ok(())
+}
+
+pub async fn create_problem(
+    mut prob: Problem,
+    id: Option<u32>,
+    data_root: &str,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<Problem, HttpResponse> {
+    let mut map = prob_map.lock().await;
+    match id {
+        Some(id) => {
+            if map.contains_key(&id) {
+                return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+                    "Problem {} already exists.",
+                    id
+                )));
+            }
+            prob.id = id;
+        }
+        None => {
+            prob.id = match map.keys().max() {
+                Some(max) => max + 1,
+                None => 0,
+            };
+        }
+    }
+    log::debug!("Creating problem {}", prob.id);
+    check_problem(&prob)?;
+    if let Err(file) = resolve_case_files(data_root, &mut prob) {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+            "Case file '{}' must be a relative path inside the test data.",
+            file
+        )));
+    }
+    save_problem(storage, &prob).await?;
+    map.insert(prob.id, prob.clone());
+    Ok(prob)
+}
+
+pub async fn update_problem(
+    mut prob: Problem,
+    data_root: &str,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<Problem, HttpResponse> {
+    log::debug!("Updating problem {}", prob.id);
+    let mut map = prob_map.lock().await;
+    if !map.contains_key(&prob.id) {
+        return Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Problem {} not found.",
+            prob.id
+        )));
+    }
+    prob.owner_id = map[&prob.id].owner_id;
+    check_problem(&prob)?;
+    if let Err(file) = resolve_case_files(data_root, &mut prob) {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+            "Case file '{}' must be a relative path inside the test data.",
+            file
+        )));
+    }
+    save_problem(storage, &prob).await?;
+    map.insert(prob.id, prob.clone());
+    Ok(prob)
+}
+
+/// Admins may change every problem, setters only the ones they own.
+/// Missing problems pass, so that the caller reports them as not found.
+pub async fn check_owner(
+    caller: &Caller,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    prob_id: u32,
+) -> Result<(), HttpResponse> {
+    if caller.is_admin() {
+        return Ok(());
+    }
+    match prob_map.lock().await.get(&prob_id) {
+        Some(prob) if prob.owner_id != Some(caller.user.id) => Err(error_log::FORBIDDEN::webmsg(
+            &format!("Problem {} is not yours.", prob_id),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub async fn delete_problem(
+    prob_id: u32,
+    data_root: &str,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<Problem, HttpResponse> {
+    log::debug!("Deleting problem {}", prob_id);
+    let mut map = prob_map.lock().await;
+    if !map.contains_key(&prob_id) {
+        return Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Problem {} not found.",
+            prob_id
+        )));
+    }
+    for contest in contests::get_contests(storage.clone()).await? {
+        if contest.problem_ids.contains(&prob_id) {
+            return Err(error_log::INVALID_STATE::webmsg(&format!(
+                "Problem {} is used by contest {}.",
+                prob_id, contest.id
+            )));
+        }
+    }
+    match storage.delete_problem(prob_id).await {
+        Ok(false) => {
+            return Err(error_log::INVALID_STATE::webmsg(&format!(
+                "Problem {} is defined in the config file.",
+                prob_id
+            )));
+        }
+        Err(_) => {
+            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+        }
+        _ => {}
+    }
+    let _ = std::fs::remove_dir_all(problem_dir(data_root, prob_id));
+    Ok(map.remove(&prob_id).unwrap())
+}
+
+/// Replace the test data of a problem with the content of a zip archive.
+pub async fn upload_data(
+    prob_id: u32,
+    archive: &[u8],
+    data_root: &str,
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<Problem, HttpResponse> {
+    log::debug!("Uploading the data of problem {}", prob_id);
+    let prob = get_problem(prob_map.clone(), prob_id).await?;
+    let mut zip = match zip::ZipArchive::new(Cursor::new(archive)) {
+        Ok(z) => z,
+        Err(e) => {
+            return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+                "Invalid zip archive: {}",
+                e
+            )));
+        }
+    };
+    // The old data stays in place until the whole archive is extracted next to it.
+    let dir = data_dir(data_root, prob_id);
+    let stamp = Utc::now().format("%Y%m%d%H%M%S%f");
+    let staging = format!("{}.new-{}", dir, stamp);
+    if let Err(e) = extract(&mut zip, &staging) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(match e {
+            ExtractError::Archive(message) => error_log::INVALID_ARGUMENT::webmsg(&message),
+            ExtractError::Io(message) => error_log::INTERNAL::webmsg(&message),
+        });
+    }
+    let old = format!("{}.old-{}", dir, stamp);
+    let had_data = Path::new(&dir).exists();
+    if had_data {
+        if let Err(e) = std::fs::rename(&dir, &old) {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(error_log::INTERNAL::webmsg(&format!(
+                "Cannot replace the test data: {}",
+                e
+            )));
+        }
+    }
+    if let Err(e) = std::fs::rename(&staging, &dir) {
+        if had_data {
+            let _ = std::fs::rename(&old, &dir);
+        }
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(error_log::INTERNAL::webmsg(&format!(
+            "Cannot replace the test data: {}",
+            e
+        )));
+    }
+    let _ = std::fs::remove_dir_all(&old);
+    update_problem(prob, data_root, storage, prob_map).await
+}
+
+enum ExtractError {
+    /// The archive is broken or names a file outside of it.
+    Archive(String),
+    Io(String),
+}
+
+/// Extract every file of a zip archive into `dir`.
+fn extract(zip: &mut zip::ZipArchive<Cursor<&[u8]>>, dir: &str) -> Result<(), ExtractError> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| ExtractError::Io(format!("Cannot create {}: {}", dir, e)))?;
+    for i in 0..zip.len() {
+        let mut file = zip
+            .by_index(i)
+            .map_err(|e| ExtractError::Archive(format!("Invalid zip archive: {}", e)))?;
+        let name = match file.enclosed_name() {
+            Some(path) => path.to_owned(),
+            None => {
+                return Err(ExtractError::Archive(format!(
+                    "Invalid file name in zip archive: {}",
+                    file.name()
+                )));
+            }
+        };
+        let path = Path::new(dir).join(name);
+        if file.is_dir() {
+            let _ = std::fs::create_dir_all(&path);
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let res =
+            std::fs::File::create(&path).and_then(|mut out| std::io::copy(&mut file, &mut out));
+        if let Err(e) = res {
+            return Err(ExtractError::Io(format!(
+                "Cannot extract {}: {}",
+                file.name(),
+                e
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Image names end up in statement URLs, so only plain file names are accepted.
+fn check_image_name(name: &str) -> Result<(), HttpResponse> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+    {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+            "Invalid image name '{}'.",
+            name
+        )));
+    }
+    Ok(())
+}
+
+pub async fn upload_image(
+    prob_id: u32,
+    name: &str,
+    image: &[u8],
+    data_root: &str,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<(), HttpResponse> {
+    log::debug!("Uploading image {} of problem {}", name, prob_id);
+    get_problem(prob_map, prob_id).await?;
+    check_image_name(name)?;
+    let dir = image_dir(data_root, prob_id);
+    let _ = std::fs::create_dir_all(&dir);
+    if let Err(e) = std::fs::write(Path::new(&dir).join(name), image) {
+        return Err(error_log::INTERNAL::webmsg(&format!(
+            "Cannot save image {}: {}",
+            name, e
+        )));
+    }
+    Ok(())
+}
+
+pub async fn get_image(
+    prob_id: u32,
+    name: &str,
+    data_root: &str,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<Vec<u8>, HttpResponse> {
+    get_problem(prob_map, prob_id).await?;
+    check_image_name(name)?;
+    match std::fs::read(Path::new(&image_dir(data_root, prob_id)).join(name)) {
+        Ok(image) => Ok(image),
+        Err(_) => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Image {} not found.",
+            name
+        ))),
+    }
+}
+
+pub async fn delete_image(
+    prob_id: u32,
+    name: &str,
+    data_root: &str,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> Result<(), HttpResponse> {
+    get_problem(prob_map, prob_id).await?;
+    check_image_name(name)?;
+    match std::fs::remove_file(Path::new(&image_dir(data_root, prob_id)).join(name)) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Image {} not found.",
+            name
+        ))),
+    }
+}
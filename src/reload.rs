@@ -0,0 +1,124 @@
+use actix_web::web::Data;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::{self, Config, Overrides, Problem, Severity};
+use crate::problems;
+use crate::storage::Storage;
+
+/// Where the running config was read from, so that it can be read again.
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: String,
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReloadReport {
+    pub success: bool,
+    pub config_path: String,
+    pub problems: usize,
+    pub languages: usize,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Read the config file again and swap it in if it is valid.
+/// Jobs already running keep the problem and language they started with.
+pub async fn reload(
+    source: &ConfigSource,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    storage: Data<dyn Storage>,
+) -> ReloadReport {
+    log::info!("Reloading config from {}", source.path);
+    let mut report = ReloadReport {
+        config_path: source.path.to_string(),
+        ..ReloadReport::default()
+    };
+    let mut new_config = match config::parse_from_file(source.path.to_string())
+        .and_then(|mut c| source.overrides.apply(&mut c).map(|_| c))
+    {
+        Ok(c) => c,
+        Err(issue) => {
+            report.errors.push(issue.to_string());
+            return finish(report);
+        }
+    };
+    for issue in config::validate(&new_config) {
+        match issue.severity {
+            Severity::Error => report.errors.push(issue.to_string()),
+            Severity::Warning => report.warnings.push(issue.to_string()),
+        }
+    }
+    if !report.errors.is_empty() {
+        return finish(report);
+    }
+
+    // Problems created through the API still take precedence over the config file. They are
+    // read with `prob_map` locked, like the API changes them, so that none is lost in between.
+    let mut config = config.lock().await;
+    let mut prob_map = prob_map.lock().await;
+    let mut map = HashMap::new();
+    for prob in &new_config.problems {
+        map.insert(prob.id, prob.clone());
+    }
+    let new_map = Data::new(Arc::new(Mutex::new(map)));
+    if problems::load_problems(storage, new_map.clone())
+        .await
+        .is_err()
+    {
+        report
+            .errors
+            .push("error: cannot load problems from database".to_string());
+        return finish(report);
+    }
+    let new_map = new_map.lock().await.clone();
+
+    if config.server.bind_address != new_config.server.bind_address
+        || config.server.bind_port != new_config.server.bind_port
+    {
+        report
+            .warnings
+            .push("warning: server: bind address changes need a restart".to_string());
+        new_config.server = config.server.clone();
+    }
+    if config.storage.backend != new_config.storage.backend
+        || config.storage.database_path != new_config.storage.database_path
+        || config.storage.database_url != new_config.storage.database_url
+    {
+        report
+            .warnings
+            .push("warning: storage: database changes need a restart".to_string());
+        new_config.storage.backend = config.storage.backend;
+        new_config.storage.database_path = config.storage.database_path.clone();
+        new_config.storage.database_url = config.storage.database_url.clone();
+    }
+    report.problems = new_map.len();
+    report.languages = new_config.languages.len();
+    *config = new_config;
+    *prob_map = new_map;
+    report.success = true;
+    finish(report)
+}
+
+fn finish(report: ReloadReport) -> ReloadReport {
+    for warning in &report.warnings {
+        log::warn!("{}", warning);
+    }
+    for error in &report.errors {
+        log::error!("{}", error);
+    }
+    if report.success {
+        log::info!(
+            "Config reloaded: {} problem(s), {} language(s)",
+            report.problems,
+            report.languages
+        );
+    } else {
+        log::error!("Config reload failed, keeping the running config");
+    }
+    report
+}
@@ -1,27 +1,27 @@
 use actix_web::web::Data;
 use actix_web::{web, HttpResponse};
 use chrono::prelude::*;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, Result};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::io::Write;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use wait_timeout::ChildExt;
 
-use crate::config::{self, Config, Ids, ProbType, Problem};
+use crate::auth::Caller;
+use crate::config::{self, Config, ProbType, Problem};
 use crate::handler::jobs::{JobsFilter, PostJob};
-use crate::{error_log, users};
+use crate::storage::{CaseUpdate, JobUpdate, Storage};
+use crate::users::Role;
+use crate::{error_log, problems, users};
 
 mod diff;
+pub mod retention;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SerdeJob {
     pub id: u32,
     pub created_time: String,
@@ -30,10 +30,19 @@ pub struct SerdeJob {
     pub state: String,
     pub result: String,
     pub score: f32,
-    cases: Vec<SerdeCase>,
+    pub cases: Vec<SerdeCase>,
 }
 
 impl SerdeJob {
+    /// Drop the source code and the case details, leaving the verdicts.
+    pub fn redact(&mut self) {
+        self.submission.source_code.clear();
+        for case in &mut self.cases {
+            case.info.clear();
+            case.sample = None;
+        }
+    }
+
     pub fn get_post(&self) -> PostJob {
         return PostJob {
             source_code: self.submission.source_code.to_string(),
@@ -41,26 +50,40 @@ impl SerdeJob {
             user_id: self.submission.user_id,
             contest_id: self.submission.contest_id,
             problem_id: self.submission.problem_id,
+            team_id: self.submission.team_id,
         };
     }
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct SerdeSubmission {
     pub source_code: String,
     pub language: String,
     pub user_id: u32,
     pub contest_id: u32,
     pub problem_id: u32,
+    /// The team the job was submitted for, if it was made in a team contest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<u32>,
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
-struct SerdeCase {
-    id: u32,
-    result: String,
-    time: u32,
-    memory: u32,
-    info: String,
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct SerdeCase {
+    pub id: u32,
+    pub result: String,
+    pub time: u32,
+    pub memory: u32,
+    pub info: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SerdeSampleResult>,
+}
+
+/// Full input, expected and actual output, only shown for sample cases.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct SerdeSampleResult {
+    pub input: String,
+    pub answer: String,
+    pub output: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -91,368 +114,311 @@ impl Job {
     }
 }
 
-pub async fn job_exists(pool: Data<Mutex<Pool<SqliteConnectionManager>>>, job_id: u32) -> bool {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt = data
-        .prepare(&format!("SELECT * FROM jobs WHERE id = {};", job_id))
-        .expect("Database Error.");
-    match stmt.exists([]) {
-        Ok(true) => true,
-        _ => false,
-    }
-}
-
-pub async fn get_submission(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    job_id: u32,
-) -> Result<SerdeSubmission, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut sub_stmt;
-    match data.prepare("SELECT * FROM submission WHERE id = :id;") {
-        Ok(stmt) => sub_stmt = stmt,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
-        }
-    }
-    if !sub_stmt
-        .exists(&[(":id", job_id.to_string().as_str())])
-        .unwrap()
-    {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
+pub async fn get_a_job(storage: Data<dyn Storage>, job_id: u32) -> Result<SerdeJob, HttpResponse> {
+    match storage.get_job(job_id).await {
+        Ok(Some(job)) => Ok(job),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
             "Job {} not found.",
             job_id
-        )));
-    }
-    let sub_iter = sub_stmt.query_map(&[(":id", job_id.to_string().as_str())], |row| {
-        Ok(SerdeSubmission {
-            source_code: row.get(1)?,
-            language: row.get(2)?,
-            user_id: row.get(3)?,
-            contest_id: row.get(4)?,
-            problem_id: row.get(5)?,
-        })
-    });
-    match sub_iter {
-        Ok(mut ans) => Ok(ans.next().unwrap().expect("Unknown Error.")),
+        ))),
         _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
 }
 
-pub async fn get_a_job(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    job_id: u32,
-) -> Result<SerdeJob, HttpResponse> {
-    // get submission
-    let submission;
-    match get_submission(pool.clone(), job_id).await {
-        Ok(sub) => {
-            submission = sub;
-        }
-        Err(e) => {
-            return Err(e);
-        }
+pub async fn get_job(storage: Data<dyn Storage>, job_id: u32) -> HttpResponse {
+    match get_a_job(storage, job_id).await {
+        Ok(job) => HttpResponse::Ok().body(serde_json::to_string_pretty(&job).unwrap()),
+        Err(e) => e,
     }
+}
 
-    //get cases
-    let data = pool.lock().await.get().unwrap();
-    let mut cases_stmt;
-    match data.prepare("SELECT * FROM cases WHERE jobid = :id ORDER BY caseid;") {
-        Ok(stmt) => cases_stmt = stmt,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+/// Admins, the submitter, their teammates and the owner of the problem see the source and
+/// case details of a job. `caller_teams` are the ids of the caller's teams.
+pub fn can_see_details(
+    caller: &Caller,
+    caller_teams: &[u32],
+    job: &SerdeJob,
+    prob: Option<&Problem>,
+) -> bool {
+    caller.is_admin()
+        || caller.user.id == job.submission.user_id
+        || job
+            .submission
+            .team_id
+            .is_some_and(|team_id| caller_teams.contains(&team_id))
+        || (caller.user.role == Role::Setter
+            && prob.is_some_and(|prob| prob.owner_id == Some(caller.user.id)))
+}
+
+/// Like `get_job`, but also shows the data of the sample cases. Jobs the caller may not
+/// see the details of are redacted.
+pub async fn get_job_with_samples(
+    caller: &Caller,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    job_id: u32,
+) -> HttpResponse {
+    let mut job;
+    match get_a_job(storage.clone(), job_id).await {
+        Ok(j) => job = j,
+        Err(e) => {
+            return e;
         }
     }
-    if !cases_stmt
-        .exists(&[(":id", job_id.to_string().as_str())])
-        .unwrap()
-    {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
-            "Job {} not found.",
-            job_id
-        )));
-    }
-    let cases_iter = cases_stmt
-        .query_map(&[(":id", job_id.to_string().as_str())], |row| {
-            Ok(SerdeCase {
-                id: row.get(1)?,
-                result: row.get(2)?,
-                time: row.get(3)?,
-                memory: row.get(4)?,
-                info: row.get(5)?,
-            })
-        })
-        .expect("Unknown Error.");
-    let mut cases: Vec<SerdeCase> = vec![];
-    for case in cases_iter {
-        cases.push(case.unwrap());
-    }
-
-    // get job
-    let mut job_stmt;
-    match data.prepare("SELECT * FROM jobs WHERE id = :id;") {
-        Ok(stmt) => job_stmt = stmt,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    let runtime_dir = config.lock().await.runtime.runtime_dir.to_string();
+    if let Some(prob) = prob_map.lock().await.get(&job.submission.problem_id) {
+        for case in &mut job.cases {
+            if case.id == 0 {
+                continue;
+            }
+            if let Some(prob_case) = prob.cases.get(case.id as usize - 1) {
+                if prob_case.sample {
+                    case.sample = Some(SerdeSampleResult {
+                        input: problems::read_sample(&prob_case.input_file),
+                        answer: problems::read_sample(&prob_case.answer_file),
+                        output: problems::read_sample(&format!(
+                            "{}/job_{}/{}.out",
+                            runtime_dir, job_id, case.id
+                        )),
+                    });
+                }
+            }
         }
     }
-    if !job_stmt
-        .exists(&[(":id", job_id.to_string().as_str())])
-        .unwrap()
-    {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
-            "Job {} not found.",
-            job_id
-        )));
+    let caller_teams = storage
+        .list_user_teams(caller.user.id)
+        .await
+        .unwrap_or_default();
+    if !can_see_details(
+        caller,
+        &caller_teams,
+        &job,
+        prob_map.lock().await.get(&job.submission.problem_id),
+    ) {
+        job.redact();
     }
-    let mut job_iter = job_stmt
-        .query_map(&[(":id", job_id.to_string().as_str())], |row| {
-            Ok(SerdeJob {
-                id: row.get(0)?,
-                created_time: row.get(1)?,
-                updated_time: row.get(2)?,
-                submission: SerdeSubmission {
-                    ..SerdeSubmission::default()
-                },
-                state: row.get(4)?,
-                result: row.get(5)?,
-                score: row.get(6)?,
-                cases: vec![],
-            })
-        })
-        .expect("Unknown Error.");
-    let mut job: SerdeJob = job_iter.next().unwrap().expect("Unknown Error.");
-    job.submission = submission;
-    job.cases = cases;
-
-    println!("Job: {:?}", job);
-
-    Ok(job)
+    HttpResponse::Ok().body(serde_json::to_string_pretty(&job).unwrap())
 }
 
-pub async fn get_job(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    job_id: u32,
-) -> HttpResponse {
-    match get_a_job(pool.clone(), job_id).await {
-        Ok(job) => HttpResponse::Ok().body(serde_json::to_string_pretty(&job).unwrap()),
-        Err(e) => e,
-    }
-}
+/// Most jobs returned in one page.
+pub const MAX_PAGE_SIZE: u32 = 1000;
 
-pub async fn get_jobs(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    mut filter: JobsFilter,
-    ids: Data<Arc<Mutex<Ids>>>,
-) -> Result<Vec<SerdeJob>, HttpResponse> {
-    let mut ans: Vec<SerdeJob> = vec![];
-    if let Some(name) = filter.user_name {
-        if let Ok(id) = users::get_user_id(pool.clone(), &name).await {
+/// Resolve `user_name` into `user_id`. Returns false if the filter can match nothing.
+async fn resolve_user(storage: Data<dyn Storage>, filter: &mut JobsFilter) -> bool {
+    if let Some(name) = filter.user_name.take() {
+        if let Ok(id) = users::get_user_id(storage, &name).await {
             if let Some(user_id) = filter.user_id {
                 if id != user_id {
-                    return Err(HttpResponse::Ok().body("[]"));
+                    return false;
                 }
             } else {
                 filter.user_id = Some(id);
             }
         }
     }
-    let tot = ids.lock().await.jobsid as i32;
-    for id in 0..tot {
-        let job = get_a_job(pool.clone(), id as u32)
-            .await
-            .expect("Get Job Error.");
-        if let Some(user_id) = filter.user_id {
-            if job.submission.user_id != user_id {
-                continue;
-            }
-        }
-        //TODO: user_name
-        if let Some(contest_id) = filter.contest_id {
-            if job.submission.contest_id != contest_id {
-                continue;
-            }
-        }
-        if let Some(problem_id) = filter.problem_id {
-            if job.submission.problem_id != problem_id {
-                continue;
-            }
-        }
-        if let Some(language) = &filter.language {
-            println!("{}", language);
-            if !job.submission.language.eq(language) {
-                continue;
-            }
-        }
-        if let Some(from) = &filter.from {
-            if job.created_time.cmp(from) == Ordering::Less {
-                continue;
-            }
-        }
-        if let Some(to) = &filter.to {
-            if job.created_time.cmp(to) == Ordering::Greater {
-                continue;
-            }
-        }
-        if let Some(state) = &filter.state {
-            if !job.state.eq(state) {
-                continue;
-            }
-        }
-        if let Some(result) = &filter.result {
-            if !job.result.eq(result) {
-                continue;
-            }
+    true
+}
+
+pub async fn count_jobs(
+    storage: Data<dyn Storage>,
+    mut filter: JobsFilter,
+) -> Result<u32, HttpResponse> {
+    if !resolve_user(storage.clone(), &mut filter).await {
+        return Ok(0);
+    }
+    match storage.count_jobs(&filter).await {
+        Ok(count) => Ok(count),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+/// Jobs matching the filter, and the cursor of the next page if there is one.
+pub async fn get_jobs(
+    storage: Data<dyn Storage>,
+    mut filter: JobsFilter,
+) -> Result<(Vec<SerdeJob>, Option<u32>), HttpResponse> {
+    if !resolve_user(storage.clone(), &mut filter).await {
+        return Ok((vec![], None));
+    }
+    // One extra job tells whether there is a next page.
+    let limit = filter.limit.map(|l| l.min(MAX_PAGE_SIZE));
+    filter.limit = limit.map(|l| l + 1);
+    let mut jobs = match storage.list_jobs(&filter).await {
+        Ok(jobs) => jobs,
+        _ => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    let mut next = None;
+    if let Some(limit) = limit {
+        if jobs.len() > limit as usize {
+            jobs.truncate(limit as usize);
+            next = jobs.last().map(|job| job.id);
         }
-        ans.push(job);
     }
-    Ok(ans)
+    Ok((jobs, next))
 }
 
 pub async fn reset_job(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
     job_id: u32,
-    prob_map: Data<HashMap<u32, config::Problem>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
 ) -> Result<(), HttpResponse> {
-    if job_exists(pool.clone(), job_id).await {
-        let data = pool.lock().await.get().unwrap();
-        let mut stmt = data
-            .prepare(&format!("SELECT * FROM jobs WHERE id = {};", job_id))
-            .expect("Database Error.");
-        if !stmt
-            .query([])
-            .unwrap()
-            .next()
-            .unwrap()
-            .unwrap()
-            .get::<_, String>(4)
-            .unwrap()
-            .eq("Finished")
-        {
-            return Err(error_log::INVALID_STATE::webmsg(&format!(
-                "Job {} not finished.",
-                job_id
-            )));
-        }
-        drop(stmt);
-        drop(data);
-    } else {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
-            "Job {} not found.",
+    let job = get_a_job(storage.clone(), job_id).await?;
+    if !job.state.eq("Finished") {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "Job {} not finished.",
             job_id
         )));
     }
-
-    let data = pool.lock().await.get().unwrap();
-    let prob_id;
-    match get_submission(pool.clone(), job_id).await {
-        Ok(sub) => {
-            prob_id = sub.problem_id;
-        }
-        Err(e) => {
-            return Err(e);
-        }
+    let prob_id = job.submission.problem_id;
+    if !prob_map.lock().await.contains_key(&prob_id) {
+        return Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Problem {} not found.",
+            prob_id
+        )));
     }
 
-    let top = prob_map.get(&prob_id).unwrap().cases.len();
-
     let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
-    let _ = data.execute(
-        "UPDATE jobs SET updated_time = ?1 WHERE id = ?2",
-        params![time.to_string(), job_id],
-    );
-    let _ = data.execute(
-        "UPDATE jobs SET state = 'Queueing' WHERE id = ?1",
-        params![job_id],
-    );
-    let _ = data.execute(
-        "UPDATE jobs SET result = 'Waiting' WHERE id = ?1",
-        params![job_id],
-    );
-    let _ = data.execute("UPDATE jobs SET score = 0.0 WHERE id = ?1", params![job_id]);
-
-    for i in 0..top {
-        let _ = data.execute(
-            "UPDATE cases SET result = 'Waiting' WHERE jobid = ?1 AND caseid = ?2",
-            params![job_id, i as i32],
-        );
-        let _ = data.execute(
-            "UPDATE cases SET time = 0 WHERE jobid = ?1 AND caseid = ?2",
-            params![job_id, i as i32],
-        );
-        let _ = data.execute(
-            "UPDATE cases SET memory = 0 WHERE jobid = ?1 AND caseid = ?2",
-            params![job_id, i as i32],
-        );
-        let _ = data.execute(
-            "UPDATE cases SET info = '' WHERE jobid = ?1 AND caseid = ?2",
-            params![job_id, i as i32],
-        );
+    if storage.reset_job(job_id, &time.to_string()).await.is_err() {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
     }
-
     Ok(())
 }
 
-async fn create_task(
-    body: web::Json<PostJob>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    prob_map_shared: Data<HashMap<u32, Problem>>,
-    ids: Data<Arc<Mutex<Ids>>>,
-) -> (HttpResponse, u32) {
-    println!("Runner: Creating Job...");
+/// A job to judge, with copies of its problem and language taken when it is submitted or
+/// rejudged. Edits and config reloads after that only apply to later jobs.
+pub struct Task {
+    pub body: PostJob,
+    pub prob: Problem,
+    pub lang: config::Language,
+}
 
-    let data = pool.lock().await.get().unwrap();
+impl Task {
+    pub async fn new(
+        body: PostJob,
+        config: &Data<Arc<Mutex<Config>>>,
+        prob_map: &Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    ) -> Result<Task, HttpResponse> {
+        let prob = match prob_map.lock().await.get(&body.problem_id) {
+            Some(prob) => prob.clone(),
+            None => {
+                return Err(error_log::NOT_FOUND::webmsg(&format!(
+                    "Problem {} not found.",
+                    body.problem_id
+                )))
+            }
+        };
+        let lang = match config
+            .lock()
+            .await
+            .languages
+            .iter()
+            .find(|language| language.name == body.language)
+        {
+            Some(lang) => lang.clone(),
+            None => {
+                return Err(error_log::NOT_FOUND::webmsg(&format!(
+                    "Language {} not found.",
+                    body.language
+                )))
+            }
+        };
+        Ok(Task { body, prob, lang })
+    }
+}
 
-    let job_id: u32 = ids.lock().await.jobsid;
-    ids.lock().await.jobsid += 1;
-    println!("Job ID: {}", job_id);
+async fn create_task(
+    task: &Task,
+    storage: Data<dyn Storage>,
+) -> Result<(HttpResponse, u32), HttpResponse> {
+    println!("Runner: Creating Job...");
 
-    match data.execute("INSERT INTO submission (id, source_code, language, user_id, contest_id, problem_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)", 
-        params![job_id as i32, body.source_code, body.language, body.user_id as i32, body.contest_id as i32, body.problem_id as i32]) {
-            Err(_) => { return (error_log::EXTERNAL::webmsg("Database Error."), job_id); },
-            _ => {},
-        };
-    let prob = prob_map_shared.get(&body.problem_id).unwrap();
+    let (body, prob) = (&task.body, &task.prob);
+    let cur = Job::new(0, 0);
+    let mut job = SerdeJob {
+        id: 0,
+        created_time: cur.created_time,
+        updated_time: cur.updated_time,
+        submission: SerdeSubmission {
+            source_code: body.source_code.to_string(),
+            language: body.language.to_string(),
+            user_id: body.user_id,
+            contest_id: body.contest_id,
+            problem_id: body.problem_id,
+            team_id: body.team_id,
+        },
+        state: cur.state,
+        result: cur.result,
+        score: 0.0,
+        cases: vec![],
+    };
     for index in 0..=prob.cases.len() {
-        match data.execute("INSERT INTO cases (jobid, caseid, result, time, memory, info) VALUES (?1, ?2, 'Waiting', 0, 0, '');", 
-            params![job_id, index]) {
-            Err(_) => { return (error_log::EXTERNAL::webmsg("Database Error."), job_id); }
-            _ => {},
-        };
+        job.cases.push(SerdeCase {
+            id: index as u32,
+            result: "Waiting".to_string(),
+            ..SerdeCase::default()
+        });
     }
-    let mut cur = Job::new(job_id, job_id);
-    for i in 0..=prob.cases.len() {
-        cur.cases.push(format!("{}-{}", job_id, i).to_string());
+    let job_id = match storage.insert_job(&job).await {
+        Ok(id) => id,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    println!("Job ID: {}", job_id);
+    Ok((get_job(storage, job_id).await, job_id))
+}
+
+/// Limit the address space of a compiler, so that it fails instead of exhausting the host.
+#[cfg(unix)]
+fn limit_memory(command: &mut Command, bytes: u64) {
+    use std::os::unix::process::CommandExt;
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
     }
-    match data.execute("INSERT INTO jobs (id, created_time, updated_time, submission_id, state, result, score, cases) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
-        params![job_id as i32, cur.created_time, cur.updated_time, cur.submission as i32, cur.state, cur.result, 0.0 as f32, cur.cases.len() as i32 - 1]) {
-            Err(_) => { return (error_log::EXTERNAL::webmsg("Database Error."), job_id); },
-            _ => {},
-        }
-    (get_job(pool, job_id).await, job_id)
 }
 
+#[cfg(not(unix))]
+fn limit_memory(_command: &mut Command, _bytes: u64) {
+    log::warn!("compile_memory_limit is only supported on unix");
+}
+
+/// Judge a job, then apply the retention policy to its runtime directory.
 pub async fn run(
-    body: PostJob,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    config: Data<Config>,
-    prob_map: Data<HashMap<u32, Problem>>,
+    task: Task,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
     job_id: u32,
 ) {
-    let _ = std::fs::create_dir("oj_runtime_dir");
-    let _ = std::fs::remove_dir_all(format!("oj_runtime_dir/job_{}", job_id));
-    let _ = std::fs::create_dir(format!("oj_runtime_dir/job_{}", job_id));
-    let path = format!("oj_runtime_dir/job_{}", job_id).to_string();
-    let mut lang = config::Language {
-        ..config::Language::default()
-    };
-    for language in &config.languages {
-        if language.name.eq(&body.language) {
-            lang.name = language.name.to_string();
-            lang.file_name = language.file_name.to_string();
-            lang.command = language.command.iter().map(|s| s.to_string()).collect();
-            break;
-        }
-    }
+    let prob = task.prob.clone();
+    judge(task, storage.clone(), config.clone(), job_id).await;
+    retention::after_judging(storage, config, &prob, job_id).await;
+}
+
+async fn judge(
+    task: Task,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    job_id: u32,
+) {
+    let Task {
+        body,
+        prob,
+        mut lang,
+    } = task;
+    let runtime_dir = config.lock().await.runtime.runtime_dir.to_string();
+    let _ = std::fs::create_dir_all(&runtime_dir);
+    let _ = std::fs::remove_dir_all(format!("{}/job_{}", runtime_dir, job_id));
+    let _ = std::fs::create_dir(format!("{}/job_{}", runtime_dir, job_id));
+    let path = format!("{}/job_{}", runtime_dir, job_id).to_string();
+    let compile_timeout = config.lock().await.runtime.compile_timeout;
     println!("Language: {:?}", lang);
     let mut file =
         std::fs::File::create(format!("{}/{}", path, lang.file_name)).expect("Cannot create file.");
@@ -480,69 +446,64 @@ pub async fn run(
     }
 
     // Start compiling
-    let data = pool.lock().await.get().unwrap();
-    let _ = data.execute(
-        "UPDATE jobs SET state = 'Running' WHERE id = ?1;",
-        params![job_id as i32],
-    );
-    let _ = data.execute(
-        "UPDATE jobs SET result = 'Running' WHERE id = ?1;",
-        params![job_id as i32],
-    );
-    let _ = data.execute(
-        "UPDATE cases SET result = 'Running' WHERE jobid = ?1 AND caseid = ?2;",
-        params![job_id as i32, 0],
-    );
-    drop(data);
-    let mut compiler = Command::new(&lang.command[0])
+    let update = JobUpdate {
+        state: Some("Running".to_string()),
+        ..JobUpdate::result("Running")
+    };
+    let _ = storage.update_job(job_id, &update).await;
+    let _ = storage
+        .update_case(job_id, 0, &CaseUpdate::result("Running"))
+        .await;
+    let mut compiler = Command::new(&lang.command[0]);
+    compiler
         .args(&lang.command[1..])
         .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .unwrap();
-    let wait_time = Duration::from_secs(15); //compiling for at most 15 seconds
-    let status_code = match compiler.wait_timeout(wait_time).unwrap() {
+        .stderr(Stdio::null());
+    if let Some(limit) = lang.compile_memory_limit {
+        limit_memory(&mut compiler, limit);
+    }
+    let compiler = compiler.spawn().unwrap();
+    let wait_time = Duration::from_secs(lang.compile_timeout.unwrap_or(compile_timeout));
+    let mut compile_info = "";
+    let (mut compiler, status) = wait_child(compiler, wait_time).await;
+    let status_code = match status {
         Some(status) => status.code(),
         None => {
+            compile_info = "Compilation timed out";
             compiler.kill().unwrap();
             compiler.wait().unwrap().code()
         }
     };
 
     //Compilation finished
-    let data = pool.lock().await.get().unwrap();
     match status_code {
         Some(0) => {
             //Compilation Success
-            let _ = data.execute(
-                "UPDATE cases SET result = 'Compilation Success' WHERE jobid = ?1 AND caseid = ?2;",
-                params![job_id as i32, 0],
-            );
+            let _ = storage
+                .update_case(job_id, 0, &CaseUpdate::result("Compilation Success"))
+                .await;
         }
         _ => {
-            let _ = data.execute(
-                "UPDATE jobs SET state = 'Finished' WHERE id = ?1;",
-                params![job_id as i32],
-            );
-            let _ = data.execute(
-                "UPDATE jobs SET result = 'Compilation Error' WHERE id = ?1;",
-                params![job_id as i32],
-            );
-            let _ = data.execute(
-                "UPDATE cases SET result = 'Compilation Error' WHERE jobid = ?1 AND caseid = ?2;",
-                params![job_id as i32, 0],
-            );
+            let update = JobUpdate {
+                state: Some("Finished".to_string()),
+                ..JobUpdate::result("Compilation Error")
+            };
+            let _ = storage.update_job(job_id, &update).await;
+            let update = CaseUpdate {
+                info: Some(compile_info.to_string()),
+                ..CaseUpdate::result("Compilation Error")
+            };
+            let _ = storage.update_case(job_id, 0, &update).await;
             return;
         }
     }
-    drop(data);
 
     // Running Cases Part
-    let cases = &prob_map.get(&body.problem_id).unwrap().cases;
+    let cases = &prob.cases;
     let mut score: f32 = 0.0;
     let mut flag: bool = true;
     let mut indexes: Vec<Vec<u32>> = vec![];
-    match &prob_map.get(&body.problem_id).unwrap().misc.packing {
+    match &prob.misc.packing {
         Some(pack) => {
             for index in pack {
                 let mut tmp_vec = vec![];
@@ -568,29 +529,23 @@ pub async fn run(
             let index = *index_tmp as i32 + 1;
             // Check if skipped
             if skip_flag {
-                let data = pool.lock().await.get().unwrap();
-                let _ = data.execute(
-                    "UPDATE cases SET result = 'Skipped' WHERE jobid = ?1 AND caseid = ?2;",
-                    params![job_id as i32, index as i32],
-                );
-                drop(data);
+                let _ = storage
+                    .update_case(job_id, index as u32, &CaseUpdate::result("Skipped"))
+                    .await;
                 continue;
             } else {
                 skip_flag = true;
             }
 
             // Update database
-            let data = pool.lock().await.get().unwrap();
-            let _ = data.execute(
-                "UPDATE cases SET result = 'Running' WHERE jobid = ?1 AND caseid = ?2;",
-                params![job_id as i32, index as i32],
-            );
-            drop(data);
+            let _ = storage
+                .update_case(job_id, index as u32, &CaseUpdate::result("Running"))
+                .await;
 
             // Running
             let out_file = format!("{}/{}.out", path, index).to_string();
             let now = Instant::now();
-            let mut runner = Command::new(&bin_path)
+            let runner = Command::new(&bin_path)
                 .stdin(Stdio::from(std::fs::File::open(&case.input_file).unwrap()))
                 .stdout(Stdio::from(std::fs::File::create(&out_file).unwrap()))
                 .stderr(Stdio::null())
@@ -598,20 +553,19 @@ pub async fn run(
                 .unwrap();
             let wait_time = Duration::from_micros(case.time_limit);
             let mut real_time: u128 = 0;
-            match runner.wait_timeout(wait_time).unwrap() {
+            match wait_child(runner, wait_time).await.1 {
                 Some(status) => {
                     if status.code().unwrap() != 0 {
                         //Runtime Error
-                        let data = pool.lock().await.get().unwrap();
-                        let _ = data.execute("UPDATE cases SET result = 'Runtime Error' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
+                        let _ = storage
+                            .update_case(job_id, index as u32, &CaseUpdate::result("Runtime Error"))
+                            .await;
                         if flag {
-                            let _ = data.execute(
-                                "UPDATE jobs SET result = 'Runtime Error' WHERE id = ?1;",
-                                params![job_id as i32],
-                            );
+                            let _ = storage
+                                .update_job(job_id, &JobUpdate::result("Runtime Error"))
+                                .await;
                             flag = false;
                         }
-                        drop(data);
                         continue;
                     } else {
                         //Exited Normally
@@ -621,39 +575,37 @@ pub async fn run(
                 None => {
                     //Time Limit Exceeded
                     real_time = now.elapsed().as_micros();
-                    let data = pool.lock().await.get().unwrap();
-                    let _ = data.execute("UPDATE cases SET result = 'Time Limit Exceeded' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
-                    let _ = data.execute(
-                        "UPDATE cases SET time = ?1 WHERE jobid = ?2 AND caseid = ?3;",
-                        params![real_time as i32, job_id as i32, index as i32],
-                    );
+                    let update = CaseUpdate {
+                        time: Some(real_time as u32),
+                        ..CaseUpdate::result("Time Limit Exceeded")
+                    };
+                    let _ = storage.update_case(job_id, index as u32, &update).await;
                     if flag {
-                        let _ = data.execute(
-                            "UPDATE jobs SET result = 'Time Limit Exceeded' WHERE id = ?1;",
-                            params![job_id as i32],
-                        );
+                        let _ = storage
+                            .update_job(job_id, &JobUpdate::result("Time Limit Exceeded"))
+                            .await;
                         flag = false;
                     }
-                    drop(data);
                     continue;
                 }
             };
 
             // Exited Normally
-            let diff_code = match prob_map.get(&body.problem_id).unwrap().ty {
+            let diff_code = match prob.ty {
                 ProbType::standard => diff::diff_standard(&case.answer_file, &out_file),
                 ProbType::strict => diff::diff_strict(&case.answer_file, &out_file),
                 ProbType::spj => {
                     let mut spj_info: Vec<String> = vec![];
-                    match &prob_map.get(&body.problem_id).unwrap().misc.special_judge {
+                    match &prob.misc.special_judge {
                         Some(info) => {
                             spj_info = info.to_vec();
                         }
                         None => {
-                            let data = pool.lock().await.get().unwrap();
-                            let _ = data.execute("UPDATE cases SET result = 'SPJ Error' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
-                            let _ = data.execute("UPDATE cases SET info = 'No SPJ specified in config: misc' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
-                            drop(data);
+                            let update = CaseUpdate {
+                                info: Some("No SPJ specified in config: misc".to_string()),
+                                ..CaseUpdate::result("SPJ Error")
+                            };
+                            let _ = storage.update_case(job_id, index as u32, &update).await;
                             continue;
                         }
                     }
@@ -669,18 +621,18 @@ pub async fn run(
                     match diff::diff_spj(&spj_info).await {
                         Ok(info) => {
                             ans = info.0;
-                            let data = pool.lock().await.get().unwrap();
-                            let _ = data.execute(
-                                "UPDATE cases SET info = ?1 WHERE jobid = ?2 AND caseid = ?3;",
-                                params![info.1, job_id as i32, index as i32],
-                            );
-                            drop(data);
+                            let update = CaseUpdate {
+                                info: Some(info.1),
+                                ..CaseUpdate::default()
+                            };
+                            let _ = storage.update_case(job_id, index as u32, &update).await;
                         }
                         Err(_) => {
-                            let data = pool.lock().await.get().unwrap();
-                            let _ = data.execute("UPDATE cases SET result = 'SPJ Error' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
-                            let _ = data.execute("UPDATE cases SET info = 'No SPJ specified in config: misc' WHERE jobid = ?1 AND caseid = ?2;", params![job_id as i32, index as i32]);
-                            drop(data);
+                            let update = CaseUpdate {
+                                info: Some("No SPJ specified in config: misc".to_string()),
+                                ..CaseUpdate::result("SPJ Error")
+                            };
+                            let _ = storage.update_case(job_id, index as u32, &update).await;
                             continue;
                         }
                     };
@@ -691,85 +643,71 @@ pub async fn run(
             if diff_code == 0 {
                 // Accepted
                 pack_score += case.score;
-                let data = pool.lock().await.get().unwrap();
-                let _ = data.execute(
-                    "UPDATE cases SET result = 'Accepted' WHERE jobid = ?1 AND caseid = ?2;",
-                    params![job_id as i32, index as i32],
-                );
-                let _ = data.execute(
-                    "UPDATE cases SET time = ?1 WHERE jobid = ?2 AND caseid = ?3;",
-                    params![real_time as i32, job_id as i32, index as i32],
-                );
-                drop(data);
+                let update = CaseUpdate {
+                    time: Some(real_time as u32),
+                    ..CaseUpdate::result("Accepted")
+                };
+                let _ = storage.update_case(job_id, index as u32, &update).await;
                 skip_flag = false;
             } else {
                 // Wrong Answer
-                let data = pool.lock().await.get().unwrap();
-                let _ = data.execute(
-                    "UPDATE cases SET result = 'Wrong Answer' WHERE jobid = ?1 AND caseid = ?2;",
-                    params![job_id as i32, index as i32],
-                );
+                let _ = storage
+                    .update_case(job_id, index as u32, &CaseUpdate::result("Wrong Answer"))
+                    .await;
                 if flag {
-                    let _ = data.execute(
-                        "UPDATE jobs SET result = 'Wrong Answer' WHERE id = ?1;",
-                        params![job_id as i32],
-                    );
+                    let _ = storage
+                        .update_job(job_id, &JobUpdate::result("Wrong Answer"))
+                        .await;
                     flag = false;
                 }
-                drop(data);
             }
         }
         if !skip_flag {
             score += pack_score;
-            let data = pool.lock().await.get().unwrap();
-            let _ = data.execute(
-                "UPDATE jobs SET score = ?1 WHERE id = ?2;",
-                params![score, job_id as i32],
-            );
-            drop(data);
+            let update = JobUpdate {
+                score: Some(score),
+                ..JobUpdate::default()
+            };
+            let _ = storage.update_job(job_id, &update).await;
         }
     }
 
     //Finished
-    let data = pool.lock().await.get().unwrap();
-    let _ = data.execute(
-        "UPDATE jobs SET state = 'Finished' WHERE id = ?1;",
-        params![job_id as i32],
-    );
+    let mut update = JobUpdate::state("Finished");
     if flag {
-        let _ = data.execute(
-            "UPDATE jobs SET result = 'Accepted' WHERE id = ?1;",
-            params![job_id as i32],
-        );
+        update.result = Some("Accepted".to_string());
     }
+    let _ = storage.update_job(job_id, &update).await;
+}
+
+/// Wait for a compiler or a submission on a blocking thread, so that judging
+/// does not hold up the requests served by the same worker.
+async fn wait_child(mut child: Child, timeout: Duration) -> (Child, Option<ExitStatus>) {
+    tokio::task::spawn_blocking(move || {
+        let status = child.wait_timeout(timeout).unwrap();
+        (child, status)
+    })
+    .await
+    .unwrap()
 }
 
 pub async fn start(
     body: web::Json<PostJob>,
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    config: Data<Config>,
-    prob_map: Data<HashMap<u32, Problem>>,
-    ids: Data<Arc<Mutex<Ids>>>,
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
 ) -> Result<HttpResponse, Box<dyn std::error::Error>> {
-    let pool_shared = pool.clone();
-    let prob_map_shared = prob_map.clone();
-    let post_job: PostJob = PostJob {
-        source_code: body.source_code.to_string(),
-        language: body.language.to_string(),
-        user_id: body.user_id,
-        contest_id: body.contest_id,
-        problem_id: body.problem_id,
+    let task = match Task::new(body.into_inner(), &config, &prob_map).await {
+        Ok(task) => task,
+        Err(e) => return Ok(e),
     };
-    let (ans, job_id) = create_task(body, pool_shared, prob_map_shared, ids.clone()).await;
-    let _ = tokio::spawn(async move {
-        run(
-            post_job,
-            pool.clone(),
-            config.clone(),
-            prob_map.clone(),
-            job_id,
-        )
-        .await;
-    }); //.await;
+    let (ans, job_id) = match create_task(&task, storage.clone()).await {
+        Ok(created) => created,
+        Err(e) => return Ok(e),
+    };
+    // Judged in the background, the response does not wait for it.
+    drop(tokio::spawn(async move {
+        run(task, storage.clone(), config.clone(), job_id).await;
+    }));
     Ok(ans)
 }
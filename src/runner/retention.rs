@@ -0,0 +1,216 @@
+use actix_web::web::Data;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+use super::SerdeJob;
+use crate::config::{Config, Problem, Retention, RetentionPolicy};
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Serialize)]
+pub struct CleanupReport {
+    pub removed_files: u64,
+    pub freed_bytes: u64,
+}
+
+/// Bytes used by every file under `path`, 0 if it does not exist.
+pub fn disk_usage(path: &Path) -> u64 {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| disk_usage(&entry.path()))
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+/// The ids of the cases whose outputs are shown by `GET /jobs/{id}`.
+fn sample_cases(prob: Option<&Problem>) -> Vec<u32> {
+    match prob {
+        Some(prob) => prob
+            .cases
+            .iter()
+            .enumerate()
+            .filter(|(_, case)| case.sample)
+            .map(|(i, _)| i as u32 + 1)
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Apply the policy to the directory of a finished job, which was last written `age` ago.
+fn apply(
+    retention: &Retention,
+    dir: &Path,
+    job: Option<&SerdeJob>,
+    samples: &[u32],
+    age: Duration,
+    report: &mut CleanupReport,
+) {
+    let remove_all = match retention.policy {
+        RetentionPolicy::Keep => false,
+        RetentionPolicy::Delete => true,
+        RetentionPolicy::KeepFailed => match job {
+            Some(job) if job.result != "Accepted" => {
+                age >= Duration::from_secs(retention.failed_hours * 3600)
+            }
+            _ => true,
+        },
+        RetentionPolicy::SamplesOnly => {
+            let keep: Vec<String> = samples.iter().map(|id| format!("{}.out", id)).collect();
+            if let Ok(entries) = std::fs::read_dir(dir) {
+                for entry in entries.filter_map(|entry| entry.ok()) {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if !keep.contains(&name) {
+                        remove(&entry.path(), report);
+                    }
+                }
+            }
+            false
+        }
+    };
+    if remove_all {
+        remove(dir, report);
+    }
+}
+
+fn remove(path: &Path, report: &mut CleanupReport) {
+    let bytes = disk_usage(path);
+    let removed = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    if removed.is_ok() {
+        report.removed_files += 1;
+        report.freed_bytes += bytes;
+    }
+}
+
+/// Remove the directories of these jobs whatever the policy, such as the ones of an
+/// anonymized user.
+pub async fn remove_jobs(runtime_dir: &str, job_ids: Vec<u32>) -> CleanupReport {
+    let runtime_dir = Path::new(runtime_dir).to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut report = CleanupReport::default();
+        for job_id in job_ids {
+            let dir = runtime_dir.join(format!("job_{}", job_id));
+            if dir.exists() {
+                remove(&dir, &mut report);
+            }
+        }
+        report
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Apply the retention policy right after a job is judged.
+pub async fn after_judging(
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob: &Problem,
+    job_id: u32,
+) {
+    let runtime = config.lock().await.runtime.clone();
+    if runtime.retention.policy == RetentionPolicy::Keep {
+        return;
+    }
+    let job = match storage.get_job(job_id).await {
+        Ok(Some(job)) => job,
+        _ => return,
+    };
+    let samples = sample_cases(Some(prob));
+    let dir = Path::new(&runtime.runtime_dir).join(format!("job_{}", job_id));
+    let _ = tokio::task::spawn_blocking(move || {
+        let mut report = CleanupReport::default();
+        apply(
+            &runtime.retention,
+            &dir,
+            Some(&job),
+            &samples,
+            Duration::ZERO,
+            &mut report,
+        );
+    })
+    .await;
+}
+
+/// Apply the retention policy to every job directory, such as the ones of failed jobs
+/// whose time is up or the ones left behind by a crash. Jobs still being judged are skipped.
+pub async fn cleanup(
+    storage: Data<dyn Storage>,
+    config: Data<Arc<Mutex<Config>>>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+) -> CleanupReport {
+    let mut report = CleanupReport::default();
+    let runtime = config.lock().await.runtime.clone();
+    if runtime.retention.policy == RetentionPolicy::Keep {
+        return report;
+    }
+    let entries = match std::fs::read_dir(&runtime.runtime_dir) {
+        Ok(entries) => entries,
+        Err(_) => return report,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let job_id = match name
+            .strip_prefix("job_")
+            .and_then(|id| id.parse::<u32>().ok())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let job = match storage.get_job(job_id).await {
+            Ok(job) => job,
+            Err(_) => continue,
+        };
+        if let Some(job) = &job {
+            if job.state != "Finished" {
+                continue;
+            }
+        }
+        let samples = match &job {
+            Some(job) => sample_cases(prob_map.lock().await.get(&job.submission.problem_id)),
+            None => vec![],
+        };
+        let age = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or(Duration::ZERO);
+        let retention = runtime.retention.clone();
+        report = tokio::task::spawn_blocking(move || {
+            apply(
+                &retention,
+                &entry.path(),
+                job.as_ref(),
+                &samples,
+                age,
+                &mut report,
+            );
+            report
+        })
+        .await
+        .unwrap_or_default();
+    }
+    if report.removed_files > 0 {
+        log::info!(
+            "Cleanup removed {} file(s), {} bytes",
+            report.removed_files,
+            report.freed_bytes
+        );
+    }
+    report
+}
@@ -0,0 +1,355 @@
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::audit::AuditEntry;
+use crate::auth::{ApiKey, Session};
+use crate::config::{self, Backend};
+use crate::contests::registration::{Registration, RegistrationState};
+use crate::contests::SerdeContest;
+use crate::groups::SerdeGroup;
+use crate::handler::jobs::{JobsFilter, SortOrder};
+use crate::migrations::Migration;
+use crate::runner::SerdeJob;
+use crate::teams::SerdeTeam;
+use crate::users::import::NewUser;
+use crate::users::{ContestRecord, Role, SerdeUser, UserStatistics};
+
+mod postgres;
+mod sqlite;
+
+pub use self::postgres::PostgresStorage;
+pub use self::sqlite::SqliteStorage;
+
+#[derive(Debug)]
+pub struct StorageError(pub String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// The fields of a job to change, `None` keeps the stored value.
+#[derive(Debug, Default, Clone)]
+pub struct JobUpdate {
+    pub state: Option<String>,
+    pub result: Option<String>,
+    pub score: Option<f32>,
+}
+
+impl JobUpdate {
+    pub fn state(state: &str) -> JobUpdate {
+        JobUpdate {
+            state: Some(state.to_string()),
+            ..JobUpdate::default()
+        }
+    }
+
+    pub fn result(result: &str) -> JobUpdate {
+        JobUpdate {
+            result: Some(result.to_string()),
+            ..JobUpdate::default()
+        }
+    }
+}
+
+/// The fields of a case to change, `None` keeps the stored value.
+#[derive(Debug, Default, Clone)]
+pub struct CaseUpdate {
+    pub result: Option<String>,
+    pub time: Option<u32>,
+    pub info: Option<String>,
+}
+
+impl CaseUpdate {
+    pub fn result(result: &str) -> CaseUpdate {
+        CaseUpdate {
+            result: Some(result.to_string()),
+            ..CaseUpdate::default()
+        }
+    }
+}
+
+/// Everything the judge keeps in its database. Problems are stored as their JSON form.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The schema version of the database, 0 if it has never been migrated.
+    async fn schema_version(&self) -> StorageResult<u32>;
+    /// Apply one migration and record it, atomically.
+    async fn apply_migration(&self, migration: &Migration) -> StorageResult<()>;
+    /// Write a consistent copy of the database to `path` while it stays in use.
+    async fn backup(&self, path: &str) -> StorageResult<()>;
+
+    async fn get_user(&self, user_id: u32) -> StorageResult<Option<SerdeUser>>;
+    async fn get_user_by_name(&self, name: &str) -> StorageResult<Option<SerdeUser>>;
+    async fn list_users(&self) -> StorageResult<Vec<SerdeUser>>;
+    async fn user_statistics(&self, user_id: u32) -> StorageResult<UserStatistics>;
+    /// The contests a user submitted in, by contest id, once for each team they submitted for.
+    /// Scores and ranks are left out, they come from the ranklist.
+    async fn user_contests(&self, user_id: u32) -> StorageResult<Vec<ContestRecord>>;
+    /// Create a contestant with the next free id, starting from 0, and return the id.
+    async fn insert_user(&self, name: &str) -> StorageResult<u32>;
+    async fn rename_user(&self, user_id: u32, name: &str) -> StorageResult<()>;
+    async fn set_role(&self, user_id: u32, role: Role) -> StorageResult<()>;
+    async fn set_display_name(&self, user_id: u32, display_name: Option<&str>)
+        -> StorageResult<()>;
+    /// Create all of these users in one transaction, adding each to its group, which is
+    /// created when no group has that name. Returns the id and group id of each user.
+    async fn import_users(&self, users: &[NewUser]) -> StorageResult<Vec<(u32, Option<u32>)>>;
+    /// The password hash of a user, `None` if the user has no password or does not exist.
+    async fn get_password_hash(&self, user_id: u32) -> StorageResult<Option<String>>;
+    async fn set_password_hash(&self, user_id: u32, hash: &str) -> StorageResult<()>;
+    /// Deactivate a user at `time`, or reactivate them with `None`.
+    async fn set_deactivated(&self, user_id: u32, time: Option<&str>) -> StorageResult<()>;
+    /// In one transaction: rename and deactivate the user of `entry` at its time, drop their
+    /// display name, password, sessions and API keys, empty the source code of their
+    /// submissions and store `entry` with their number of jobs as its detail. Returns their
+    /// job ids.
+    async fn anonymize_user(&self, name: &str, entry: &AuditEntry) -> StorageResult<Vec<u32>>;
+
+    /// Store an audit entry under the next free id, returning the id.
+    async fn insert_audit(&self, entry: &AuditEntry) -> StorageResult<u32>;
+    /// The latest audit entries, newest first.
+    async fn list_audit(&self, limit: u32) -> StorageResult<Vec<AuditEntry>>;
+
+    /// Store a new session, and drop every session that expired before it was created.
+    async fn insert_session(&self, session: &Session) -> StorageResult<()>;
+    /// The user of the session with this token hash, if it has not expired by `now`.
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<SerdeUser>>;
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()>;
+    /// End every session of a user.
+    async fn delete_sessions(&self, user_id: u32) -> StorageResult<()>;
+
+    /// Store a new API key under the next free id, returning the id.
+    async fn insert_api_key(&self, key: &ApiKey) -> StorageResult<u32>;
+    async fn get_api_key(&self, key_id: u32) -> StorageResult<Option<ApiKey>>;
+    async fn list_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKey>>;
+    /// The key with this hash and its user, if the key has not expired by `now`.
+    async fn get_api_key_user(
+        &self,
+        key_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<(ApiKey, SerdeUser)>>;
+    async fn touch_api_key(&self, key_id: u32, time: &str) -> StorageResult<()>;
+    /// False if there is no such key.
+    async fn delete_api_key(&self, key_id: u32) -> StorageResult<bool>;
+
+    /// Store a new team and its members under the next free id, returning the id.
+    async fn insert_team(&self, team: &SerdeTeam) -> StorageResult<u32>;
+    /// Rename a team and replace its members.
+    async fn update_team(&self, team: &SerdeTeam) -> StorageResult<()>;
+    async fn get_team(&self, team_id: u32) -> StorageResult<Option<SerdeTeam>>;
+    async fn get_team_by_name(&self, name: &str) -> StorageResult<Option<SerdeTeam>>;
+    async fn list_teams(&self) -> StorageResult<Vec<SerdeTeam>>;
+    /// The ids of the teams a user is a member of.
+    async fn list_user_teams(&self, user_id: u32) -> StorageResult<Vec<u32>>;
+
+    /// Store a new group and its members under the next free id, returning the id.
+    async fn insert_group(&self, group: &SerdeGroup) -> StorageResult<u32>;
+    async fn rename_group(&self, group_id: u32, name: &str) -> StorageResult<()>;
+    async fn get_group(&self, group_id: u32) -> StorageResult<Option<SerdeGroup>>;
+    async fn get_group_by_name(&self, name: &str) -> StorageResult<Option<SerdeGroup>>;
+    async fn list_groups(&self) -> StorageResult<Vec<SerdeGroup>>;
+    /// The ids of the groups a user is a member of.
+    async fn list_user_groups(&self, user_id: u32) -> StorageResult<Vec<u32>>;
+    /// Add users to a group, skipping those already in it.
+    async fn add_group_members(&self, group_id: u32, user_ids: &[u32]) -> StorageResult<()>;
+    /// False if the user was not in the group.
+    async fn remove_group_member(&self, group_id: u32, user_id: u32) -> StorageResult<bool>;
+
+    async fn get_contest(&self, contest_id: u32) -> StorageResult<Option<SerdeContest>>;
+    async fn list_contests(&self) -> StorageResult<Vec<SerdeContest>>;
+    /// Create a contest with the next free id, starting from 1, and return the id.
+    /// `contest.id` is ignored.
+    async fn insert_contest(&self, contest: &SerdeContest) -> StorageResult<u32>;
+    async fn update_contest(&self, contest: &SerdeContest) -> StorageResult<()>;
+    /// Add a user to `user_ids` of a contest in one transaction. False if already there.
+    async fn add_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool>;
+    /// Remove a user from `user_ids` of a contest in one transaction. False if not there.
+    async fn remove_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool>;
+    /// Insert a registration, or replace the state and `updated_time` of an existing one.
+    async fn save_registration(&self, registration: &Registration) -> StorageResult<()>;
+    async fn get_registration(
+        &self,
+        contest_id: u32,
+        user_id: u32,
+    ) -> StorageResult<Option<Registration>>;
+    /// Registrations of a contest by user id, only those in `state` if given.
+    async fn list_registrations(
+        &self,
+        contest_id: u32,
+        state: Option<RegistrationState>,
+    ) -> StorageResult<Vec<Registration>>;
+    async fn delete_registration(&self, contest_id: u32, user_id: u32) -> StorageResult<bool>;
+
+    /// Store a new job with its submission and cases in one transaction, with the next
+    /// free id starting from 0, and return the id. `job.id` is ignored.
+    async fn insert_job(&self, job: &SerdeJob) -> StorageResult<u32>;
+    async fn get_job(&self, job_id: u32) -> StorageResult<Option<SerdeJob>>;
+    /// Jobs matching the filter, at most `filter.limit` of them.
+    async fn list_jobs(&self, filter: &JobsFilter) -> StorageResult<Vec<SerdeJob>>;
+    async fn count_jobs(&self, filter: &JobsFilter) -> StorageResult<u32>;
+    async fn count_submissions(
+        &self,
+        user_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32>;
+    async fn count_team_submissions(
+        &self,
+        team_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32>;
+    async fn update_job(&self, job_id: u32, update: &JobUpdate) -> StorageResult<()>;
+    async fn update_case(
+        &self,
+        job_id: u32,
+        case_id: u32,
+        update: &CaseUpdate,
+    ) -> StorageResult<()>;
+    /// Put a job and all of its cases back in the queue.
+    async fn reset_job(&self, job_id: u32, updated_time: &str) -> StorageResult<()>;
+
+    async fn list_problems(&self) -> StorageResult<Vec<String>>;
+    async fn save_problem(&self, problem_id: u32, content: &str) -> StorageResult<()>;
+    /// Returns false if there was no such problem.
+    async fn delete_problem(&self, problem_id: u32) -> StorageResult<bool>;
+}
+
+/// Open the backend selected in the config. `flush` drops all the data first.
+pub async fn open(config: &config::Storage, flush: bool) -> StorageResult<Arc<dyn Storage>> {
+    match config.backend {
+        Backend::Sqlite => {
+            if flush {
+                let _ = std::fs::remove_file(&config.database_path);
+            }
+            Ok(Arc::new(SqliteStorage::open(&config.database_path)?))
+        }
+        Backend::Postgres => {
+            let url = match &config.database_url {
+                Some(url) => url,
+                None => {
+                    return Err(StorageError(
+                        "the postgres backend needs a database_url".to_string(),
+                    ))
+                }
+            };
+            let storage = PostgresStorage::open(url)?;
+            if flush {
+                storage.flush().await?;
+            }
+            Ok(Arc::new(storage))
+        }
+    }
+}
+
+/// A value bound to a query parameter.
+enum Param {
+    Int(u32),
+    Text(String),
+}
+
+/// The columns read by `job_row`, from `jobs JOIN submission`.
+const JOB_COLUMNS: &str = "jobs.id, jobs.created_time, jobs.updated_time, jobs.state, jobs.result, jobs.score, \
+    submission.source_code, submission.language, submission.user_id, submission.contest_id, submission.problem_id, submission.team_id";
+
+/// Queries of `user_statistics` on the jobs of one user, each taking the user id.
+const USER_TOTALS: &str =
+    "SELECT COUNT(*), COALESCE(SUM(CASE WHEN jobs.result = 'Accepted' THEN 1 ELSE 0 END), 0) \
+    FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = ?;";
+const USER_PROBLEMS: &str =
+    "SELECT submission.problem_id, MAX(CASE WHEN jobs.result = 'Accepted' THEN 1 ELSE 0 END) \
+    FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = ? \
+    GROUP BY submission.problem_id ORDER BY submission.problem_id;";
+const USER_RESULTS: &str = "SELECT jobs.result, COUNT(*) \
+    FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = ? GROUP BY jobs.result;";
+const USER_LANGUAGES: &str = "SELECT submission.language, COUNT(*) \
+    FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = ? GROUP BY submission.language;";
+
+/// `contest_id, name, team_id` of every contest a user submitted in, once for each team they
+/// submitted for, taking the user id once. Contest 0 is left out.
+const USER_CONTESTS: &str = "SELECT submission.contest_id, contests.name, submission.team_id \
+    FROM submission JOIN contests ON contests.id = submission.contest_id \
+    WHERE submission.user_id = ? AND submission.contest_id != 0 \
+    GROUP BY submission.contest_id, contests.name, submission.team_id \
+    ORDER BY submission.contest_id, submission.team_id IS NOT NULL, submission.team_id;";
+
+/// The `WHERE` clause of a jobs query on `jobs JOIN submission`, with `?` placeholders.
+fn job_condition(filter: &JobsFilter) -> (String, Vec<Param>) {
+    let mut conds: Vec<&str> = vec![];
+    let mut args: Vec<Param> = vec![];
+    if let Some(user_id) = filter.user_id {
+        conds.push("submission.user_id = ?");
+        args.push(Param::Int(user_id));
+    }
+    if let Some(team_id) = filter.team_id {
+        conds.push("submission.team_id = ?");
+        args.push(Param::Int(team_id));
+    }
+    if let Some(contest_id) = filter.contest_id {
+        conds.push("submission.contest_id = ?");
+        args.push(Param::Int(contest_id));
+    }
+    if let Some(problem_id) = filter.problem_id {
+        conds.push("submission.problem_id = ?");
+        args.push(Param::Int(problem_id));
+    }
+    if let Some(language) = &filter.language {
+        conds.push("submission.language = ?");
+        args.push(Param::Text(language.to_string()));
+    }
+    if let Some(from) = &filter.from {
+        conds.push("jobs.created_time >= ?");
+        args.push(Param::Text(from.to_string()));
+    }
+    if let Some(to) = &filter.to {
+        conds.push("jobs.created_time <= ?");
+        args.push(Param::Text(to.to_string()));
+    }
+    if let Some(state) = &filter.state {
+        conds.push("jobs.state = ?");
+        args.push(Param::Text(state.to_string()));
+    }
+    if let Some(result) = &filter.result {
+        conds.push("jobs.result = ?");
+        args.push(Param::Text(result.to_string()));
+    }
+    if let Some(cursor) = filter.cursor {
+        if filter.order == Some(SortOrder::desc) {
+            conds.push("jobs.id < ?");
+        } else {
+            conds.push("jobs.id > ?");
+        }
+        args.push(Param::Int(cursor));
+    }
+    if conds.is_empty() {
+        return (String::new(), args);
+    }
+    (format!("WHERE {}", conds.join(" AND ")), args)
+}
+
+/// The `ORDER BY` and `LIMIT` clauses of a jobs query.
+fn job_order(filter: &JobsFilter, args: &mut Vec<Param>) -> String {
+    let order = match filter.order {
+        Some(SortOrder::desc) => "ORDER BY jobs.id DESC",
+        _ => "ORDER BY jobs.id ASC",
+    };
+    match filter.limit {
+        Some(limit) => {
+            args.push(Param::Int(limit));
+            // PostgreSQL would take an untyped `LIMIT` parameter as a BIGINT.
+            format!("{} LIMIT CAST(? AS INTEGER)", order)
+        }
+        None => order.to_string(),
+    }
+}
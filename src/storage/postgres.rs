@@ -0,0 +1,1328 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use deadpool_postgres::{Manager, Object, Pool, Transaction};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{GenericClient, NoTls, Row};
+
+use super::{
+    job_condition, job_order, CaseUpdate, JobUpdate, Param, Storage, StorageError, StorageResult,
+    JOB_COLUMNS, USER_CONTESTS, USER_LANGUAGES, USER_PROBLEMS, USER_RESULTS, USER_TOTALS,
+};
+use crate::audit::AuditEntry;
+use crate::auth::{ApiKey, Session};
+use crate::contests::registration::{Registration, RegistrationMode, RegistrationState};
+use crate::contests::SerdeContest;
+use crate::groups::SerdeGroup;
+use crate::handler::jobs::JobsFilter;
+use crate::migrations::Migration;
+use crate::runner::{SerdeCase, SerdeJob, SerdeSubmission};
+use crate::teams::SerdeTeam;
+use crate::users::import::NewUser;
+use crate::users::{ContestRecord, Role, SerdeUser, UserStatistics};
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for StorageError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+/// Columns are `INTEGER`, which only binds to `i32`.
+fn int(value: u32) -> i32 {
+    value as i32
+}
+
+fn bind(args: &[Param]) -> Vec<Box<dyn ToSql + Sync + Send>> {
+    args.iter()
+        .map(|arg| -> Box<dyn ToSql + Sync + Send> {
+            match arg {
+                Param::Int(i) => Box::new(int(*i)),
+                Param::Text(s) => Box::new(s.to_string()),
+            }
+        })
+        .collect()
+}
+
+fn refs(args: &[Box<dyn ToSql + Sync + Send>]) -> Vec<&(dyn ToSql + Sync)> {
+    args.iter()
+        .map(|arg| arg.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+/// Turn the `?` placeholders shared with SQLite into `$1`, `$2`, ...
+fn numbered(sql: &str) -> String {
+    let mut ans = String::new();
+    let mut index = 0;
+    for c in sql.chars() {
+        if c == '?' {
+            index += 1;
+            ans.push_str(&format!("${}", index));
+        } else {
+            ans.push(c);
+        }
+    }
+    ans
+}
+
+pub struct PostgresStorage {
+    pool: Pool,
+}
+
+impl PostgresStorage {
+    pub fn open(url: &str) -> StorageResult<PostgresStorage> {
+        let config: tokio_postgres::Config = url.parse()?;
+        let manager = Manager::new(config, NoTls);
+        match Pool::builder(manager).max_size(16).build() {
+            Ok(pool) => Ok(PostgresStorage { pool }),
+            Err(e) => Err(StorageError(e.to_string())),
+        }
+    }
+
+    /// Drop every table of the judge, like deleting the SQLite file does.
+    pub async fn flush(&self) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .batch_execute(
+                "DROP TABLE IF EXISTS audit_log, registrations, api_keys, sessions, group_members, user_groups, team_members, teams, cases, jobs, submission, contests, users, problems, schema_migrations CASCADE;",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn client(&self) -> StorageResult<Object> {
+        Ok(self.pool.get().await?)
+    }
+
+    /// Add or remove a user in `user_ids` of a contest, returning whether it changed.
+    async fn set_contest_user(
+        &self,
+        contest_id: u32,
+        user_id: u32,
+        registered: bool,
+    ) -> StorageResult<bool> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let row = tx
+            .query_opt(
+                "SELECT user_ids FROM contests WHERE id = $1 FOR UPDATE;",
+                &[&int(contest_id)],
+            )
+            .await?;
+        let mut user_ids: Vec<u32> = match row {
+            Some(row) => serde_json::from_str(row.get(0)).unwrap(),
+            None => return Ok(false),
+        };
+        if user_ids.contains(&user_id) == registered {
+            return Ok(false);
+        }
+        if registered {
+            user_ids.push(user_id);
+        } else {
+            user_ids.retain(|id| *id != user_id);
+        }
+        tx.execute(
+            "UPDATE contests SET user_ids = $1 WHERE id = $2;",
+            &[&serde_json::to_string(&user_ids).unwrap(), &int(contest_id)],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+/// `CREATE TABLE IF NOT EXISTS` would log a notice every time the table exists.
+async fn create_migrations_table<C: GenericClient>(client: &C) -> StorageResult<()> {
+    let row = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL;", &[])
+        .await?;
+    if !row.get::<_, bool>(0) {
+        client
+            .batch_execute(
+                "CREATE TABLE schema_migrations (version INTEGER PRIMARY KEY, name VARCHAR, applied_time VARCHAR);",
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// The id a new row of `table` gets. The table is locked against other writers
+/// until the transaction ends, readers are not blocked.
+async fn next_id(tx: &Transaction<'_>, table: &str, first: u32) -> StorageResult<u32> {
+    tx.batch_execute(&format!("LOCK TABLE {} IN EXCLUSIVE MODE;", table))
+        .await?;
+    let row = tx
+        .query_one(
+            &format!("SELECT COALESCE(MAX(id) + 1, $1) FROM {};", table),
+            &[&int(first)],
+        )
+        .await?;
+    Ok(row.get::<_, i32>(0) as u32)
+}
+
+fn user_row(row: &Row) -> SerdeUser {
+    SerdeUser {
+        id: row.get::<_, i32>(0) as u32,
+        name: row.get(1),
+        role: Role::parse(row.get(2)),
+        display_name: row.get(4),
+        deactivated_time: row.get(3),
+    }
+}
+
+fn audit_row(row: &Row) -> AuditEntry {
+    AuditEntry {
+        id: row.get::<_, i32>(0) as u32,
+        time: row.get(1),
+        actor_id: row.get::<_, i32>(2) as u32,
+        action: row.get(3),
+        user_id: row.get::<_, i32>(4) as u32,
+        detail: row.get(5),
+    }
+}
+
+const API_KEY_COLUMNS: &str =
+    "api_keys.id, api_keys.user_id, api_keys.name, api_keys.key_hash, api_keys.scopes, \
+     api_keys.created_time, api_keys.expires_time, api_keys.last_used_time";
+
+fn api_key_row(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get::<_, i32>(0) as u32,
+        user_id: row.get::<_, i32>(1) as u32,
+        name: row.get(2),
+        key_hash: row.get(3),
+        scopes: serde_json::from_str(row.get(4)).unwrap_or_default(),
+        created_time: row.get(5),
+        expires_time: row.get(6),
+        last_used_time: row.get(7),
+    }
+}
+
+fn contest_row(row: &Row) -> SerdeContest {
+    SerdeContest {
+        id: row.get::<_, i32>(0) as u32,
+        name: row.get(1),
+        from: row.get(2),
+        to: row.get(3),
+        problem_ids: serde_json::from_str(row.get(4)).unwrap(),
+        user_ids: serde_json::from_str(row.get(5)).unwrap(),
+        submission_limit: row.get::<_, i32>(6) as u32,
+        team_ids: serde_json::from_str(row.get(7)).unwrap(),
+        group_ids: serde_json::from_str(row.get(8)).unwrap(),
+        registration: RegistrationMode::parse(row.get(9)),
+        registration_password: row.get(10),
+        registration_deadline: row.get(11),
+    }
+}
+
+fn registration_row(row: &Row) -> Registration {
+    Registration {
+        contest_id: row.get::<_, i32>(0) as u32,
+        user_id: row.get::<_, i32>(1) as u32,
+        state: RegistrationState::parse(row.get(2)),
+        created_time: row.get(3),
+        updated_time: row.get(4),
+    }
+}
+
+fn job_row(row: &Row) -> SerdeJob {
+    SerdeJob {
+        id: row.get::<_, i32>(0) as u32,
+        created_time: row.get(1),
+        updated_time: row.get(2),
+        state: row.get(3),
+        result: row.get(4),
+        score: row.get(5),
+        submission: SerdeSubmission {
+            source_code: row.get(6),
+            language: row.get(7),
+            user_id: row.get::<_, i32>(8) as u32,
+            contest_id: row.get::<_, i32>(9) as u32,
+            problem_id: row.get::<_, i32>(10) as u32,
+            team_id: row.get::<_, Option<i32>>(11).map(|id| id as u32),
+        },
+        cases: vec![],
+    }
+}
+
+fn case_row(row: &Row) -> SerdeCase {
+    SerdeCase {
+        id: row.get::<_, i32>(0) as u32,
+        result: row.get(1),
+        time: row.get::<_, i32>(2) as u32,
+        memory: row.get::<_, i32>(3) as u32,
+        info: row.get(4),
+        sample: None,
+    }
+}
+
+/// A team with its members, from `id, name`.
+async fn team_row<C: GenericClient>(client: &C, row: &Row) -> StorageResult<SerdeTeam> {
+    let id = row.get::<_, i32>(0);
+    let members = client
+        .query(
+            "SELECT user_id FROM team_members WHERE team_id = $1 ORDER BY user_id;",
+            &[&id],
+        )
+        .await?;
+    Ok(SerdeTeam {
+        id: id as u32,
+        name: row.get(1),
+        member_ids: members
+            .iter()
+            .map(|row| row.get::<_, i32>(0) as u32)
+            .collect(),
+    })
+}
+
+async fn write_audit(tx: &Transaction<'_>, entry: &AuditEntry) -> StorageResult<u32> {
+    let entry_id = next_id(tx, "audit_log", 1).await?;
+    tx.execute(
+        "INSERT INTO audit_log (id, time, actor_id, action, user_id, detail) VALUES ($1, $2, $3, $4, $5, $6);",
+        &[
+            &int(entry_id),
+            &entry.time,
+            &int(entry.actor_id),
+            &entry.action,
+            &int(entry.user_id),
+            &entry.detail,
+        ],
+    )
+    .await?;
+    Ok(entry_id)
+}
+
+async fn write_members(tx: &Transaction<'_>, team: &SerdeTeam, team_id: u32) -> StorageResult<()> {
+    tx.execute(
+        "DELETE FROM team_members WHERE team_id = $1;",
+        &[&int(team_id)],
+    )
+    .await?;
+    for user_id in &team.member_ids {
+        tx.execute(
+            "INSERT INTO team_members (team_id, user_id) VALUES ($1, $2);",
+            &[&int(team_id), &int(*user_id)],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// A group with its members, from `id, name`.
+async fn group_row<C: GenericClient>(client: &C, row: &Row) -> StorageResult<SerdeGroup> {
+    let id = row.get::<_, i32>(0);
+    let members = client
+        .query(
+            "SELECT user_id FROM group_members WHERE group_id = $1 ORDER BY user_id;",
+            &[&id],
+        )
+        .await?;
+    Ok(SerdeGroup {
+        id: id as u32,
+        name: row.get(1),
+        member_ids: members
+            .iter()
+            .map(|row| row.get::<_, i32>(0) as u32)
+            .collect(),
+    })
+}
+
+async fn add_members(tx: &Transaction<'_>, group_id: u32, user_ids: &[u32]) -> StorageResult<()> {
+    for user_id in user_ids {
+        tx.execute(
+            "INSERT INTO group_members (group_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+            &[&int(group_id), &int(*user_id)],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn read_cases<C: GenericClient>(client: &C, jobs: &mut [SerdeJob]) -> StorageResult<()> {
+    let stmt = client
+        .prepare(
+            "SELECT caseid, result, time, memory, info FROM cases WHERE jobid = $1 ORDER BY caseid;",
+        )
+        .await?;
+    for job in jobs {
+        job.cases = client
+            .query(&stmt, &[&int(job.id)])
+            .await?
+            .iter()
+            .map(case_row)
+            .collect();
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn schema_version(&self) -> StorageResult<u32> {
+        let client = self.client().await?;
+        create_migrations_table(&**client).await?;
+        let row = client
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations;",
+                &[],
+            )
+            .await?;
+        Ok(row.get::<_, i32>(0) as u32)
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        tx.batch_execute(migration.postgres).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, applied_time) VALUES ($1, $2, $3);",
+            &[
+                &int(migration.version),
+                &migration.name,
+                &Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn backup(&self, _path: &str) -> StorageResult<()> {
+        Err(StorageError(
+            "PostgreSQL databases are backed up with pg_dump, or with export".to_string(),
+        ))
+    }
+
+    async fn get_user(&self, user_id: u32) -> StorageResult<Option<SerdeUser>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name, role, deactivated_time, display_name FROM users WHERE id = $1;",
+                &[&int(user_id)],
+            )
+            .await?;
+        Ok(row.as_ref().map(user_row))
+    }
+
+    async fn get_user_by_name(&self, name: &str) -> StorageResult<Option<SerdeUser>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name, role, deactivated_time, display_name FROM users WHERE name = $1;",
+                &[&name],
+            )
+            .await?;
+        Ok(row.as_ref().map(user_row))
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<SerdeUser>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT id, name, role, deactivated_time, display_name FROM users ORDER BY id;",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().map(user_row).collect())
+    }
+
+    async fn user_statistics(&self, user_id: u32) -> StorageResult<UserStatistics> {
+        let client = self.client().await?;
+        let user_id = int(user_id);
+        let totals = client
+            .query_one(&numbered(USER_TOTALS), &[&user_id])
+            .await?;
+        let mut statistics = UserStatistics {
+            submissions: totals.get::<_, i64>(0) as u32,
+            accepted: totals.get::<_, i64>(1) as u32,
+            ..UserStatistics::default()
+        };
+        for row in client.query(&numbered(USER_PROBLEMS), &[&user_id]).await? {
+            let problem_id = row.get::<_, i32>(0) as u32;
+            match row.get::<_, i32>(1) {
+                0 => statistics.attempted_problem_ids.push(problem_id),
+                _ => statistics.solved_problem_ids.push(problem_id),
+            }
+        }
+        for row in client.query(&numbered(USER_RESULTS), &[&user_id]).await? {
+            statistics
+                .results
+                .insert(row.get(0), row.get::<_, i64>(1) as u32);
+        }
+        for row in client.query(&numbered(USER_LANGUAGES), &[&user_id]).await? {
+            statistics
+                .languages
+                .insert(row.get(0), row.get::<_, i64>(1) as u32);
+        }
+        Ok(statistics)
+    }
+
+    async fn user_contests(&self, user_id: u32) -> StorageResult<Vec<ContestRecord>> {
+        let client = self.client().await?;
+        let user_id = int(user_id);
+        let rows = client.query(&numbered(USER_CONTESTS), &[&user_id]).await?;
+        Ok(rows
+            .iter()
+            .map(|row| ContestRecord {
+                contest_id: row.get::<_, i32>(0) as u32,
+                contest_name: row.get(1),
+                team_id: row.get::<_, Option<i32>>(2).map(|id| id as u32),
+                ..ContestRecord::default()
+            })
+            .collect())
+    }
+
+    async fn insert_user(&self, name: &str) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let user_id = next_id(&tx, "users", 0).await?;
+        tx.execute(
+            "INSERT INTO users (id, name) VALUES ($1, $2);",
+            &[&int(user_id), &name],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(user_id)
+    }
+
+    async fn rename_user(&self, user_id: u32, name: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE users SET name = $1 WHERE id = $2;",
+                &[&name, &int(user_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_role(&self, user_id: u32, role: Role) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE users SET role = $1 WHERE id = $2;",
+                &[&role.as_str(), &int(user_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_display_name(
+        &self,
+        user_id: u32,
+        display_name: Option<&str>,
+    ) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE users SET display_name = $1 WHERE id = $2;",
+                &[&display_name, &int(user_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn import_users(&self, users: &[NewUser]) -> StorageResult<Vec<(u32, Option<u32>)>> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let mut ids = vec![];
+        for user in users {
+            let user_id = next_id(&tx, "users", 0).await?;
+            tx.execute(
+                "INSERT INTO users (id, name, display_name, password_hash) VALUES ($1, $2, $3, $4);",
+                &[&int(user_id), &user.name, &user.display_name, &user.password_hash],
+            )
+            .await?;
+            let group_id = match &user.group {
+                Some(group) => {
+                    let existing = tx
+                        .query_opt("SELECT id FROM user_groups WHERE name = $1;", &[group])
+                        .await?;
+                    let group_id = match existing {
+                        Some(row) => row.get::<_, i32>(0) as u32,
+                        None => {
+                            let group_id = next_id(&tx, "user_groups", 1).await?;
+                            tx.execute(
+                                "INSERT INTO user_groups (id, name) VALUES ($1, $2);",
+                                &[&int(group_id), group],
+                            )
+                            .await?;
+                            group_id
+                        }
+                    };
+                    add_members(&tx, group_id, &[user_id]).await?;
+                    Some(group_id)
+                }
+                None => None,
+            };
+            ids.push((user_id, group_id));
+        }
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn set_deactivated(&self, user_id: u32, time: Option<&str>) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE users SET deactivated_time = $1 WHERE id = $2;",
+                &[&time, &int(user_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn anonymize_user(&self, name: &str, entry: &AuditEntry) -> StorageResult<Vec<u32>> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let user_id = int(entry.user_id);
+        tx.execute(
+            "UPDATE users SET name = $1, display_name = NULL, password_hash = NULL, deactivated_time = COALESCE(deactivated_time, $2) WHERE id = $3;",
+            &[&name, &entry.time, &user_id],
+        )
+        .await?;
+        tx.execute("DELETE FROM sessions WHERE user_id = $1;", &[&user_id])
+            .await?;
+        tx.execute("DELETE FROM api_keys WHERE user_id = $1;", &[&user_id])
+            .await?;
+        tx.execute(
+            "UPDATE submission SET source_code = '' WHERE user_id = $1;",
+            &[&user_id],
+        )
+        .await?;
+        let rows = tx
+            .query(
+                "SELECT jobs.id FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = $1;",
+                &[&user_id],
+            )
+            .await?;
+        let entry = AuditEntry {
+            detail: format!("{} job(s)", rows.len()),
+            ..entry.clone()
+        };
+        write_audit(&tx, &entry).await?;
+        tx.commit().await?;
+        Ok(rows.iter().map(|row| row.get::<_, i32>(0) as u32).collect())
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let entry_id = write_audit(&tx, entry).await?;
+        tx.commit().await?;
+        Ok(entry_id)
+    }
+
+    async fn list_audit(&self, limit: u32) -> StorageResult<Vec<AuditEntry>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT id, time, actor_id, action, user_id, detail FROM audit_log ORDER BY id DESC LIMIT $1;",
+                &[&(limit as i64)],
+            )
+            .await?;
+        Ok(rows.iter().map(audit_row).collect())
+    }
+
+    async fn get_password_hash(&self, user_id: u32) -> StorageResult<Option<String>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT password_hash FROM users WHERE id = $1;",
+                &[&int(user_id)],
+            )
+            .await?;
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    async fn set_password_hash(&self, user_id: u32, hash: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE users SET password_hash = $1 WHERE id = $2;",
+                &[&hash, &int(user_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_session(&self, session: &Session) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        tx.execute(
+            "DELETE FROM sessions WHERE expires_time < $1;",
+            &[&session.created_time],
+        )
+        .await?;
+        tx.execute(
+            "INSERT INTO sessions (token_hash, user_id, created_time, expires_time) VALUES ($1, $2, $3, $4);",
+            &[
+                &session.token_hash,
+                &int(session.user_id),
+                &session.created_time,
+                &session.expires_time,
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<SerdeUser>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT users.id, users.name, users.role, users.deactivated_time, users.display_name FROM sessions JOIN users ON users.id = sessions.user_id \
+                 WHERE sessions.token_hash = $1 AND sessions.expires_time > $2 AND users.deactivated_time IS NULL;",
+                &[&token_hash, &now],
+            )
+            .await?;
+        Ok(row.as_ref().map(user_row))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "DELETE FROM sessions WHERE token_hash = $1;",
+                &[&token_hash],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, user_id: u32) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute("DELETE FROM sessions WHERE user_id = $1;", &[&int(user_id)])
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let key_id = next_id(&tx, "api_keys", 1).await?;
+        tx.execute(
+            "INSERT INTO api_keys (id, user_id, name, key_hash, scopes, created_time, expires_time, last_used_time) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8);",
+            &[
+                &int(key_id),
+                &int(key.user_id),
+                &key.name,
+                &key.key_hash,
+                &serde_json::to_string(&key.scopes).unwrap(),
+                &key.created_time,
+                &key.expires_time,
+                &key.last_used_time,
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(key_id)
+    }
+
+    async fn get_api_key(&self, key_id: u32) -> StorageResult<Option<ApiKey>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                &format!("SELECT {} FROM api_keys WHERE id = $1;", API_KEY_COLUMNS),
+                &[&int(key_id)],
+            )
+            .await?;
+        Ok(row.as_ref().map(api_key_row))
+    }
+
+    async fn list_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKey>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY id;",
+                    API_KEY_COLUMNS
+                ),
+                &[&int(user_id)],
+            )
+            .await?;
+        Ok(rows.iter().map(api_key_row).collect())
+    }
+
+    async fn get_api_key_user(
+        &self,
+        key_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<(ApiKey, SerdeUser)>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                &format!(
+                    "SELECT {}, users.id, users.name, users.role, users.deactivated_time, users.display_name FROM api_keys JOIN users ON users.id = api_keys.user_id \
+                     WHERE api_keys.key_hash = $1 AND (api_keys.expires_time IS NULL OR api_keys.expires_time > $2) \
+                     AND users.deactivated_time IS NULL;",
+                    API_KEY_COLUMNS
+                ),
+                &[&key_hash, &now],
+            )
+            .await?;
+        Ok(row.map(|row| {
+            let user = SerdeUser {
+                id: row.get::<_, i32>(8) as u32,
+                name: row.get(9),
+                role: Role::parse(row.get(10)),
+                display_name: row.get(12),
+                deactivated_time: row.get(11),
+            };
+            (api_key_row(&row), user)
+        }))
+    }
+
+    async fn touch_api_key(&self, key_id: u32, time: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE api_keys SET last_used_time = $1 WHERE id = $2;",
+                &[&time, &int(key_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, key_id: u32) -> StorageResult<bool> {
+        let client = self.client().await?;
+        let deleted = client
+            .execute("DELETE FROM api_keys WHERE id = $1;", &[&int(key_id)])
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn insert_team(&self, team: &SerdeTeam) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let team_id = next_id(&tx, "teams", 1).await?;
+        tx.execute(
+            "INSERT INTO teams (id, name) VALUES ($1, $2);",
+            &[&int(team_id), &team.name],
+        )
+        .await?;
+        write_members(&tx, team, team_id).await?;
+        tx.commit().await?;
+        Ok(team_id)
+    }
+
+    async fn update_team(&self, team: &SerdeTeam) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        tx.execute(
+            "UPDATE teams SET name = $1 WHERE id = $2;",
+            &[&team.name, &int(team.id)],
+        )
+        .await?;
+        write_members(&tx, team, team.id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_team(&self, team_id: u32) -> StorageResult<Option<SerdeTeam>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name FROM teams WHERE id = $1;",
+                &[&int(team_id)],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(Some(team_row(&**client, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_team_by_name(&self, name: &str) -> StorageResult<Option<SerdeTeam>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt("SELECT id, name FROM teams WHERE name = $1;", &[&name])
+            .await?;
+        match row {
+            Some(row) => Ok(Some(team_row(&**client, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_teams(&self) -> StorageResult<Vec<SerdeTeam>> {
+        let client = self.client().await?;
+        let rows = client
+            .query("SELECT id, name FROM teams ORDER BY id;", &[])
+            .await?;
+        let mut teams = vec![];
+        for row in &rows {
+            teams.push(team_row(&**client, row).await?);
+        }
+        Ok(teams)
+    }
+
+    async fn list_user_teams(&self, user_id: u32) -> StorageResult<Vec<u32>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT team_id FROM team_members WHERE user_id = $1 ORDER BY team_id;",
+                &[&int(user_id)],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get::<_, i32>(0) as u32).collect())
+    }
+
+    async fn insert_group(&self, group: &SerdeGroup) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let group_id = next_id(&tx, "user_groups", 1).await?;
+        tx.execute(
+            "INSERT INTO user_groups (id, name) VALUES ($1, $2);",
+            &[&int(group_id), &group.name],
+        )
+        .await?;
+        add_members(&tx, group_id, &group.member_ids).await?;
+        tx.commit().await?;
+        Ok(group_id)
+    }
+
+    async fn rename_group(&self, group_id: u32, name: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE user_groups SET name = $1 WHERE id = $2;",
+                &[&name, &int(group_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_group(&self, group_id: u32) -> StorageResult<Option<SerdeGroup>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name FROM user_groups WHERE id = $1;",
+                &[&int(group_id)],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(Some(group_row(&**client, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_group_by_name(&self, name: &str) -> StorageResult<Option<SerdeGroup>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name FROM user_groups WHERE name = $1;",
+                &[&name],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(Some(group_row(&**client, &row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_groups(&self) -> StorageResult<Vec<SerdeGroup>> {
+        let client = self.client().await?;
+        let rows = client
+            .query("SELECT id, name FROM user_groups ORDER BY id;", &[])
+            .await?;
+        let mut groups = vec![];
+        for row in &rows {
+            groups.push(group_row(&**client, row).await?);
+        }
+        Ok(groups)
+    }
+
+    async fn list_user_groups(&self, user_id: u32) -> StorageResult<Vec<u32>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT group_id FROM group_members WHERE user_id = $1 ORDER BY group_id;",
+                &[&int(user_id)],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get::<_, i32>(0) as u32).collect())
+    }
+
+    async fn add_group_members(&self, group_id: u32, user_ids: &[u32]) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        add_members(&tx, group_id, user_ids).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_group_member(&self, group_id: u32, user_id: u32) -> StorageResult<bool> {
+        let client = self.client().await?;
+        let deleted = client
+            .execute(
+                "DELETE FROM group_members WHERE group_id = $1 AND user_id = $2;",
+                &[&int(group_id), &int(user_id)],
+            )
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn get_contest(&self, contest_id: u32) -> StorageResult<Option<SerdeContest>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline FROM contests WHERE id = $1;",
+                &[&int(contest_id)],
+            )
+            .await?;
+        Ok(row.as_ref().map(contest_row))
+    }
+
+    async fn list_contests(&self) -> StorageResult<Vec<SerdeContest>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline FROM contests ORDER BY id;",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().map(contest_row).collect())
+    }
+
+    async fn insert_contest(&self, contest: &SerdeContest) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let contest_id = next_id(&tx, "contests", 1).await?;
+        tx.execute(
+                "INSERT INTO contests (id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12);",
+                &[
+                    &int(contest_id),
+                    &contest.name,
+                    &contest.from,
+                    &contest.to,
+                    &serde_json::to_string(&contest.problem_ids).unwrap(),
+                    &serde_json::to_string(&contest.user_ids).unwrap(),
+                    &int(contest.submission_limit),
+                    &serde_json::to_string(&contest.team_ids).unwrap(),
+                    &serde_json::to_string(&contest.group_ids).unwrap(),
+                    &contest.registration.as_str(),
+                    &contest.registration_password,
+                    &contest.registration_deadline,
+                ],
+            )
+            .await?;
+        tx.commit().await?;
+        Ok(contest_id)
+    }
+
+    async fn update_contest(&self, contest: &SerdeContest) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE contests SET (name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline) = ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) WHERE id = $12;",
+                &[
+                    &contest.name,
+                    &contest.from,
+                    &contest.to,
+                    &serde_json::to_string(&contest.problem_ids).unwrap(),
+                    &serde_json::to_string(&contest.user_ids).unwrap(),
+                    &int(contest.submission_limit),
+                    &serde_json::to_string(&contest.team_ids).unwrap(),
+                    &serde_json::to_string(&contest.group_ids).unwrap(),
+                    &contest.registration.as_str(),
+                    &contest.registration_password,
+                    &contest.registration_deadline,
+                    &int(contest.id),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn add_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.set_contest_user(contest_id, user_id, true).await
+    }
+
+    async fn remove_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.set_contest_user(contest_id, user_id, false).await
+    }
+
+    async fn save_registration(&self, registration: &Registration) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO registrations (contest_id, user_id, state, created_time, updated_time) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (contest_id, user_id) DO UPDATE SET state = excluded.state, updated_time = excluded.updated_time;",
+                &[
+                    &int(registration.contest_id),
+                    &int(registration.user_id),
+                    &registration.state.as_str(),
+                    &registration.created_time,
+                    &registration.updated_time,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_registration(
+        &self,
+        contest_id: u32,
+        user_id: u32,
+    ) -> StorageResult<Option<Registration>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                "SELECT contest_id, user_id, state, created_time, updated_time FROM registrations WHERE contest_id = $1 AND user_id = $2;",
+                &[&int(contest_id), &int(user_id)],
+            )
+            .await?;
+        Ok(row.as_ref().map(registration_row))
+    }
+
+    async fn list_registrations(
+        &self,
+        contest_id: u32,
+        state: Option<RegistrationState>,
+    ) -> StorageResult<Vec<Registration>> {
+        let client = self.client().await?;
+        let rows = client
+            .query(
+                "SELECT contest_id, user_id, state, created_time, updated_time FROM registrations WHERE contest_id = $1 AND ($2::VARCHAR IS NULL OR state = $2) ORDER BY user_id;",
+                &[&int(contest_id), &state.map(|state| state.as_str())],
+            )
+            .await?;
+        Ok(rows.iter().map(registration_row).collect())
+    }
+
+    async fn delete_registration(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        let client = self.client().await?;
+        let deleted = client
+            .execute(
+                "DELETE FROM registrations WHERE contest_id = $1 AND user_id = $2;",
+                &[&int(contest_id), &int(user_id)],
+            )
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn insert_job(&self, job: &SerdeJob) -> StorageResult<u32> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        // A job and its submission share the id.
+        let job_id = next_id(&tx, "submission", 0).await?;
+        tx.execute(
+                "INSERT INTO submission (id, source_code, language, user_id, contest_id, problem_id, team_id) VALUES ($1, $2, $3, $4, $5, $6, $7);",
+                &[
+                    &int(job_id),
+                    &job.submission.source_code,
+                    &job.submission.language,
+                    &int(job.submission.user_id),
+                    &int(job.submission.contest_id),
+                    &int(job.submission.problem_id),
+                    &job.submission.team_id.map(int),
+                ],
+            )
+            .await?;
+        tx.execute(
+                "INSERT INTO jobs (id, created_time, updated_time, submission_id, state, result, score, cases) VALUES ($1, $2, $3, $4, $5, $6, $7, $8);",
+                &[
+                    &int(job_id),
+                    &job.created_time,
+                    &job.updated_time,
+                    &int(job_id),
+                    &job.state,
+                    &job.result,
+                    &job.score,
+                    &(job.cases.len() as i32 - 1),
+                ],
+            )
+            .await?;
+        for case in &job.cases {
+            tx.execute(
+                    "INSERT INTO cases (jobid, caseid, result, time, memory, info) VALUES ($1, $2, $3, $4, $5, $6);",
+                    &[
+                        &int(job_id),
+                        &int(case.id),
+                        &case.result,
+                        &int(case.time),
+                        &int(case.memory),
+                        &case.info,
+                    ],
+                )
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(job_id)
+    }
+
+    async fn get_job(&self, job_id: u32) -> StorageResult<Option<SerdeJob>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt(
+                &format!(
+                    "SELECT {} FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE jobs.id = $1;",
+                    JOB_COLUMNS
+                ),
+                &[&int(job_id)],
+            )
+            .await?;
+        match row {
+            Some(row) => {
+                let mut jobs = [job_row(&row)];
+                read_cases(&**client, &mut jobs).await?;
+                let [job] = jobs;
+                Ok(Some(job))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn list_jobs(&self, filter: &JobsFilter) -> StorageResult<Vec<SerdeJob>> {
+        let client = self.client().await?;
+        let (cond, mut args) = job_condition(filter);
+        let order = job_order(filter, &mut args);
+        let args = bind(&args);
+        let rows = client
+            .query(
+                &numbered(&format!(
+                    "SELECT {} FROM jobs JOIN submission ON submission.id = jobs.submission_id {} {};",
+                    JOB_COLUMNS, cond, order
+                )),
+                &refs(&args),
+            )
+            .await?;
+        let mut jobs: Vec<SerdeJob> = rows.iter().map(job_row).collect();
+        read_cases(&**client, &mut jobs).await?;
+        Ok(jobs)
+    }
+
+    async fn count_jobs(&self, filter: &JobsFilter) -> StorageResult<u32> {
+        let client = self.client().await?;
+        let (cond, args) = job_condition(filter);
+        let args = bind(&args);
+        let row = client
+            .query_one(
+                &numbered(&format!(
+                    "SELECT COUNT(*) FROM jobs JOIN submission ON submission.id = jobs.submission_id {};",
+                    cond
+                )),
+                &refs(&args),
+            )
+            .await?;
+        Ok(row.get::<_, i64>(0) as u32)
+    }
+
+    async fn count_submissions(
+        &self,
+        user_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32> {
+        let client = self.client().await?;
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) FROM submission WHERE user_id = $1 AND problem_id = $2 AND contest_id = $3;",
+                &[&int(user_id), &int(problem_id), &int(contest_id)],
+            )
+            .await?;
+        Ok(row.get::<_, i64>(0) as u32)
+    }
+
+    async fn count_team_submissions(
+        &self,
+        team_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32> {
+        let client = self.client().await?;
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) FROM submission WHERE team_id = $1 AND problem_id = $2 AND contest_id = $3;",
+                &[&int(team_id), &int(problem_id), &int(contest_id)],
+            )
+            .await?;
+        Ok(row.get::<_, i64>(0) as u32)
+    }
+
+    async fn update_job(&self, job_id: u32, update: &JobUpdate) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE jobs SET (state, result, score) = (COALESCE($1, state), COALESCE($2, result), COALESCE($3, score)) WHERE id = $4;",
+                &[&update.state, &update.result, &update.score, &int(job_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_case(
+        &self,
+        job_id: u32,
+        case_id: u32,
+        update: &CaseUpdate,
+    ) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE cases SET (result, time, info) = (COALESCE($1, result), COALESCE($2, time), COALESCE($3, info)) WHERE jobid = $4 AND caseid = $5;",
+                &[
+                    &update.result,
+                    &update.time.map(int),
+                    &update.info,
+                    &int(job_id),
+                    &int(case_id),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn reset_job(&self, job_id: u32, updated_time: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "UPDATE jobs SET (updated_time, state, result, score) = ($1, 'Queueing', 'Waiting', 0.0) WHERE id = $2;",
+                &[&updated_time, &int(job_id)],
+            )
+            .await?;
+        client
+            .execute(
+                "UPDATE cases SET (result, time, memory, info) = ('Waiting', 0, 0, '') WHERE jobid = $1;",
+                &[&int(job_id)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_problems(&self) -> StorageResult<Vec<String>> {
+        let client = self.client().await?;
+        let rows = client
+            .query("SELECT content FROM problems ORDER BY id;", &[])
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn save_problem(&self, problem_id: u32, content: &str) -> StorageResult<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "INSERT INTO problems (id, content) VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET content = EXCLUDED.content;",
+                &[&int(problem_id), &content],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_problem(&self, problem_id: u32) -> StorageResult<bool> {
+        let client = self.client().await?;
+        let deleted = client
+            .execute("DELETE FROM problems WHERE id = $1;", &[&int(problem_id)])
+            .await?;
+        Ok(deleted > 0)
+    }
+}
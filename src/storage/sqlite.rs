@@ -0,0 +1,1364 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    params, params_from_iter, Connection, OptionalExtension, Row, ToSql, Transaction,
+    TransactionBehavior,
+};
+
+use super::{
+    job_condition, job_order, CaseUpdate, JobUpdate, Param, Storage, StorageError, StorageResult,
+    JOB_COLUMNS, USER_CONTESTS, USER_LANGUAGES, USER_PROBLEMS, USER_RESULTS, USER_TOTALS,
+};
+use crate::audit::AuditEntry;
+use crate::auth::{ApiKey, Session};
+use crate::contests::registration::{Registration, RegistrationMode, RegistrationState};
+use crate::contests::SerdeContest;
+use crate::groups::SerdeGroup;
+use crate::handler::jobs::JobsFilter;
+use crate::migrations::Migration;
+use crate::runner::{SerdeCase, SerdeJob, SerdeSubmission};
+use crate::teams::SerdeTeam;
+use crate::users::import::NewUser;
+use crate::users::{ContestRecord, Role, SerdeUser, UserStatistics};
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl From<r2d2::Error> for StorageError {
+    fn from(e: r2d2::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+impl ToSql for Param {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        match self {
+            Param::Int(i) => i.to_sql(),
+            Param::Text(s) => s.to_sql(),
+        }
+    }
+}
+
+pub struct SqliteStorage {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> StorageResult<SqliteStorage> {
+        // WAL lets readers go on while a job is being written, and writers wait for each
+        // other for up to `busy_timeout` instead of failing with SQLITE_BUSY.
+        let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+            conn.busy_timeout(std::time::Duration::from_secs(5))?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.execute_batch("PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;")
+        });
+        Ok(SqliteStorage {
+            pool: Pool::builder().max_size(16).build(manager)?,
+        })
+    }
+
+    /// Run `f` on a pooled connection. SQLite calls block, so they run on tokio's blocking
+    /// threads and never hold up the async workers.
+    async fn with_conn<T, F>(&self, f: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> StorageResult<T> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+        let task = move || -> StorageResult<T> {
+            let mut data = pool.get()?;
+            f(&mut data)
+        };
+        match tokio::task::spawn_blocking(task).await {
+            Ok(result) => result,
+            Err(e) => Err(StorageError(e.to_string())),
+        }
+    }
+}
+
+fn user_row(row: &Row) -> rusqlite::Result<SerdeUser> {
+    Ok(SerdeUser {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        role: Role::parse(&row.get::<_, String>(2)?),
+        display_name: row.get(4)?,
+        deactivated_time: row.get(3)?,
+    })
+}
+
+fn audit_row(row: &Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        time: row.get(1)?,
+        actor_id: row.get(2)?,
+        action: row.get(3)?,
+        user_id: row.get(4)?,
+        detail: row.get(5)?,
+    })
+}
+
+const API_KEY_COLUMNS: &str =
+    "api_keys.id, api_keys.user_id, api_keys.name, api_keys.key_hash, api_keys.scopes, \
+     api_keys.created_time, api_keys.expires_time, api_keys.last_used_time";
+
+fn api_key_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        key_hash: row.get(3)?,
+        scopes: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or_default(),
+        created_time: row.get(5)?,
+        expires_time: row.get(6)?,
+        last_used_time: row.get(7)?,
+    })
+}
+
+fn contest_row(row: &Row) -> rusqlite::Result<SerdeContest> {
+    Ok(SerdeContest {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        from: row.get(2)?,
+        to: row.get(3)?,
+        problem_ids: serde_json::from_str(&row.get::<_, String>(4)?).unwrap(),
+        user_ids: serde_json::from_str(&row.get::<_, String>(5)?).unwrap(),
+        submission_limit: row.get(6)?,
+        team_ids: serde_json::from_str(&row.get::<_, String>(7)?).unwrap(),
+        group_ids: serde_json::from_str(&row.get::<_, String>(8)?).unwrap(),
+        registration: RegistrationMode::parse(&row.get::<_, String>(9)?),
+        registration_password: row.get(10)?,
+        registration_deadline: row.get(11)?,
+    })
+}
+
+fn registration_row(row: &Row) -> rusqlite::Result<Registration> {
+    Ok(Registration {
+        contest_id: row.get(0)?,
+        user_id: row.get(1)?,
+        state: RegistrationState::parse(&row.get::<_, String>(2)?),
+        created_time: row.get(3)?,
+        updated_time: row.get(4)?,
+    })
+}
+
+/// Add or remove a user in `user_ids` of a contest, returning whether it changed.
+fn set_contest_user(
+    data: &mut Connection,
+    contest_id: u32,
+    user_id: u32,
+    registered: bool,
+) -> rusqlite::Result<bool> {
+    let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let user_ids: Option<String> = tx
+        .query_row(
+            "SELECT user_ids FROM contests WHERE id = ?1;",
+            params![contest_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let mut user_ids: Vec<u32> = match user_ids {
+        Some(user_ids) => serde_json::from_str(&user_ids).unwrap(),
+        None => return Ok(false),
+    };
+    if user_ids.contains(&user_id) == registered {
+        return Ok(false);
+    }
+    if registered {
+        user_ids.push(user_id);
+    } else {
+        user_ids.retain(|id| *id != user_id);
+    }
+    tx.execute(
+        "UPDATE contests SET user_ids = ?1 WHERE id = ?2;",
+        params![serde_json::to_string(&user_ids).unwrap(), contest_id],
+    )?;
+    tx.commit()?;
+    Ok(true)
+}
+
+fn job_row(row: &Row) -> rusqlite::Result<SerdeJob> {
+    Ok(SerdeJob {
+        id: row.get(0)?,
+        created_time: row.get(1)?,
+        updated_time: row.get(2)?,
+        state: row.get(3)?,
+        result: row.get(4)?,
+        score: row.get(5)?,
+        submission: SerdeSubmission {
+            source_code: row.get(6)?,
+            language: row.get(7)?,
+            user_id: row.get(8)?,
+            contest_id: row.get(9)?,
+            problem_id: row.get(10)?,
+            team_id: row.get(11)?,
+        },
+        cases: vec![],
+    })
+}
+
+fn case_row(row: &Row) -> rusqlite::Result<SerdeCase> {
+    Ok(SerdeCase {
+        id: row.get(0)?,
+        result: row.get(1)?,
+        time: row.get(2)?,
+        memory: row.get(3)?,
+        info: row.get(4)?,
+        sample: None,
+    })
+}
+
+/// The id a new row of `table` gets. Only call it inside an immediate transaction,
+/// which holds the write lock until the row is inserted.
+fn next_id(tx: &Transaction, table: &str, first: u32) -> rusqlite::Result<u32> {
+    tx.query_row(
+        &format!("SELECT COALESCE(MAX(id) + 1, ?1) FROM {};", table),
+        params![first],
+        |row| row.get(0),
+    )
+}
+
+/// A team with its members, from `id, name`.
+fn team_row(data: &Connection, row: &Row) -> rusqlite::Result<SerdeTeam> {
+    let id = row.get(0)?;
+    let mut stmt =
+        data.prepare("SELECT user_id FROM team_members WHERE team_id = ?1 ORDER BY user_id;")?;
+    let member_ids = stmt
+        .query_map(params![id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(SerdeTeam {
+        id,
+        name: row.get(1)?,
+        member_ids,
+    })
+}
+
+fn write_audit(tx: &Transaction, entry: &AuditEntry) -> rusqlite::Result<u32> {
+    let entry_id = next_id(tx, "audit_log", 1)?;
+    tx.execute(
+        "INSERT INTO audit_log (id, time, actor_id, action, user_id, detail) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        params![
+            entry_id,
+            entry.time,
+            entry.actor_id,
+            entry.action,
+            entry.user_id,
+            entry.detail
+        ],
+    )?;
+    Ok(entry_id)
+}
+
+fn write_members(tx: &Transaction, team: &SerdeTeam, team_id: u32) -> rusqlite::Result<()> {
+    tx.execute(
+        "DELETE FROM team_members WHERE team_id = ?1;",
+        params![team_id],
+    )?;
+    let mut stmt = tx.prepare("INSERT INTO team_members (team_id, user_id) VALUES (?1, ?2);")?;
+    for user_id in &team.member_ids {
+        stmt.execute(params![team_id, user_id])?;
+    }
+    Ok(())
+}
+
+/// A group with its members, from `id, name`.
+fn group_row(data: &Connection, row: &Row) -> rusqlite::Result<SerdeGroup> {
+    let id = row.get(0)?;
+    let mut stmt =
+        data.prepare("SELECT user_id FROM group_members WHERE group_id = ?1 ORDER BY user_id;")?;
+    let member_ids = stmt
+        .query_map(params![id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(SerdeGroup {
+        id,
+        name: row.get(1)?,
+        member_ids,
+    })
+}
+
+fn add_members(tx: &Transaction, group_id: u32, user_ids: &[u32]) -> rusqlite::Result<()> {
+    let mut stmt =
+        tx.prepare("INSERT OR IGNORE INTO group_members (group_id, user_id) VALUES (?1, ?2);")?;
+    for user_id in user_ids {
+        stmt.execute(params![group_id, user_id])?;
+    }
+    Ok(())
+}
+
+fn read_cases(data: &Connection, jobs: &mut [SerdeJob]) -> rusqlite::Result<()> {
+    let mut stmt = data.prepare(
+        "SELECT caseid, result, time, memory, info FROM cases WHERE jobid = ?1 ORDER BY caseid;",
+    )?;
+    for job in jobs {
+        job.cases = stmt
+            .query_map(params![job.id], case_row)?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn schema_version(&self) -> StorageResult<u32> {
+        self.with_conn(|data| {
+            data.execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY, name VARCHAR, applied_time VARCHAR);",
+                [],
+            )?;
+            Ok(data.query_row(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations;",
+                [],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> StorageResult<()> {
+        let (version, name, sql) = (migration.version, migration.name, migration.sqlite);
+        self.with_conn(move |data| {
+            // Tables are rebuilt by renaming them, which must not rewrite or check the foreign keys
+            // pointing at them. The pragma is a no-op inside a transaction, so it is set out here.
+            data.execute_batch("PRAGMA foreign_keys = OFF;")?;
+            let result = (|| -> StorageResult<()> {
+                let tx = data.transaction()?;
+                tx.execute_batch(sql)?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, name, applied_time) VALUES (?1, ?2, ?3);",
+                    params![
+                        version,
+                        name,
+                        Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+                    ],
+                )?;
+                if tx.prepare("PRAGMA foreign_key_check;")?.exists([])? {
+                    return Err(StorageError("it leaves broken foreign keys".to_string()));
+                }
+                tx.commit()?;
+                Ok(())
+            })();
+            data.execute_batch("PRAGMA foreign_keys = ON;")?;
+            result
+        })
+        .await
+    }
+
+    async fn backup(&self, path: &str) -> StorageResult<()> {
+        let path = path.to_string();
+        self.with_conn(move |data| {
+            // Reads one snapshot, so writers are not blocked and nothing half-written is copied.
+            data.execute("VACUUM INTO ?1;", params![path])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_user(&self, user_id: u32) -> StorageResult<Option<SerdeUser>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name, role, deactivated_time, display_name FROM users WHERE id = ?1;",
+                    params![user_id],
+                    user_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn get_user_by_name(&self, name: &str) -> StorageResult<Option<SerdeUser>> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name, role, deactivated_time, display_name FROM users WHERE name = ?1;",
+                    params![name],
+                    user_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<SerdeUser>> {
+        self.with_conn(|data| {
+            let mut stmt = data.prepare(
+                "SELECT id, name, role, deactivated_time, display_name FROM users ORDER BY id;",
+            )?;
+            let users = stmt
+                .query_map([], user_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(users)
+        })
+        .await
+    }
+
+    async fn user_statistics(&self, user_id: u32) -> StorageResult<UserStatistics> {
+        self.with_conn(move |data| {
+            let (submissions, accepted) = data.query_row(USER_TOTALS, params![user_id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            let mut statistics = UserStatistics {
+                submissions,
+                accepted,
+                ..UserStatistics::default()
+            };
+            let mut stmt = data.prepare(USER_PROBLEMS)?;
+            let mut rows = stmt.query(params![user_id])?;
+            while let Some(row) = rows.next()? {
+                match row.get::<_, u32>(1)? {
+                    0 => statistics.attempted_problem_ids.push(row.get(0)?),
+                    _ => statistics.solved_problem_ids.push(row.get(0)?),
+                }
+            }
+            let mut stmt = data.prepare(USER_RESULTS)?;
+            statistics.results = stmt
+                .query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut stmt = data.prepare(USER_LANGUAGES)?;
+            statistics.languages = stmt
+                .query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(statistics)
+        })
+        .await
+    }
+
+    async fn user_contests(&self, user_id: u32) -> StorageResult<Vec<ContestRecord>> {
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(USER_CONTESTS)?;
+            let contests = stmt
+                .query_map(params![user_id], |row| {
+                    Ok(ContestRecord {
+                        contest_id: row.get(0)?,
+                        contest_name: row.get(1)?,
+                        team_id: row.get(2)?,
+                        ..ContestRecord::default()
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(contests)
+        })
+        .await
+    }
+
+    async fn insert_user(&self, name: &str) -> StorageResult<u32> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let user_id = next_id(&tx, "users", 0)?;
+            tx.execute(
+                "INSERT INTO users (id, name) VALUES (?1, ?2);",
+                params![user_id, name],
+            )?;
+            tx.commit()?;
+            Ok(user_id)
+        })
+        .await
+    }
+
+    async fn rename_user(&self, user_id: u32, name: &str) -> StorageResult<()> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE users SET name = ?1 WHERE id = ?2;",
+                params![name, user_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_role(&self, user_id: u32, role: Role) -> StorageResult<()> {
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE users SET role = ?1 WHERE id = ?2;",
+                params![role.as_str(), user_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_display_name(
+        &self,
+        user_id: u32,
+        display_name: Option<&str>,
+    ) -> StorageResult<()> {
+        let display_name = display_name.map(|name| name.to_string());
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE users SET display_name = ?1 WHERE id = ?2;",
+                params![display_name, user_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn import_users(&self, users: &[NewUser]) -> StorageResult<Vec<(u32, Option<u32>)>> {
+        let users = users.to_vec();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut ids = vec![];
+            for user in users {
+                let user_id = next_id(&tx, "users", 0)?;
+                tx.execute(
+                    "INSERT INTO users (id, name, display_name, password_hash) VALUES (?1, ?2, ?3, ?4);",
+                    params![user_id, user.name, user.display_name, user.password_hash],
+                )?;
+                let group_id = match &user.group {
+                    Some(group) => {
+                        let existing = tx
+                            .query_row(
+                                "SELECT id FROM user_groups WHERE name = ?1;",
+                                params![group],
+                                |row| row.get(0),
+                            )
+                            .optional()?;
+                        let group_id = match existing {
+                            Some(group_id) => group_id,
+                            None => {
+                                let group_id = next_id(&tx, "user_groups", 1)?;
+                                tx.execute(
+                                    "INSERT INTO user_groups (id, name) VALUES (?1, ?2);",
+                                    params![group_id, group],
+                                )?;
+                                group_id
+                            }
+                        };
+                        add_members(&tx, group_id, &[user_id])?;
+                        Some(group_id)
+                    }
+                    None => None,
+                };
+                ids.push((user_id, group_id));
+            }
+            tx.commit()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn set_deactivated(&self, user_id: u32, time: Option<&str>) -> StorageResult<()> {
+        let time = time.map(|time| time.to_string());
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE users SET deactivated_time = ?1 WHERE id = ?2;",
+                params![time, user_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn anonymize_user(&self, name: &str, entry: &AuditEntry) -> StorageResult<Vec<u32>> {
+        let (name, mut entry) = (name.to_string(), entry.clone());
+        let (user_id, time) = (entry.user_id, entry.time.clone());
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.execute(
+                "UPDATE users SET name = ?1, display_name = NULL, password_hash = NULL, deactivated_time = COALESCE(deactivated_time, ?2) WHERE id = ?3;",
+                params![name, time, user_id],
+            )?;
+            tx.execute("DELETE FROM sessions WHERE user_id = ?1;", params![user_id])?;
+            tx.execute("DELETE FROM api_keys WHERE user_id = ?1;", params![user_id])?;
+            tx.execute(
+                "UPDATE submission SET source_code = '' WHERE user_id = ?1;",
+                params![user_id],
+            )?;
+            let job_ids = {
+                let mut stmt = tx.prepare(
+                    "SELECT jobs.id FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE submission.user_id = ?1;",
+                )?;
+                let job_ids = stmt
+                    .query_map(params![user_id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<u32>>>()?;
+                job_ids
+            };
+            entry.detail = format!("{} job(s)", job_ids.len());
+            write_audit(&tx, &entry)?;
+            tx.commit()?;
+            Ok(job_ids)
+        })
+        .await
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> StorageResult<u32> {
+        let entry = entry.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let entry_id = write_audit(&tx, &entry)?;
+            tx.commit()?;
+            Ok(entry_id)
+        })
+        .await
+    }
+
+    async fn list_audit(&self, limit: u32) -> StorageResult<Vec<AuditEntry>> {
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(
+                "SELECT id, time, actor_id, action, user_id, detail FROM audit_log ORDER BY id DESC LIMIT ?1;",
+            )?;
+            let entries = stmt
+                .query_map(params![limit], audit_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(entries)
+        })
+        .await
+    }
+
+    async fn get_password_hash(&self, user_id: u32) -> StorageResult<Option<String>> {
+        self.with_conn(move |data| {
+            let hash: Option<Option<String>> = data
+                .query_row(
+                    "SELECT password_hash FROM users WHERE id = ?1;",
+                    params![user_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(hash.flatten())
+        })
+        .await
+    }
+
+    async fn set_password_hash(&self, user_id: u32, hash: &str) -> StorageResult<()> {
+        let hash = hash.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE users SET password_hash = ?1 WHERE id = ?2;",
+                params![hash, user_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_session(&self, session: &Session) -> StorageResult<()> {
+        let session = session.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction()?;
+            tx.execute(
+                "DELETE FROM sessions WHERE expires_time < ?1;",
+                params![session.created_time],
+            )?;
+            tx.execute(
+                "INSERT INTO sessions (token_hash, user_id, created_time, expires_time) VALUES (?1, ?2, ?3, ?4);",
+                params![
+                    session.token_hash,
+                    session.user_id,
+                    session.created_time,
+                    session.expires_time
+                ],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<SerdeUser>> {
+        let (token_hash, now) = (token_hash.to_string(), now.to_string());
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT users.id, users.name, users.role, users.deactivated_time, users.display_name FROM sessions JOIN users ON users.id = sessions.user_id \
+                     WHERE sessions.token_hash = ?1 AND sessions.expires_time > ?2 AND users.deactivated_time IS NULL;",
+                    params![token_hash, now],
+                    user_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        let token_hash = token_hash.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "DELETE FROM sessions WHERE token_hash = ?1;",
+                params![token_hash],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_sessions(&self, user_id: u32) -> StorageResult<()> {
+        self.with_conn(move |data| {
+            data.execute("DELETE FROM sessions WHERE user_id = ?1;", params![user_id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_api_key(&self, key: &ApiKey) -> StorageResult<u32> {
+        let key = key.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let key_id = next_id(&tx, "api_keys", 1)?;
+            tx.execute(
+                "INSERT INTO api_keys (id, user_id, name, key_hash, scopes, created_time, expires_time, last_used_time) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+                params![
+                    key_id,
+                    key.user_id,
+                    key.name,
+                    key.key_hash,
+                    serde_json::to_string(&key.scopes).unwrap(),
+                    key.created_time,
+                    key.expires_time,
+                    key.last_used_time
+                ],
+            )?;
+            tx.commit()?;
+            Ok(key_id)
+        })
+        .await
+    }
+
+    async fn get_api_key(&self, key_id: u32) -> StorageResult<Option<ApiKey>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    &format!("SELECT {} FROM api_keys WHERE id = ?1;", API_KEY_COLUMNS),
+                    params![key_id],
+                    api_key_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKey>> {
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(&format!(
+                "SELECT {} FROM api_keys WHERE user_id = ?1 ORDER BY id;",
+                API_KEY_COLUMNS
+            ))?;
+            let keys = stmt
+                .query_map(params![user_id], api_key_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn get_api_key_user(
+        &self,
+        key_hash: &str,
+        now: &str,
+    ) -> StorageResult<Option<(ApiKey, SerdeUser)>> {
+        let (key_hash, now) = (key_hash.to_string(), now.to_string());
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    &format!(
+                        "SELECT {}, users.id, users.name, users.role, users.deactivated_time, users.display_name FROM api_keys JOIN users ON users.id = api_keys.user_id \
+                         WHERE api_keys.key_hash = ?1 AND (api_keys.expires_time IS NULL OR api_keys.expires_time > ?2) \
+                         AND users.deactivated_time IS NULL;",
+                        API_KEY_COLUMNS
+                    ),
+                    params![key_hash, now],
+                    |row| {
+                        let user = SerdeUser {
+                            id: row.get(8)?,
+                            name: row.get(9)?,
+                            role: Role::parse(&row.get::<_, String>(10)?),
+                            display_name: row.get(12)?,
+                            deactivated_time: row.get(11)?,
+                        };
+                        Ok((api_key_row(row)?, user))
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn touch_api_key(&self, key_id: u32, time: &str) -> StorageResult<()> {
+        let time = time.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE api_keys SET last_used_time = ?1 WHERE id = ?2;",
+                params![time, key_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_api_key(&self, key_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| {
+            let deleted = data.execute("DELETE FROM api_keys WHERE id = ?1;", params![key_id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn insert_team(&self, team: &SerdeTeam) -> StorageResult<u32> {
+        let team = team.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let team_id = next_id(&tx, "teams", 1)?;
+            tx.execute(
+                "INSERT INTO teams (id, name) VALUES (?1, ?2);",
+                params![team_id, team.name],
+            )?;
+            write_members(&tx, &team, team_id)?;
+            tx.commit()?;
+            Ok(team_id)
+        })
+        .await
+    }
+
+    async fn update_team(&self, team: &SerdeTeam) -> StorageResult<()> {
+        let team = team.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction()?;
+            tx.execute(
+                "UPDATE teams SET name = ?1 WHERE id = ?2;",
+                params![team.name, team.id],
+            )?;
+            write_members(&tx, &team, team.id)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_team(&self, team_id: u32) -> StorageResult<Option<SerdeTeam>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name FROM teams WHERE id = ?1;",
+                    params![team_id],
+                    |row| team_row(data, row),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn get_team_by_name(&self, name: &str) -> StorageResult<Option<SerdeTeam>> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name FROM teams WHERE name = ?1;",
+                    params![name],
+                    |row| team_row(data, row),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_teams(&self) -> StorageResult<Vec<SerdeTeam>> {
+        self.with_conn(|data| {
+            let mut stmt = data.prepare("SELECT id, name FROM teams ORDER BY id;")?;
+            let teams = stmt
+                .query_map([], |row| team_row(data, row))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(teams)
+        })
+        .await
+    }
+
+    async fn list_user_teams(&self, user_id: u32) -> StorageResult<Vec<u32>> {
+        self.with_conn(move |data| {
+            let mut stmt = data
+                .prepare("SELECT team_id FROM team_members WHERE user_id = ?1 ORDER BY team_id;")?;
+            let team_ids = stmt
+                .query_map(params![user_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(team_ids)
+        })
+        .await
+    }
+
+    async fn insert_group(&self, group: &SerdeGroup) -> StorageResult<u32> {
+        let group = group.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let group_id = next_id(&tx, "user_groups", 1)?;
+            tx.execute(
+                "INSERT INTO user_groups (id, name) VALUES (?1, ?2);",
+                params![group_id, group.name],
+            )?;
+            add_members(&tx, group_id, &group.member_ids)?;
+            tx.commit()?;
+            Ok(group_id)
+        })
+        .await
+    }
+
+    async fn rename_group(&self, group_id: u32, name: &str) -> StorageResult<()> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE user_groups SET name = ?1 WHERE id = ?2;",
+                params![name, group_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_group(&self, group_id: u32) -> StorageResult<Option<SerdeGroup>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name FROM user_groups WHERE id = ?1;",
+                    params![group_id],
+                    |row| group_row(data, row),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn get_group_by_name(&self, name: &str) -> StorageResult<Option<SerdeGroup>> {
+        let name = name.to_string();
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name FROM user_groups WHERE name = ?1;",
+                    params![name],
+                    |row| group_row(data, row),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_groups(&self) -> StorageResult<Vec<SerdeGroup>> {
+        self.with_conn(|data| {
+            let mut stmt = data.prepare("SELECT id, name FROM user_groups ORDER BY id;")?;
+            let groups = stmt
+                .query_map([], |row| group_row(data, row))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(groups)
+        })
+        .await
+    }
+
+    async fn list_user_groups(&self, user_id: u32) -> StorageResult<Vec<u32>> {
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(
+                "SELECT group_id FROM group_members WHERE user_id = ?1 ORDER BY group_id;",
+            )?;
+            let group_ids = stmt
+                .query_map(params![user_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(group_ids)
+        })
+        .await
+    }
+
+    async fn add_group_members(&self, group_id: u32, user_ids: &[u32]) -> StorageResult<()> {
+        let user_ids = user_ids.to_vec();
+        self.with_conn(move |data| {
+            let tx = data.transaction()?;
+            add_members(&tx, group_id, &user_ids)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_group_member(&self, group_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| {
+            let deleted = data.execute(
+                "DELETE FROM group_members WHERE group_id = ?1 AND user_id = ?2;",
+                params![group_id, user_id],
+            )?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn get_contest(&self, contest_id: u32) -> StorageResult<Option<SerdeContest>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline FROM contests WHERE id = ?1;",
+                    params![contest_id],
+                    contest_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_contests(&self) -> StorageResult<Vec<SerdeContest>> {
+        self.with_conn(|data| {
+            let mut stmt = data.prepare(
+                "SELECT id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline FROM contests ORDER BY id;",
+            )?;
+            let contests = stmt
+                .query_map([], contest_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(contests)
+        })
+        .await
+    }
+
+    async fn insert_contest(&self, contest: &SerdeContest) -> StorageResult<u32> {
+        let contest = contest.clone();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let contest_id = next_id(&tx, "contests", 1)?;
+            tx.execute(
+                "INSERT INTO contests (id, name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);",
+                params![
+                    contest_id,
+                    contest.name,
+                    contest.from,
+                    contest.to,
+                    serde_json::to_string(&contest.problem_ids).unwrap(),
+                    serde_json::to_string(&contest.user_ids).unwrap(),
+                    contest.submission_limit,
+                    serde_json::to_string(&contest.team_ids).unwrap(),
+                    serde_json::to_string(&contest.group_ids).unwrap(),
+                    contest.registration.as_str(),
+                    contest.registration_password,
+                    contest.registration_deadline
+                ],
+            )?;
+            tx.commit()?;
+            Ok(contest_id)
+        })
+        .await
+    }
+
+    async fn update_contest(&self, contest: &SerdeContest) -> StorageResult<()> {
+        let contest = contest.clone();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE contests SET (name, from_time, to_time, problem_ids, user_ids, submission_limit, team_ids, group_ids, registration, registration_password, registration_deadline) = (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11) WHERE id = ?12;",
+                params![
+                    contest.name,
+                    contest.from,
+                    contest.to,
+                    serde_json::to_string(&contest.problem_ids).unwrap(),
+                    serde_json::to_string(&contest.user_ids).unwrap(),
+                    contest.submission_limit,
+                    serde_json::to_string(&contest.team_ids).unwrap(),
+                    serde_json::to_string(&contest.group_ids).unwrap(),
+                    contest.registration.as_str(),
+                    contest.registration_password,
+                    contest.registration_deadline,
+                    contest.id
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn add_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| Ok(set_contest_user(data, contest_id, user_id, true)?))
+            .await
+    }
+
+    async fn remove_contest_user(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| Ok(set_contest_user(data, contest_id, user_id, false)?))
+            .await
+    }
+
+    async fn save_registration(&self, registration: &Registration) -> StorageResult<()> {
+        let registration = registration.clone();
+        self.with_conn(move |data| {
+            data.execute(
+                "INSERT INTO registrations (contest_id, user_id, state, created_time, updated_time) VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (contest_id, user_id) DO UPDATE SET state = excluded.state, updated_time = excluded.updated_time;",
+                params![
+                    registration.contest_id,
+                    registration.user_id,
+                    registration.state.as_str(),
+                    registration.created_time,
+                    registration.updated_time
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_registration(
+        &self,
+        contest_id: u32,
+        user_id: u32,
+    ) -> StorageResult<Option<Registration>> {
+        self.with_conn(move |data| {
+            Ok(data
+                .query_row(
+                    "SELECT contest_id, user_id, state, created_time, updated_time FROM registrations WHERE contest_id = ?1 AND user_id = ?2;",
+                    params![contest_id, user_id],
+                    registration_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn list_registrations(
+        &self,
+        contest_id: u32,
+        state: Option<RegistrationState>,
+    ) -> StorageResult<Vec<Registration>> {
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(
+                "SELECT contest_id, user_id, state, created_time, updated_time FROM registrations WHERE contest_id = ?1 AND (?2 IS NULL OR state = ?2) ORDER BY user_id;",
+            )?;
+            let registrations = stmt
+                .query_map(
+                    params![contest_id, state.map(|state| state.as_str())],
+                    registration_row,
+                )?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(registrations)
+        })
+        .await
+    }
+
+    async fn delete_registration(&self, contest_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| {
+            let deleted = data.execute(
+                "DELETE FROM registrations WHERE contest_id = ?1 AND user_id = ?2;",
+                params![contest_id, user_id],
+            )?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn insert_job(&self, job: &SerdeJob) -> StorageResult<u32> {
+        let job = job.clone();
+        self.with_conn(move |data| {
+            // One transaction: a failure leaves no partial rows, and the job costs a single sync.
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            // A job and its submission share the id.
+            let job_id = next_id(&tx, "submission", 0)?;
+            tx.execute(
+                "INSERT INTO submission (id, source_code, language, user_id, contest_id, problem_id, team_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                params![
+                    job_id,
+                    job.submission.source_code,
+                    job.submission.language,
+                    job.submission.user_id,
+                    job.submission.contest_id,
+                    job.submission.problem_id,
+                    job.submission.team_id
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO jobs (id, created_time, updated_time, submission_id, state, result, score, cases) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+                params![
+                    job_id,
+                    job.created_time,
+                    job.updated_time,
+                    job_id,
+                    job.state,
+                    job.result,
+                    job.score,
+                    job.cases.len() as i32 - 1
+                ],
+            )?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO cases (jobid, caseid, result, time, memory, info) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+                )?;
+                for case in &job.cases {
+                    stmt.execute(params![
+                        job_id,
+                        case.id,
+                        case.result,
+                        case.time,
+                        case.memory,
+                        case.info
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(job_id)
+        })
+        .await
+    }
+
+    async fn get_job(&self, job_id: u32) -> StorageResult<Option<SerdeJob>> {
+        self.with_conn(move |data| {
+            let job = data
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM jobs JOIN submission ON submission.id = jobs.submission_id WHERE jobs.id = ?1;",
+                        JOB_COLUMNS
+                    ),
+                    params![job_id],
+                    job_row,
+                )
+                .optional()?;
+            match job {
+                Some(job) => {
+                    let mut jobs = [job];
+                    read_cases(data, &mut jobs)?;
+                    let [job] = jobs;
+                    Ok(Some(job))
+                }
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn list_jobs(&self, filter: &JobsFilter) -> StorageResult<Vec<SerdeJob>> {
+        let (cond, mut args) = job_condition(filter);
+        let order = job_order(filter, &mut args);
+        self.with_conn(move |data| {
+            let mut stmt = data.prepare(&format!(
+                "SELECT {} FROM jobs JOIN submission ON submission.id = jobs.submission_id {} {};",
+                JOB_COLUMNS, cond, order
+            ))?;
+            let mut jobs: Vec<SerdeJob> = stmt
+                .query_map(params_from_iter(args.iter()), job_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            read_cases(data, &mut jobs)?;
+            Ok(jobs)
+        })
+        .await
+    }
+
+    async fn count_jobs(&self, filter: &JobsFilter) -> StorageResult<u32> {
+        let (cond, args) = job_condition(filter);
+        self.with_conn(move |data| {
+            Ok(data.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM jobs JOIN submission ON submission.id = jobs.submission_id {};",
+                    cond
+                ),
+                params_from_iter(args.iter()),
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn count_submissions(
+        &self,
+        user_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32> {
+        self.with_conn(move |data| {
+            Ok(data.query_row(
+                "SELECT COUNT(*) FROM submission WHERE user_id = ?1 AND problem_id = ?2 AND contest_id = ?3;",
+                params![user_id, problem_id, contest_id],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn count_team_submissions(
+        &self,
+        team_id: u32,
+        problem_id: u32,
+        contest_id: u32,
+    ) -> StorageResult<u32> {
+        self.with_conn(move |data| {
+            Ok(data.query_row(
+                "SELECT COUNT(*) FROM submission WHERE team_id = ?1 AND problem_id = ?2 AND contest_id = ?3;",
+                params![team_id, problem_id, contest_id],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn update_job(&self, job_id: u32, update: &JobUpdate) -> StorageResult<()> {
+        let update = update.clone();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE jobs SET (state, result, score) = (COALESCE(?1, state), COALESCE(?2, result), COALESCE(?3, score)) WHERE id = ?4;",
+                params![update.state, update.result, update.score, job_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_case(
+        &self,
+        job_id: u32,
+        case_id: u32,
+        update: &CaseUpdate,
+    ) -> StorageResult<()> {
+        let update = update.clone();
+        self.with_conn(move |data| {
+            data.execute(
+                "UPDATE cases SET (result, time, info) = (COALESCE(?1, result), COALESCE(?2, time), COALESCE(?3, info)) WHERE jobid = ?4 AND caseid = ?5;",
+                params![update.result, update.time, update.info, job_id, case_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn reset_job(&self, job_id: u32, updated_time: &str) -> StorageResult<()> {
+        let updated_time = updated_time.to_string();
+        self.with_conn(move |data| {
+            let tx = data.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.execute(
+                "UPDATE jobs SET (updated_time, state, result, score) = (?1, 'Queueing', 'Waiting', 0.0) WHERE id = ?2;",
+                params![updated_time, job_id],
+            )?;
+            tx.execute(
+                "UPDATE cases SET (result, time, memory, info) = ('Waiting', 0, 0, '') WHERE jobid = ?1;",
+                params![job_id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_problems(&self) -> StorageResult<Vec<String>> {
+        self.with_conn(|data| {
+            let mut stmt = data.prepare("SELECT content FROM problems ORDER BY id;")?;
+            let contents = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(contents)
+        })
+        .await
+    }
+
+    async fn save_problem(&self, problem_id: u32, content: &str) -> StorageResult<()> {
+        let content = content.to_string();
+        self.with_conn(move |data| {
+            data.execute(
+                "INSERT OR REPLACE INTO problems (id, content) VALUES (?1, ?2);",
+                params![problem_id, content],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_problem(&self, problem_id: u32) -> StorageResult<bool> {
+        self.with_conn(move |data| {
+            let deleted =
+                data.execute("DELETE FROM problems WHERE id = ?1;", params![problem_id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
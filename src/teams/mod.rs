@@ -0,0 +1,136 @@
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::contests::SerdeContest;
+use crate::error_log;
+use crate::handler::teams::PostTeam;
+use crate::storage::Storage;
+use crate::users;
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct SerdeTeam {
+    pub id: u32,
+    pub name: String,
+    pub member_ids: Vec<u32>,
+}
+
+pub async fn get_team(storage: Data<dyn Storage>, team_id: u32) -> Result<SerdeTeam, HttpResponse> {
+    match storage.get_team(team_id).await {
+        Ok(Some(team)) => Ok(team),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
+            "Team {} not found.",
+            team_id
+        ))),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+pub async fn get_teams(storage: Data<dyn Storage>) -> Result<Vec<SerdeTeam>, HttpResponse> {
+    match storage.list_teams().await {
+        Ok(teams) => Ok(teams),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+/// Create a team, or rename one and replace its members if `id` is given.
+pub async fn save_team(
+    storage: Data<dyn Storage>,
+    body: PostTeam,
+) -> Result<SerdeTeam, HttpResponse> {
+    if body.name.trim().is_empty() {
+        return Err(error_log::INVALID_ARGUMENT::webmsg("Team name is empty."));
+    }
+    let mut member_ids = body.member_ids;
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    for user_id in &member_ids {
+        if !users::user_exists(storage.clone(), *user_id).await {
+            return Err(error_log::NOT_FOUND::webmsg(&format!(
+                "User {} not found.",
+                user_id
+            )));
+        }
+    }
+    let same_name = match storage.get_team_by_name(&body.name).await {
+        Ok(team) => team,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    if same_name.is_some_and(|team| Some(team.id) != body.id) {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+            "Team name '{}' already exists.",
+            body.name
+        )));
+    }
+    let mut team = SerdeTeam {
+        id: 0,
+        name: body.name,
+        member_ids,
+    };
+    match body.id {
+        Some(id) => {
+            get_team(storage.clone(), id).await?;
+            team.id = id;
+            if storage.update_team(&team).await.is_err() {
+                return Err(error_log::EXTERNAL::webmsg("Database Error."));
+            }
+        }
+        None => match storage.insert_team(&team).await {
+            Ok(id) => team.id = id,
+            Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+        },
+    }
+    Ok(team)
+}
+
+/// Refuse contests where a user would compete twice: in two registered teams, or in a
+/// team and on their own.
+pub async fn check_contest_teams(
+    storage: Data<dyn Storage>,
+    team_ids: &[u32],
+    user_ids: &[u32],
+) -> Result<(), HttpResponse> {
+    let mut seen: Vec<(u32, u32)> = vec![];
+    for team_id in team_ids {
+        let team = get_team(storage.clone(), *team_id).await?;
+        for user_id in team.member_ids {
+            if user_ids.contains(&user_id) {
+                return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+                    "User {} is registered both alone and in team {}.",
+                    user_id, team.id
+                )));
+            }
+            if let Some((_, other)) = seen.iter().find(|(id, _)| *id == user_id) {
+                return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+                    "User {} is in both team {} and team {}.",
+                    user_id, other, team.id
+                )));
+            }
+            seen.push((user_id, team.id));
+        }
+    }
+    Ok(())
+}
+
+/// The registered team a user submits for in a contest, `None` if they take part alone.
+pub async fn contest_team(
+    storage: Data<dyn Storage>,
+    contest: &SerdeContest,
+    user_id: u32,
+) -> Result<Option<u32>, HttpResponse> {
+    let team_ids = match storage.list_user_teams(user_id).await {
+        Ok(team_ids) => team_ids,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    // Members can change after registration, so this is checked again on every submission.
+    let mut registered = team_ids
+        .into_iter()
+        .filter(|id| contest.team_ids.contains(id));
+    match (registered.next(), registered.next()) {
+        (Some(first), Some(second)) => Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} is in both team {} and team {} of contest {}.",
+            user_id, first, second, contest.id
+        ))),
+        (team_id, _) => Ok(team_id),
+    }
+}
@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::auth;
+use crate::storage::Storage;
+
+/// The columns of a row, in order. Only `name` is required.
+pub const COLUMNS: [&str; 4] = ["name", "display_name", "group", "password"];
+
+/// Length of generated passwords.
+const GENERATED_PASSWORD_LEN: usize = 16;
+
+/// A user to create, with the password already hashed.
+#[derive(Clone, Debug)]
+pub struct NewUser {
+    pub name: String,
+    pub display_name: Option<String>,
+    /// The name of the group, created if it does not exist.
+    pub group: Option<String>,
+    pub password_hash: String,
+}
+
+/// A row of the CSV file. Empty fields are read as `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportRow {
+    pub line: u64,
+    pub name: String,
+    pub display_name: Option<String>,
+    pub group: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RowReport {
+    pub line: u64,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<u32>,
+    /// Only set for generated passwords, which are not shown again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: u32,
+    pub errors: u32,
+    pub rows: Vec<RowReport>,
+}
+
+/// Read the rows of a CSV file with the columns `name,display_name,group,password`. A first
+/// row starting with `name` is taken as a header, and blank rows are skipped.
+pub fn parse(input: &str) -> Result<Vec<ImportRow>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+    let mut rows = vec![];
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Cannot read the CSV: {}", e))?;
+        // The position of a record starts at the blank lines before it.
+        let line = record.position().map_or(index as u64 + 1, |pos| {
+            let blank = input[pos.byte() as usize..]
+                .bytes()
+                .take_while(|b| *b == b'\n' || *b == b'\r')
+                .filter(|b| *b == b'\n')
+                .count();
+            pos.line() + blank as u64
+        });
+        if index == 0 && record.get(0) == Some(COLUMNS[0]) {
+            continue;
+        }
+        if record.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        if record.len() > COLUMNS.len() {
+            return Err(format!(
+                "Line {} has {} columns, expected at most {}: {}.",
+                line,
+                record.len(),
+                COLUMNS.len(),
+                COLUMNS.join(",")
+            ));
+        }
+        let field = |i: usize| {
+            record
+                .get(i)
+                .filter(|field| !field.is_empty())
+                .map(|field| field.to_string())
+        };
+        rows.push(ImportRow {
+            line,
+            name: field(0).unwrap_or_default(),
+            display_name: field(1),
+            group: field(2),
+            password: field(3),
+        });
+    }
+    Ok(rows)
+}
+
+fn check_row(row: &ImportRow, names: &HashSet<String>, seen: &HashSet<&str>) -> Option<String> {
+    if row.name.is_empty() {
+        return Some("The name is empty.".to_string());
+    }
+    if names.contains(&row.name) {
+        return Some(format!("User name '{}' already exists.", row.name));
+    }
+    if seen.contains(row.name.as_str()) {
+        return Some(format!("User name '{}' appears more than once.", row.name));
+    }
+    row.password
+        .as_deref()
+        .and_then(|password| super::check_password(password).err())
+}
+
+/// Create many users at once, such as the students of a new term. Every row is checked
+/// first, and all the users are created in one transaction unless a row has an error or
+/// this is a dry run. Rows without a password get a generated one.
+pub async fn import(
+    storage: &dyn Storage,
+    rows: &[ImportRow],
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    let names: HashSet<String> = storage
+        .list_users()
+        .await
+        .map_err(|e| format!("Cannot read the users: {}", e))?
+        .into_iter()
+        .map(|user| user.name)
+        .collect();
+    let mut report = ImportReport {
+        dry_run,
+        ..ImportReport::default()
+    };
+    let mut seen: HashSet<&str> = HashSet::new();
+    for row in rows {
+        let error = check_row(row, &names, &seen);
+        if error.is_some() {
+            report.errors += 1;
+        }
+        seen.insert(&row.name);
+        report.rows.push(RowReport {
+            line: row.line,
+            name: row.name.clone(),
+            error,
+            ..RowReport::default()
+        });
+    }
+    if dry_run || report.errors > 0 || rows.is_empty() {
+        return Ok(report);
+    }
+
+    let passwords: Vec<(String, bool)> = rows
+        .iter()
+        .map(|row| match &row.password {
+            Some(password) => (password.clone(), false),
+            None => (
+                auth::new_token()[..GENERATED_PASSWORD_LEN].to_string(),
+                true,
+            ),
+        })
+        .collect();
+    let plain: Vec<String> = passwords
+        .iter()
+        .map(|(password, _)| password.clone())
+        .collect();
+    let hashes = tokio::task::spawn_blocking(move || {
+        plain
+            .iter()
+            .map(|password| auth::hash_password(password))
+            .collect::<Result<Vec<String>, _>>()
+    })
+    .await
+    .map_err(|e| format!("Cannot hash the passwords: {}", e))?
+    .map_err(|e| format!("Cannot hash the passwords: {}", e))?;
+    let users: Vec<NewUser> = rows
+        .iter()
+        .zip(hashes)
+        .map(|(row, password_hash)| NewUser {
+            name: row.name.clone(),
+            display_name: row.display_name.clone(),
+            group: row.group.clone(),
+            password_hash,
+        })
+        .collect();
+    let ids = storage
+        .import_users(&users)
+        .await
+        .map_err(|e| format!("Cannot create the users: {}", e))?;
+    for ((row, (user_id, group_id)), (password, generated)) in
+        report.rows.iter_mut().zip(ids).zip(passwords)
+    {
+        row.user_id = Some(user_id);
+        row.group_id = group_id;
+        if generated {
+            row.password = Some(password);
+        }
+    }
+    report.created = report.rows.len() as u32;
+    log::info!("Imported {} user(s)", report.created);
+    Ok(report)
+}
@@ -1,120 +1,152 @@
 use actix_web::web::Data;
 use actix_web::HttpResponse;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, Result};
+use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
-// use web::{Json, Path};
-use crate::config::Ids;
-use crate::error_log;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+// use web::{Json, Path};
+use crate::audit;
+use crate::auth;
+use crate::config;
+use crate::contests;
+use crate::error_log;
+use crate::handler::jobs::RankFilter;
+use crate::storage::Storage;
+
+pub mod import;
 
-#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
 pub struct SerdeUser {
-    id: u32,
-    name: String,
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub role: Role,
+    /// Shown instead of the login name where set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Deactivated users cannot log in or submit and are left out of ranklists.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deactivated_time: Option<String>,
 }
 
-pub async fn user_name_exists(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    user_name: &str,
-) -> bool {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM users WHERE name = :name;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return true;
-        }
-    };
-    stmt.exists(&[(":name", user_name)]).unwrap()
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Everything, including users, contests, rejudging and the `/internal` endpoints.
+    Admin,
+    /// Creates problems and manages the ones they own.
+    Setter,
+    /// Submits, and sees the source and case details of their own jobs only.
+    #[default]
+    Contestant,
+    /// Sees everything a contestant does, but cannot submit.
+    Observer,
 }
 
-pub async fn user_exists(pool: Data<Mutex<Pool<SqliteConnectionManager>>>, user_id: u32) -> bool {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM users WHERE id = :id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return true;
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Setter => "setter",
+            Role::Contestant => "contestant",
+            Role::Observer => "observer",
         }
-    };
-    stmt.exists(&[(":id", user_id.to_string().as_str())])
-        .unwrap()
-}
+    }
 
-pub async fn get_user_id(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    user_name: &str,
-) -> Result<u32, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM users WHERE name = :name;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    /// Unknown names are read as `Contestant`, the role with the least rights to change things.
+    pub fn parse(name: &str) -> Role {
+        match name {
+            "admin" => Role::Admin,
+            "setter" => Role::Setter,
+            "observer" => Role::Observer,
+            _ => Role::Contestant,
         }
     }
-    if !stmt.exists(&[(":name", user_name)]).unwrap() {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
+}
+
+/// Submission statistics of a user, over all of their own jobs including unjudged ones.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct UserStatistics {
+    pub submissions: u32,
+    pub accepted: u32,
+    /// Problems with an accepted job.
+    pub solved_problem_ids: Vec<u32>,
+    /// Problems with jobs, none of them accepted.
+    pub attempted_problem_ids: Vec<u32>,
+    /// Jobs by result.
+    pub results: BTreeMap<String, u32>,
+    /// Jobs by language.
+    pub languages: BTreeMap<String, u32>,
+}
+
+/// A contest a user submitted in, with the score and rank of their row in the default
+/// ranklist of the contest.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct ContestRecord {
+    pub contest_id: u32,
+    pub contest_name: String,
+    /// The team the user submitted for, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<u32>,
+    pub score: f32,
+    /// Left out when the user is not on the ranklist, such as after leaving the contest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserProfile {
+    #[serde(flatten)]
+    pub user: SerdeUser,
+    pub statistics: UserStatistics,
+    pub contests: Vec<ContestRecord>,
+}
+
+pub async fn user_name_exists(storage: Data<dyn Storage>, user_name: &str) -> bool {
+    match storage.get_user_by_name(user_name).await {
+        Ok(user) => user.is_some(),
+        _ => true,
+    }
+}
+
+pub async fn user_exists(storage: Data<dyn Storage>, user_id: u32) -> bool {
+    match storage.get_user(user_id).await {
+        Ok(user) => user.is_some(),
+        _ => true,
+    }
+}
+
+pub async fn get_user_id(storage: Data<dyn Storage>, user_name: &str) -> Result<u32, HttpResponse> {
+    match storage.get_user_by_name(user_name).await {
+        Ok(Some(user)) => Ok(user.id),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
             "User {} not found.",
             user_name
-        )));
-    }
-    let iter = stmt.query_map(&[(":name", user_name)], |row| {
-        Ok(SerdeUser {
-            id: row.get(0)?,
-            name: row.get(1)?,
-        })
-    });
-    match iter {
-        Ok(mut ans) => Ok(ans.next().unwrap().expect("Unknown Error.").id),
+        ))),
         _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
 }
 
-pub async fn get_user(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-    user_id: u32,
-) -> Result<SerdeUser, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM users WHERE id = :id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
-        }
-    }
-    if !stmt
-        .exists(&[(":id", user_id.to_string().as_str())])
-        .unwrap()
-    {
-        return Err(error_log::NOT_FOUND::webmsg(&format!(
+pub async fn get_user(storage: Data<dyn Storage>, user_id: u32) -> Result<SerdeUser, HttpResponse> {
+    match storage.get_user(user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(error_log::NOT_FOUND::webmsg(&format!(
             "User {} not found.",
             user_id
-        )));
-    }
-    let iter = stmt.query_map(&[(":id", user_id.to_string().as_str())], |row| {
-        Ok(SerdeUser {
-            id: row.get(0)?,
-            name: row.get(1)?,
-        })
-    });
-    match iter {
-        Ok(mut ans) => Ok(ans.next().unwrap().expect("Unknown Error.")),
+        ))),
         _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
 }
 
 pub async fn update_user(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
     user_id: u32,
     user_name: &str,
 ) -> HttpResponse {
     println!("Users: Updating User...");
     let mut user: SerdeUser;
-    match get_user(pool.clone(), user_id).await {
+    match get_user(storage.clone(), user_id).await {
         Ok(ans) => {
             user = ans;
         }
@@ -124,18 +156,14 @@ pub async fn update_user(
     };
     if !user_name.eq(&user.name) {
         //Update the names if they are not the same one.
-        if user_name_exists(pool.clone(), user_name).await {
+        if user_name_exists(storage.clone(), user_name).await {
             return error_log::INVALID_ARGUMENT::webmsg(&format!(
                 "User name '{}' already exists.",
                 user_name
             ));
         } else {
             println!("updating now. {}: {} -> {}", user_id, user.name, user_name);
-            let data = pool.lock().await.get().unwrap();
-            let _ = data.execute(
-                "UPDATE users SET name = ?1 WHERE id = ?2;",
-                params![user_name.to_string(), user_id as i32],
-            );
+            let _ = storage.rename_user(user_id, user_name).await;
         }
     }
     user.name = user_name.to_string();
@@ -143,60 +171,237 @@ pub async fn update_user(
 }
 
 pub async fn create_user(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
+    storage: Data<dyn Storage>,
     user_name: &str,
-    ids: Data<Arc<Mutex<Ids>>>,
 ) -> Result<SerdeUser, HttpResponse> {
     println!("Users: Creating User...");
 
     let user_id: u32;
-    // Check if user name exists before increase the counter.
-    if user_name_exists(pool.clone(), user_name).await {
+    if user_name_exists(storage.clone(), user_name).await {
         return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
             "User name '{}' already exists.",
             user_name
         )));
     } else {
-        user_id = ids.lock().await.usersid;
-        ids.lock().await.usersid += 1;
-        println!("User ID: {}", user_id);
-        let data = pool.lock().await.get().unwrap();
-        if let Err(_) = data.execute(
-            "INSERT INTO users (id, name) VALUES (?1, ?2);",
-            params![user_id, user_name],
-        ) {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+        match storage.insert_user(user_name).await {
+            Ok(id) => user_id = id,
+            // Someone else took the name since the check.
+            Err(_) if user_name_exists(storage.clone(), user_name).await => {
+                return Err(error_log::INVALID_ARGUMENT::webmsg(&format!(
+                    "User name '{}' already exists.",
+                    user_name
+                )));
+            }
+            Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
         }
+        println!("User ID: {}", user_id);
     }
 
     Ok(SerdeUser {
         id: user_id,
         name: user_name.to_string(),
+        role: Role::default(),
+        display_name: None,
+        deactivated_time: None,
+    })
+}
+
+pub async fn get_users(storage: Data<dyn Storage>) -> Result<Vec<SerdeUser>, HttpResponse> {
+    match storage.list_users().await {
+        Ok(users) => Ok(users),
+        _ => Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    }
+}
+
+pub async fn get_profile(
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, config::Problem>>>>,
+    user_id: u32,
+) -> Result<UserProfile, HttpResponse> {
+    let user = get_user(storage.clone(), user_id).await?;
+    let statistics = storage.user_statistics(user_id).await;
+    let records = storage.user_contests(user_id).await;
+    let (statistics, records) = match (statistics, records) {
+        (Ok(statistics), Ok(records)) => (statistics, records),
+        _ => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    let mut contests: Vec<ContestRecord> = vec![];
+    for mut record in records {
+        let contest = contests::get_contest(storage.clone(), record.contest_id).await?;
+        // Jobs for a team that is not registered count for the user alone, as in the ranklist.
+        record.team_id = record.team_id.filter(|id| contest.team_ids.contains(id));
+        if contests
+            .iter()
+            .any(|c| c.contest_id == record.contest_id && c.team_id == record.team_id)
+        {
+            continue;
+        }
+        let ranklist = contests::get_ranklist(
+            storage.clone(),
+            prob_map.clone(),
+            RankFilter::default(),
+            record.contest_id,
+        )
+        .await?;
+        let row = ranklist.iter().find(|row| match record.team_id {
+            Some(team_id) => row.team.as_ref().map(|team| team.id) == Some(team_id),
+            None => row.user.as_ref().map(|user| user.id) == Some(user_id),
+        });
+        if let Some(row) = row {
+            record.score = row.scores.iter().sum();
+            record.rank = Some(row.rank);
+        }
+        contests.push(record);
+    }
+    Ok(UserProfile {
+        user,
+        statistics,
+        contests,
     })
 }
 
-pub async fn get_users(
-    pool: Data<Mutex<Pool<SqliteConnectionManager>>>,
-) -> Result<Vec<SerdeUser>, HttpResponse> {
-    let data = pool.lock().await.get().unwrap();
-    let mut stmt;
-    match data.prepare("SELECT * FROM users ORDER BY id;") {
-        Ok(s) => stmt = s,
-        _ => {
-            return Err(error_log::EXTERNAL::webmsg("Database Error."));
+/// Refuse passwords that are too short, before anything is written.
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < auth::MIN_PASSWORD_LEN {
+        return Err(format!(
+            "Password must have at least {} characters.",
+            auth::MIN_PASSWORD_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Set a new password and end every session of the user.
+pub async fn set_password(
+    storage: Data<dyn Storage>,
+    user_id: u32,
+    password: &str,
+) -> Result<(), HttpResponse> {
+    if let Err(e) = check_password(password) {
+        return Err(error_log::INVALID_ARGUMENT::webmsg(&e));
+    }
+    let password = password.to_string();
+    let hash = match actix_web::web::block(move || auth::hash_password(&password)).await {
+        Ok(Ok(hash)) => hash,
+        _ => return Err(error_log::INTERNAL::webmsg("Cannot hash the password.")),
+    };
+    if storage.set_password_hash(user_id, &hash).await.is_err()
+        || storage.delete_sessions(user_id).await.is_err()
+    {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    Ok(())
+}
+
+pub async fn set_role(
+    storage: Data<dyn Storage>,
+    user_id: u32,
+    role: Role,
+) -> Result<SerdeUser, HttpResponse> {
+    if storage.set_role(user_id, role).await.is_err() {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    get_user(storage, user_id).await
+}
+
+/// Set the name shown for a user, or remove it with an empty name.
+pub async fn set_display_name(
+    storage: Data<dyn Storage>,
+    user_id: u32,
+    display_name: &str,
+) -> Result<SerdeUser, HttpResponse> {
+    let display_name = Some(display_name).filter(|name| !name.is_empty());
+    if storage
+        .set_display_name(user_id, display_name)
+        .await
+        .is_err()
+    {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    get_user(storage, user_id).await
+}
+
+fn refuse_self(actor_id: u32, user_id: u32, action: &str) -> Result<(), String> {
+    if actor_id == user_id {
+        return Err(format!("Admins cannot {} themselves.", action));
+    }
+    Ok(())
+}
+
+/// Deactivate a user and end their sessions. Their API keys stop working until they are
+/// activated again.
+pub async fn deactivate(
+    storage: Data<dyn Storage>,
+    actor_id: u32,
+    user_id: u32,
+) -> Result<SerdeUser, HttpResponse> {
+    if let Err(e) = refuse_self(actor_id, user_id, "deactivate") {
+        return Err(error_log::INVALID_STATE::webmsg(&e));
+    }
+    let user = get_user(storage.clone(), user_id).await?;
+    if user.deactivated_time.is_some() {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} is already deactivated.",
+            user_id
+        )));
+    }
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    if storage.set_deactivated(user_id, Some(&now)).await.is_err()
+        || storage.delete_sessions(user_id).await.is_err()
+    {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    audit::record(storage.clone(), actor_id, "deactivate", user_id, "").await?;
+    get_user(storage, user_id).await
+}
+
+pub async fn activate(
+    storage: Data<dyn Storage>,
+    actor_id: u32,
+    user_id: u32,
+) -> Result<SerdeUser, HttpResponse> {
+    let user = get_user(storage.clone(), user_id).await?;
+    if user.deactivated_time.is_none() {
+        return Err(error_log::INVALID_STATE::webmsg(&format!(
+            "User {} is not deactivated.",
+            user_id
+        )));
+    }
+    if storage.set_deactivated(user_id, None).await.is_err() {
+        return Err(error_log::EXTERNAL::webmsg("Database Error."));
+    }
+    audit::record(storage.clone(), actor_id, "activate", user_id, "").await?;
+    get_user(storage, user_id).await
+}
+
+/// Replace the name of a user, deactivate them and strip the source code of their jobs.
+/// Their jobs stay for statistics. Returns the user and the ids of their jobs.
+pub async fn anonymize(
+    storage: Data<dyn Storage>,
+    actor_id: u32,
+    user_id: u32,
+) -> Result<(SerdeUser, Vec<u32>), HttpResponse> {
+    if let Err(e) = refuse_self(actor_id, user_id, "anonymize") {
+        return Err(error_log::INVALID_STATE::webmsg(&e));
+    }
+    get_user(storage.clone(), user_id).await?;
+    let name = format!("anonymous-{}", user_id);
+    match storage.get_user_by_name(&name).await {
+        Ok(Some(user)) if user.id != user_id => {
+            return Err(error_log::INVALID_STATE::webmsg(&format!(
+                "User {} is already named {}, rename them first.",
+                user.id, name
+            )))
         }
+        Ok(_) => {}
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
     }
-    let iter = stmt
-        .query_map([], |row| {
-            Ok(SerdeUser {
-                id: row.get(0)?,
-                name: row.get(1)?,
-            })
-        })
-        .expect("Unknown Error.");
-    let mut users: Vec<SerdeUser> = vec![];
-    for user in iter {
-        users.push(user.unwrap());
-    }
-    Ok(users)
+    let mut entry = audit::entry(actor_id, "anonymize", user_id, "");
+    let job_ids = match storage.anonymize_user(&name, &entry).await {
+        Ok(job_ids) => job_ids,
+        Err(_) => return Err(error_log::EXTERNAL::webmsg("Database Error.")),
+    };
+    entry.detail = format!("{} job(s)", job_ids.len());
+    audit::log(&entry);
+    Ok((get_user(storage, user_id).await?, job_ids))
 }
@@ -0,0 +1,177 @@
+//! Problems, contests and ranklists can be read without a login, except the problems of a
+//! contest that has not started. The internal endpoints are for admins only.
+
+use actix_web::http::Method;
+use actix_web::web::{self, Data};
+use actix_web::{test, App, HttpResponse};
+use oj::auth::policy::{self, Access};
+use oj::auth::{self, Session};
+use oj::config::{Config, Problem};
+use oj::contests::SerdeContest;
+use oj::handler;
+use oj::storage::{self, Storage};
+use oj::users::Role;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn problem(id: u32) -> Problem {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "name": format!("problem {}", id),
+        "type": "standard",
+        "misc": {},
+        "cases": []
+    }))
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn public_reads() {
+    let dir = std::env::temp_dir().join(format!("mroj-access-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = Config::default();
+    config.storage.database_path = dir.join("data.db").to_str().unwrap().to_string();
+    config.storage.problem_data_dir = dir.join("problems").to_str().unwrap().to_string();
+    let storage = storage::open(&config.storage, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    let storage: Data<dyn Storage> = Data::from(storage);
+    // Problem 1 is only in a contest that has not started.
+    for (name, from) in [
+        ("past", "2022-08-27T00:00:00.000Z"),
+        ("future", "9999-01-01T00:00:00.000Z"),
+    ] {
+        storage
+            .insert_contest(&SerdeContest {
+                name: name.to_string(),
+                from: from.to_string(),
+                to: "9999-12-31T00:00:00.000Z".to_string(),
+                problem_ids: if name == "past" { vec![0] } else { vec![1] },
+                ..SerdeContest::default()
+            })
+            .await
+            .unwrap();
+    }
+    let prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>> = Data::new(Arc::new(Mutex::new(
+        [(0, problem(0)), (1, problem(1))].into_iter().collect(),
+    )));
+    let app = test::init_service(
+        App::new()
+            .wrap(auth::Authenticate)
+            .app_data(Data::new(Arc::new(Mutex::new(config))))
+            .app_data(prob_map)
+            .app_data(storage)
+            .configure(handler::route),
+    )
+    .await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+    for uri in [
+        "/problems",
+        "/problems/0",
+        "/problems/0/statement",
+        "/contests",
+        "/contests/1",
+        "/contests/1/ranklist",
+    ] {
+        let res = test::call_service(&app, get(uri)).await;
+        assert_eq!(res.status(), 200, "{}", uri);
+    }
+    let problems: serde_json::Value = test::call_and_read_body_json(&app, get("/problems")).await;
+    assert_eq!(problems.as_array().unwrap().len(), 1);
+    assert_eq!(problems[0]["id"], 0);
+    // Hidden problems and missing images are not found, not refused.
+    for uri in [
+        "/problems/1",
+        "/problems/1/statement",
+        "/problems/1/images/a.png",
+        "/problems/0/images/a.png",
+    ] {
+        let res = test::call_service(&app, get(uri)).await;
+        assert_eq!(res.status(), 404, "{}", uri);
+    }
+    let res = test::call_service(&app, get("/problems?contest_id=2")).await;
+    assert_eq!(res.status(), 400);
+    // Everything else still needs a login.
+    for uri in ["/users", "/users/0", "/jobs", "/teams", "/groups"] {
+        let res = test::call_service(&app, get(uri)).await;
+        assert_eq!(res.status(), 401, "{}", uri);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The internal endpoints are registered in `main`, outside `handler::route`, so stand-ins are
+/// mounted at the same paths here.
+#[actix_web::test]
+async fn internal_endpoints_are_for_admins() {
+    let endpoints = [
+        (Method::POST, "/internal/exit"),
+        (Method::POST, "/internal/reload"),
+        (Method::GET, "/internal/disk"),
+        (Method::GET, "/internal/audit"),
+    ];
+    for (method, path) in &endpoints {
+        assert_eq!(
+            policy::access(method, path),
+            Access::Roles(&[Role::Admin]),
+            "{}",
+            path
+        );
+    }
+
+    let dir = std::env::temp_dir().join(format!("mroj-internal-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = oj::config::Storage {
+        database_path: dir.join("data.db").to_str().unwrap().to_string(),
+        ..oj::config::Storage::default()
+    };
+    let storage = storage::open(&config, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    for (name, role, token) in [
+        ("root", Role::Admin, "admin-token"),
+        ("sam", Role::Setter, "setter-token"),
+    ] {
+        let user_id = storage.insert_user(name).await.unwrap();
+        storage.set_role(user_id, role).await.unwrap();
+        storage
+            .insert_session(&Session {
+                token_hash: auth::token_hash(token),
+                user_id,
+                created_time: "2022-08-27T00:00:00.000Z".to_string(),
+                expires_time: "9999-12-31T00:00:00.000Z".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+    let storage: Data<dyn Storage> = Data::from(storage);
+    let mut app = App::new().wrap(auth::Authenticate).app_data(storage);
+    for (method, path) in &endpoints {
+        app = app
+            .service(web::resource(*path).route(web::method(method.clone()).to(HttpResponse::Ok)));
+    }
+    let app = test::init_service(app).await;
+
+    for (method, path) in &endpoints {
+        let request = |token: Option<&str>| {
+            let req = test::TestRequest::default()
+                .method(method.clone())
+                .uri(path);
+            match token {
+                Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+                None => req,
+            }
+            .to_request()
+        };
+        for (token, status) in [
+            (None, 401),
+            (Some("setter-token"), 403),
+            (Some("admin-token"), 200),
+        ] {
+            let res = test::call_service(&app, request(token)).await;
+            assert_eq!(res.status(), status, "{} {:?}", path, token);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,171 @@
+//! Exports are read back into another database with new ids, and hot backups are complete copies.
+
+use oj::archive;
+use oj::config;
+use oj::contests::SerdeContest;
+use oj::groups::SerdeGroup;
+use oj::runner::{SerdeCase, SerdeJob, SerdeSubmission};
+use oj::storage::{self, Storage};
+use oj::teams::SerdeTeam;
+use oj::users::Role;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn temp_db(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("mroj-archive-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn open(path: &Path, flush: bool) -> Arc<dyn Storage> {
+    let config = config::Storage {
+        database_path: path.to_str().unwrap().to_string(),
+        ..config::Storage::default()
+    };
+    let storage = storage::open(&config, flush).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    storage
+}
+
+fn job(user_id: u32, contest_id: u32) -> SerdeJob {
+    SerdeJob {
+        id: 0,
+        created_time: "2022-08-27T02:05:00.000Z".to_string(),
+        updated_time: "2022-08-27T02:05:01.000Z".to_string(),
+        submission: SerdeSubmission {
+            source_code: format!("// user {}", user_id),
+            language: "Rust".to_string(),
+            user_id,
+            contest_id,
+            problem_id: 0,
+            team_id: None,
+        },
+        state: "Finished".to_string(),
+        result: "Accepted".to_string(),
+        score: 100.0,
+        cases: vec![SerdeCase {
+            id: 0,
+            result: "Compilation Success".to_string(),
+            ..SerdeCase::default()
+        }],
+    }
+}
+
+#[tokio::test]
+async fn export_and_import() {
+    let (from_path, to_path) = (temp_db("from"), temp_db("to"));
+    let from = open(&from_path, true).await;
+    for name in ["root", "alice", "bob"] {
+        from.insert_user(name).await.unwrap();
+    }
+    from.set_role(1, Role::Setter).await.unwrap();
+    let team = SerdeTeam {
+        name: "red".to_string(),
+        member_ids: vec![1],
+        ..SerdeTeam::default()
+    };
+    from.insert_team(&team).await.unwrap();
+    let group = SerdeGroup {
+        name: "class-a".to_string(),
+        member_ids: vec![2],
+        ..SerdeGroup::default()
+    };
+    from.insert_group(&group).await.unwrap();
+    let contest = SerdeContest {
+        name: "weekly".to_string(),
+        problem_ids: vec![0],
+        user_ids: vec![2],
+        team_ids: vec![1],
+        group_ids: vec![1],
+        ..SerdeContest::default()
+    };
+    from.insert_contest(&contest).await.unwrap();
+    from.insert_job(&job(1, 0)).await.unwrap();
+    from.insert_job(&job(2, 1)).await.unwrap();
+    let mut team_job = job(1, 1);
+    team_job.submission.team_id = Some(1);
+    from.insert_job(&team_job).await.unwrap();
+
+    let mut export = vec![];
+    let summary = archive::export(from.as_ref(), &mut export).await.unwrap();
+    assert_eq!(
+        (
+            summary.users,
+            summary.teams,
+            summary.groups,
+            summary.contests,
+            summary.jobs
+        ),
+        (3, 1, 1, 1, 3)
+    );
+
+    // The target already has users of its own, so "bob" is merged and the rest move up.
+    let to = open(&to_path, true).await;
+    for name in ["root", "carol", "bob"] {
+        to.insert_user(name).await.unwrap();
+    }
+    let summary = archive::import(to.as_ref(), &mut export.as_slice())
+        .await
+        .unwrap();
+    assert_eq!(
+        (
+            summary.users,
+            summary.teams,
+            summary.groups,
+            summary.contests,
+            summary.jobs
+        ),
+        (1, 1, 1, 1, 3)
+    );
+    assert_eq!(summary.conflicts.len(), 2);
+
+    let alice = to.get_user_by_name("alice").await.unwrap().unwrap();
+    assert_eq!((alice.id, alice.role), (3, Role::Setter));
+    let contest = to.get_contest(1).await.unwrap().unwrap();
+    assert_eq!((contest.user_ids, contest.team_ids), (vec![2], vec![1]));
+    assert_eq!(to.get_team(1).await.unwrap().unwrap().member_ids, [3]);
+    assert_eq!(contest.group_ids, [1]);
+    assert_eq!(to.get_group(1).await.unwrap().unwrap().member_ids, [2]);
+    let imported = to.get_job(2).await.unwrap().unwrap();
+    assert_eq!(imported.submission.team_id, Some(1));
+    let imported = to.get_job(1).await.unwrap().unwrap();
+    assert_eq!(imported.submission.user_id, 2);
+    assert_eq!(imported.submission.contest_id, 1);
+    assert_eq!(imported.submission.source_code, "// user 2");
+    assert_eq!(imported.cases[0].result, "Compilation Success");
+
+    let _ = std::fs::remove_file(&from_path);
+    let _ = std::fs::remove_file(&to_path);
+}
+
+#[tokio::test]
+async fn import_rejects_newer_formats() {
+    let path = temp_db("newer");
+    let storage = open(&path, true).await;
+    let export = format!(
+        "{{\"record\":\"header\",\"format\":\"mroj-export\",\"version\":{},\"schema_version\":2,\"exported_time\":\"\"}}\n",
+        archive::FORMAT_VERSION + 1
+    );
+    assert!(archive::import(storage.as_ref(), &mut export.as_bytes())
+        .await
+        .is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn backup() {
+    let (path, backup_path) = (temp_db("live"), temp_db("backup"));
+    let storage = open(&path, true).await;
+    storage.insert_user("root").await.unwrap();
+    storage.insert_job(&job(0, 0)).await.unwrap();
+    storage.backup(backup_path.to_str().unwrap()).await.unwrap();
+    // The target must not exist, so that an old backup is never overwritten.
+    assert!(storage.backup(backup_path.to_str().unwrap()).await.is_err());
+
+    let copy = open(&backup_path, false).await;
+    assert_eq!(copy.list_users().await.unwrap().len(), 1);
+    assert_eq!(copy.get_job(0).await.unwrap().unwrap().score, 100.0);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&backup_path);
+}
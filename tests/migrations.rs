@@ -0,0 +1,55 @@
+//! Databases created before schema versioning are migrated without losing rows.
+
+use oj::config;
+use oj::migrations::{self, MIGRATIONS};
+use oj::storage;
+
+#[tokio::test]
+async fn duplicate_rows_stop_the_migration() {
+    let path = std::env::temp_dir().join(format!("mroj-migrations-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let data = rusqlite::Connection::open(&path).unwrap();
+    data.execute_batch(MIGRATIONS[0].sqlite).unwrap();
+    data.execute_batch(
+        "INSERT INTO users (id, name) VALUES (0, 'root'), (1, 'alice'), (1, 'alice');
+        INSERT INTO problems (id, content) VALUES (0, '{}');",
+    )
+    .unwrap();
+    let count = |table: &str| -> u32 {
+        data.query_row(&format!("SELECT COUNT(*) FROM {};", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+
+    let config = config::Storage {
+        database_path: path.to_str().unwrap().to_string(),
+        ..config::Storage::default()
+    };
+    let storage = storage::open(&config, false).await.unwrap();
+    let error = migrations::migrate(storage.as_ref()).await.unwrap_err();
+    assert!(error.starts_with("Migration 2 failed"), "{}", error);
+    assert!(error.contains("UNIQUE"), "{}", error);
+    // The failed migration is rolled back, with every row still there.
+    assert_eq!(storage.schema_version().await.unwrap(), 1);
+    assert_eq!(count("users"), 3);
+
+    data.execute("DELETE FROM users WHERE rowid = 3;", [])
+        .unwrap();
+    migrations::migrate(storage.as_ref()).await.unwrap();
+    assert_eq!(
+        storage.schema_version().await.unwrap(),
+        migrations::latest_version()
+    );
+    let names: Vec<String> = storage
+        .list_users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.name)
+        .collect();
+    assert_eq!(names, ["root", "alice"]);
+    assert_eq!(count("problems"), 1);
+    drop(data);
+    std::fs::remove_file(&path).unwrap();
+}
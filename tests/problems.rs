@@ -0,0 +1,149 @@
+//! Problems created through the API only read test data from their own data directory.
+
+use actix_web::web::Data;
+use oj::config::Problem;
+use oj::problems;
+use oj::storage::{self, Storage};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+struct Setup {
+    storage: Data<dyn Storage>,
+    prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>>,
+    data_root: String,
+}
+
+async fn setup(name: &str) -> Setup {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("mroj-problems-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = oj::config::Storage {
+        database_path: dir.join("data.db").to_str().unwrap().to_string(),
+        ..oj::config::Storage::default()
+    };
+    let storage = storage::open(&config, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    Setup {
+        storage: Data::from(storage),
+        prob_map: Data::new(Arc::new(Mutex::new(HashMap::new()))),
+        data_root: dir.join("problems").to_str().unwrap().to_string(),
+    }
+}
+
+fn problem(input_file: &str) -> Problem {
+    serde_json::from_value(serde_json::json!({
+        "id": 0,
+        "name": "sum",
+        "type": "standard",
+        "misc": {},
+        "cases": [{
+            "score": 100.0,
+            "input_file": input_file,
+            "answer_file": "1.ans",
+            "time_limit": 1000000,
+            "memory_limit": 0,
+            "sample": true
+        }]
+    }))
+    .unwrap()
+}
+
+async fn error_reason(res: actix_web::HttpResponse) -> String {
+    assert_eq!(res.status(), 400);
+    let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    body["reason"].as_str().unwrap().to_string()
+}
+
+#[actix_web::test]
+async fn case_files_stay_in_the_data_directory() {
+    let s = setup("paths").await;
+    for file in ["/etc/passwd", "../../config.json", "data/../../x", ""] {
+        let res = problems::create_problem(
+            problem(file),
+            Some(1),
+            &s.data_root,
+            s.storage.clone(),
+            s.prob_map.clone(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_reason(res).await, "ERR_INVALID_ARGUMENT", "{}", file);
+    }
+    assert!(s.storage.list_problems().await.unwrap().is_empty());
+
+    let prob = problems::create_problem(
+        problem("1.in"),
+        Some(1),
+        &s.data_root,
+        s.storage.clone(),
+        s.prob_map.clone(),
+    )
+    .await
+    .unwrap();
+    let data = problems::data_dir(&s.data_root, 1);
+    assert_eq!(prob.cases[0].input_file, format!("{}/1.in", data));
+    // Resolved paths are accepted again by updates.
+    let prob = problems::update_problem(prob, &s.data_root, s.storage.clone(), s.prob_map.clone())
+        .await
+        .unwrap();
+    assert_eq!(prob.cases[0].input_file, format!("{}/1.in", data));
+}
+
+fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, content) in files {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[actix_web::test]
+async fn failed_uploads_keep_the_old_data() {
+    let s = setup("upload").await;
+    problems::create_problem(
+        problem("1.in"),
+        Some(1),
+        &s.data_root,
+        s.storage.clone(),
+        s.prob_map.clone(),
+    )
+    .await
+    .unwrap();
+    let upload = |files: &[(&str, &str)]| {
+        let body = archive(files);
+        let (data_root, storage, prob_map) =
+            (s.data_root.clone(), s.storage.clone(), s.prob_map.clone());
+        async move { problems::upload_data(1, &body, &data_root, storage, prob_map).await }
+    };
+    let data = problems::data_dir(&s.data_root, 1);
+    let read = |name: &str| std::fs::read_to_string(format!("{}/{}", data, name)).unwrap();
+
+    upload(&[("1.in", "1 2\n"), ("1.ans", "3\n")])
+        .await
+        .unwrap();
+    assert_eq!(read("1.in"), "1 2\n");
+    let res = upload(&[("1.in", "5 5\n"), ("../evil", "x")])
+        .await
+        .unwrap_err();
+    assert_eq!(error_reason(res).await, "ERR_INVALID_ARGUMENT");
+    assert_eq!(
+        (read("1.in"), read("1.ans")),
+        ("1 2\n".into(), "3\n".into())
+    );
+    upload(&[("1.in", "2 2\n"), ("1.ans", "4\n")])
+        .await
+        .unwrap();
+    assert_eq!(read("1.in"), "2 2\n");
+    // Nothing is left next to the data.
+    let entries = std::fs::read_dir(problems::problem_dir(&s.data_root, 1))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(entries, ["data"]);
+}
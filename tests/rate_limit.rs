@@ -0,0 +1,190 @@
+//! Token buckets refill over time, each client has its own, and requests refused to logged in
+//! users cost nothing.
+
+use actix_web::http::Method;
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::web::Data;
+use actix_web::App;
+use oj::auth::rate_limit::{self, Client, RateLimiter};
+use oj::auth::{self, ApiKey, Scope, Session};
+use oj::config::{Config, Problem, RateLimit};
+use oj::handler;
+use oj::storage::{self, Storage};
+use oj::users::Role;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+#[test]
+fn buckets() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        burst: 3,
+        per_minute: 6,
+    };
+    let start = Instant::now();
+    let alice = Client::User(1);
+    for _ in 0..3 {
+        assert!(limiter.check(alice, &limit, start).is_ok());
+    }
+    // A token comes back every 10 seconds.
+    let wait = limiter.check(alice, &limit, start).unwrap_err();
+    assert_eq!(wait.as_secs(), 10);
+    let later = start + Duration::from_secs(4);
+    assert_eq!(
+        limiter.check(alice, &limit, later).unwrap_err().as_secs(),
+        6
+    );
+    let later = start + Duration::from_secs(10);
+    assert!(limiter.check(alice, &limit, later).is_ok());
+    assert!(limiter.check(alice, &limit, later).is_err());
+    // Other clients are not affected, and the bucket never holds more than `burst`.
+    let ip = Client::Ip("127.0.0.1".parse().unwrap());
+    assert!(limiter.check(ip, &limit, later).is_ok());
+    let much_later = start + Duration::from_secs(3600);
+    for _ in 0..3 {
+        assert!(limiter.check(alice, &limit, much_later).is_ok());
+    }
+    assert!(limiter.check(alice, &limit, much_later).is_err());
+}
+
+#[test]
+fn disabled() {
+    let limiter = RateLimiter::default();
+    let now = Instant::now();
+    for _ in 0..100 {
+        assert!(limiter
+            .check(Client::User(1), &RateLimit::default(), now)
+            .is_ok());
+    }
+    assert!(rate_limit::limited(&Method::POST, "/jobs"));
+    assert!(!rate_limit::limited(&Method::GET, "/jobs"));
+}
+
+#[test]
+fn least_recently_used_buckets_are_dropped() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        burst: 1,
+        per_minute: 1,
+    };
+    let start = Instant::now();
+    let at = |i: usize| start + Duration::from_millis(i as u64);
+    // No bucket refills in time, so none of them is dropped for being full.
+    for i in 0..rate_limit::MAX_BUCKETS {
+        assert!(limiter.check(Client::User(i as u32), &limit, at(i)).is_ok());
+    }
+    let newest = rate_limit::MAX_BUCKETS as u32 - 1;
+    assert!(limiter
+        .check(Client::User(newest), &limit, at(5000))
+        .is_err());
+    assert!(limiter
+        .check(Client::User(newest + 1), &limit, at(5001))
+        .is_ok());
+    // The oldest quarter is gone and starts over with a full bucket, the newest is kept.
+    assert!(limiter.check(Client::User(0), &limit, at(5002)).is_ok());
+    assert!(limiter
+        .check(Client::User(newest), &limit, at(5003))
+        .is_err());
+    assert!(limiter
+        .check(Client::User(newest + 1), &limit, at(5004))
+        .is_err());
+}
+
+#[actix_web::test]
+async fn refused_requests_are_free() {
+    let path = std::env::temp_dir().join(format!("mroj-rate-limit-{}.db", std::process::id()));
+    let mut config = Config::default();
+    config.storage.database_path = path.to_str().unwrap().to_string();
+    config.rate_limit = RateLimit {
+        burst: 1,
+        per_minute: 1,
+    };
+    let storage = storage::open(&config.storage, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    for name in ["root", "alice", "bob"] {
+        storage.insert_user(name).await.unwrap();
+    }
+    storage.set_role(2, Role::Observer).await.unwrap();
+    for (token, user_id) in [("alice-token", 1), ("bob-token", 2)] {
+        storage
+            .insert_session(&Session {
+                token_hash: auth::token_hash(token),
+                user_id,
+                created_time: "2022-08-27T00:00:00.000Z".to_string(),
+                expires_time: "9999-12-31T00:00:00.000Z".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+    // A key of alice that cannot submit.
+    storage
+        .insert_api_key(&ApiKey {
+            id: 0,
+            user_id: 1,
+            name: "ci".to_string(),
+            scopes: vec![Scope::ReadJobs],
+            created_time: "2022-08-27T00:00:00.000Z".to_string(),
+            expires_time: None,
+            last_used_time: None,
+            key_hash: auth::token_hash("mroj_read"),
+        })
+        .await
+        .unwrap();
+    let storage: Data<dyn Storage> = Data::from(storage);
+    let prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>> =
+        Data::new(Arc::new(Mutex::new(HashMap::new())));
+    let app = init_service(
+        App::new()
+            .wrap(auth::Authenticate)
+            .app_data(Data::new(Arc::new(Mutex::new(config))))
+            .app_data(prob_map)
+            .app_data(storage)
+            .app_data(Data::new(RateLimiter::default()))
+            .configure(handler::route),
+    )
+    .await;
+    let submit = |token: &str| {
+        TestRequest::post()
+            .uri("/jobs")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({
+                "source_code": "",
+                "language": "Rust",
+                "contest_id": 0,
+                "problem_id": 0
+            }))
+            .to_request()
+    };
+
+    for token in ["mroj_read", "bob-token", "mroj_read", "bob-token"] {
+        let res = call_service(&app, submit(token)).await;
+        assert_eq!(res.status(), 403, "{}", token);
+    }
+    // Alice still has her token, the unknown language is only found out by the handler.
+    let res = call_service(&app, submit("alice-token")).await;
+    assert_eq!(res.status(), 404);
+    let res = call_service(&app, submit("alice-token")).await;
+    assert!(res.headers().contains_key("Retry-After"));
+
+    // Requests without a valid login cost a token of their IP address before they are refused.
+    let anonymous = |ip: &str, token: Option<&str>| {
+        let mut req = TestRequest::post()
+            .uri("/jobs")
+            .peer_addr(format!("{}:4000", ip).parse().unwrap());
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        req.to_request()
+    };
+    let res = call_service(&app, anonymous("10.0.0.1", None)).await;
+    assert_eq!(res.status(), 401);
+    for token in [None, Some("expired-token")] {
+        let res = call_service(&app, anonymous("10.0.0.1", token)).await;
+        assert!(res.headers().contains_key("Retry-After"));
+    }
+    let res = call_service(&app, anonymous("10.0.0.2", Some("expired-token"))).await;
+    assert_eq!(res.status(), 401);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,812 @@
+//! The same cases run against every storage backend, each on a fresh database. SQLite always
+//! runs. PostgreSQL is ignored by default and runs with `--include-ignored` when
+//! `MROJ_TEST_POSTGRES_URL` points to a database the tests may wipe.
+
+use oj::audit::AuditEntry;
+use oj::auth::{ApiKey, Scope, Session};
+use oj::config::{self, Backend};
+use oj::contests::registration::{Registration, RegistrationMode, RegistrationState};
+use oj::contests::SerdeContest;
+use oj::groups::SerdeGroup;
+use oj::handler::jobs::{JobsFilter, SortOrder};
+use oj::migrations;
+use oj::runner::{SerdeCase, SerdeJob, SerdeSubmission};
+use oj::storage::{self, CaseUpdate, JobUpdate, Storage};
+use oj::teams::SerdeTeam;
+use oj::users::import::{self, NewUser};
+use oj::users::Role;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn job(id: u32, user_id: u32, problem_id: u32, language: &str, cases: u32) -> SerdeJob {
+    SerdeJob {
+        id,
+        created_time: format!("2022-08-27T02:05:{:02}.000Z", id),
+        updated_time: format!("2022-08-27T02:05:{:02}.000Z", id),
+        submission: SerdeSubmission {
+            source_code: format!("// job {}", id),
+            language: language.to_string(),
+            user_id,
+            contest_id: 0,
+            problem_id,
+            team_id: None,
+        },
+        state: "Queueing".to_string(),
+        result: "Waiting".to_string(),
+        score: 0.0,
+        cases: (0..=cases)
+            .map(|caseid| SerdeCase {
+                id: caseid,
+                result: "Waiting".to_string(),
+                ..SerdeCase::default()
+            })
+            .collect(),
+    }
+}
+
+fn ids(jobs: &[SerdeJob]) -> Vec<u32> {
+    jobs.iter().map(|job| job.id).collect()
+}
+
+/// Migrate a fresh database and add root, alice, carol and dave as users 0 to 3.
+async fn with_users(storage: &dyn Storage) {
+    migrations::migrate(storage).await.unwrap();
+    for name in ["root", "alice", "carol", "dave"] {
+        storage.insert_user(name).await.unwrap();
+    }
+}
+
+/// Add team 1 of carol and dave and contest 1, named monthly, to [`with_users`].
+async fn with_contest(storage: &dyn Storage) {
+    with_users(storage).await;
+    let team_id = storage
+        .insert_team(&SerdeTeam {
+            id: 0,
+            name: "blue".to_string(),
+            member_ids: vec![2, 3],
+        })
+        .await
+        .unwrap();
+    let contest_id = storage
+        .insert_contest(&SerdeContest {
+            name: "monthly".to_string(),
+            from: "2022-08-27T00:00:00.000Z".to_string(),
+            to: "2022-08-28T00:00:00.000Z".to_string(),
+            problem_ids: vec![1],
+            user_ids: vec![2, 3],
+            team_ids: vec![team_id],
+            ..SerdeContest::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!((team_id, contest_id), (1, 1));
+}
+
+/// Add jobs 0 to 3 to [`with_contest`]. Only job 3 is in the contest, for team 1.
+async fn with_jobs(storage: &dyn Storage) {
+    with_contest(storage).await;
+    let mut in_contest = job(3, 1, 1, "Rust", 1);
+    in_contest.submission.contest_id = 1;
+    in_contest.submission.team_id = Some(1);
+    let jobs = [
+        job(0, 1, 0, "Rust", 2),
+        job(1, 2, 0, "C++", 1),
+        job(2, 1, 1, "Rust", 1),
+        in_contest,
+    ];
+    for job in &jobs {
+        assert_eq!(storage.insert_job(job).await.unwrap(), job.id);
+    }
+}
+
+async fn schema(storage: &dyn Storage) {
+    assert_eq!(
+        migrations::migrate(storage).await.unwrap().len(),
+        migrations::MIGRATIONS.len()
+    );
+    assert_eq!(
+        storage.schema_version().await.unwrap(),
+        migrations::latest_version()
+    );
+    assert!(migrations::migrate(storage).await.unwrap().is_empty());
+}
+
+async fn users(storage: &dyn Storage) {
+    migrations::migrate(storage).await.unwrap();
+    for (id, name) in [(0, "root"), (1, "alice"), (2, "bob")] {
+        assert_eq!(storage.insert_user(name).await.unwrap(), id);
+    }
+    // A failed insert does not use up an id.
+    assert!(storage.insert_user("alice").await.is_err());
+    assert_eq!(storage.insert_user("dave").await.unwrap(), 3);
+    storage.rename_user(2, "carol").await.unwrap();
+    assert_eq!(storage.get_user(2).await.unwrap().unwrap().name, "carol");
+    assert_eq!(
+        storage.get_user(2).await.unwrap().unwrap().role,
+        Role::Contestant
+    );
+    storage.set_role(2, Role::Setter).await.unwrap();
+    assert_eq!(
+        storage.get_user(2).await.unwrap().unwrap().role,
+        Role::Setter
+    );
+    assert_eq!(
+        storage.get_user_by_name("alice").await.unwrap().unwrap().id,
+        1
+    );
+    assert!(storage.get_user(7).await.unwrap().is_none());
+    assert!(storage.get_user_by_name("bob").await.unwrap().is_none());
+    let names: Vec<String> = storage
+        .list_users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|u| u.name)
+        .collect();
+    assert_eq!(names, ["root", "alice", "carol", "dave"]);
+}
+
+async fn sessions(storage: &dyn Storage) {
+    with_users(storage).await;
+    assert!(storage.get_password_hash(1).await.unwrap().is_none());
+    storage
+        .set_password_hash(1, "$argon2id$hash")
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_password_hash(1).await.unwrap().unwrap(),
+        "$argon2id$hash"
+    );
+    assert!(storage.get_password_hash(7).await.unwrap().is_none());
+    let session = |token_hash: &str, user_id, created: &str, expires: &str| Session {
+        token_hash: token_hash.to_string(),
+        user_id,
+        created_time: created.to_string(),
+        expires_time: expires.to_string(),
+    };
+    let (created, expires) = ("2022-08-27T02:00:00.000Z", "2022-08-28T02:00:00.000Z");
+    storage
+        .insert_session(&session("a", 1, created, expires))
+        .await
+        .unwrap();
+    storage
+        .insert_session(&session("b", 1, created, expires))
+        .await
+        .unwrap();
+    storage.set_role(1, Role::Observer).await.unwrap();
+    let user = storage
+        .get_session_user("a", "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((user.name.as_str(), user.role), ("alice", Role::Observer));
+    assert!(storage
+        .get_session_user("a", "2022-08-28T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(storage
+        .get_session_user("c", "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_none());
+    storage.delete_session("a").await.unwrap();
+    let now = "2022-08-27T12:00:00.000Z";
+    assert!(storage.get_session_user("a", now).await.unwrap().is_none());
+    assert!(storage.get_session_user("b", now).await.unwrap().is_some());
+    // Creating a session drops the expired ones.
+    storage
+        .insert_session(&session(
+            "d",
+            3,
+            "2022-09-01T00:00:00.000Z",
+            "2022-09-02T00:00:00.000Z",
+        ))
+        .await
+        .unwrap();
+    assert!(storage.get_session_user("b", now).await.unwrap().is_none());
+    storage.delete_sessions(3).await.unwrap();
+    assert!(storage
+        .get_session_user("d", "2022-09-01T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn api_keys(storage: &dyn Storage) {
+    with_users(storage).await;
+    let mut key = ApiKey {
+        id: 0,
+        user_id: 2,
+        name: "ci".to_string(),
+        scopes: vec![Scope::Submit, Scope::ReadJobs],
+        created_time: "2022-08-27T00:00:00.000Z".to_string(),
+        expires_time: Some("2022-08-28T00:00:00.000Z".to_string()),
+        last_used_time: None,
+        key_hash: "k1".to_string(),
+    };
+    key.id = storage.insert_api_key(&key).await.unwrap();
+    assert_eq!(key.id, 1);
+    key.expires_time = None;
+    key.key_hash = "k2".to_string();
+    assert_eq!(storage.insert_api_key(&key).await.unwrap(), 2);
+    let (found, user) = storage
+        .get_api_key_user("k1", "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (found.id, found.scopes),
+        (1, vec![Scope::Submit, Scope::ReadJobs])
+    );
+    assert_eq!(user.name, "carol");
+    // Expired keys are not found, keys without an expiry always are.
+    assert!(storage
+        .get_api_key_user("k1", "2022-08-28T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(storage
+        .get_api_key_user("k2", "2030-01-01T00:00:00.000Z")
+        .await
+        .unwrap()
+        .is_some());
+    storage
+        .touch_api_key(2, "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap();
+    assert_eq!(
+        storage
+            .get_api_key(2)
+            .await
+            .unwrap()
+            .unwrap()
+            .last_used_time
+            .as_deref(),
+        Some("2022-08-27T12:00:00.000Z")
+    );
+    assert_eq!(storage.list_api_keys(2).await.unwrap().len(), 2);
+    assert!(storage.list_api_keys(1).await.unwrap().is_empty());
+    assert!(storage.delete_api_key(1).await.unwrap());
+    assert!(!storage.delete_api_key(1).await.unwrap());
+    assert!(storage.get_api_key(1).await.unwrap().is_none());
+    // Deactivated users cannot use their keys until they are activated again.
+    storage
+        .set_deactivated(2, Some("2022-08-27T12:00:00.000Z"))
+        .await
+        .unwrap();
+    assert_eq!(
+        storage
+            .get_user(2)
+            .await
+            .unwrap()
+            .unwrap()
+            .deactivated_time
+            .as_deref(),
+        Some("2022-08-27T12:00:00.000Z")
+    );
+    assert!(storage
+        .get_api_key_user("k2", "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_none());
+    storage.set_deactivated(2, None).await.unwrap();
+    assert!(storage
+        .get_api_key_user("k2", "2022-08-27T12:00:00.000Z")
+        .await
+        .unwrap()
+        .is_some());
+}
+
+async fn teams(storage: &dyn Storage) {
+    with_users(storage).await;
+    let mut team = SerdeTeam {
+        id: 0,
+        name: "red".to_string(),
+        member_ids: vec![1, 2],
+    };
+    team.id = storage.insert_team(&team).await.unwrap();
+    assert_eq!(team.id, 1);
+    assert_eq!(storage.get_team(1).await.unwrap().unwrap(), team);
+    team.name = "blue".to_string();
+    team.member_ids = vec![2, 3];
+    storage.update_team(&team).await.unwrap();
+    assert_eq!(
+        storage.get_team_by_name("blue").await.unwrap().unwrap(),
+        team
+    );
+    assert!(storage.get_team_by_name("red").await.unwrap().is_none());
+    assert!(storage.get_team(2).await.unwrap().is_none());
+    assert_eq!(storage.list_teams().await.unwrap(), [team]);
+    assert_eq!(storage.list_user_teams(2).await.unwrap(), [1]);
+    assert!(storage.list_user_teams(1).await.unwrap().is_empty());
+}
+
+async fn groups(storage: &dyn Storage) {
+    with_users(storage).await;
+    let mut group = SerdeGroup {
+        id: 0,
+        name: "class-a".to_string(),
+        member_ids: vec![1, 2],
+    };
+    group.id = storage.insert_group(&group).await.unwrap();
+    assert_eq!(group.id, 1);
+    assert_eq!(storage.get_group(1).await.unwrap().unwrap(), group);
+    storage.rename_group(1, "class-b").await.unwrap();
+    storage.add_group_members(1, &[2, 3]).await.unwrap();
+    group.name = "class-b".to_string();
+    group.member_ids = vec![1, 2, 3];
+    assert_eq!(
+        storage.get_group_by_name("class-b").await.unwrap().unwrap(),
+        group
+    );
+    assert!(storage
+        .get_group_by_name("class-a")
+        .await
+        .unwrap()
+        .is_none());
+    assert!(storage.remove_group_member(1, 1).await.unwrap());
+    assert!(!storage.remove_group_member(1, 1).await.unwrap());
+    assert!(storage.list_user_groups(1).await.unwrap().is_empty());
+    assert_eq!(storage.list_user_groups(3).await.unwrap(), [1]);
+    assert_eq!(storage.list_groups().await.unwrap().len(), 1);
+    assert!(storage.get_group(2).await.unwrap().is_none());
+}
+
+async fn contests(storage: &dyn Storage) {
+    with_users(storage).await;
+    let mut contest = SerdeContest {
+        id: 0,
+        name: "weekly".to_string(),
+        from: "2022-08-27T00:00:00.000Z".to_string(),
+        to: "2022-08-28T00:00:00.000Z".to_string(),
+        problem_ids: vec![0, 1],
+        user_ids: vec![1, 2],
+        submission_limit: 3,
+        team_ids: vec![],
+        group_ids: vec![],
+        registration: RegistrationMode::Password,
+        registration_deadline: None,
+        registration_password: Some("hash".to_string()),
+    };
+    contest.id = storage.insert_contest(&contest).await.unwrap();
+    assert_eq!(contest.id, 1);
+    contest.name = "monthly".to_string();
+    contest.problem_ids = vec![1];
+    contest.team_ids = vec![1];
+    contest.group_ids = vec![1];
+    storage.update_contest(&contest).await.unwrap();
+    let stored = storage.get_contest(1).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.name.as_str(), stored.problem_ids, stored.team_ids),
+        ("monthly", vec![1], vec![1])
+    );
+    assert_eq!(stored.group_ids, [1]);
+    assert_eq!(stored.registration, RegistrationMode::Password);
+    assert_eq!(stored.registration_password.as_deref(), Some("hash"));
+    assert!(storage.add_contest_user(1, 3).await.unwrap());
+    assert!(!storage.add_contest_user(1, 3).await.unwrap());
+    assert!(!storage.add_contest_user(9, 3).await.unwrap());
+    assert!(storage.remove_contest_user(1, 1).await.unwrap());
+    assert!(!storage.remove_contest_user(1, 1).await.unwrap());
+    assert_eq!(
+        storage.get_contest(1).await.unwrap().unwrap().user_ids,
+        [2, 3]
+    );
+    assert_eq!(storage.list_contests().await.unwrap().len(), 1);
+    assert!(storage.get_contest(2).await.unwrap().is_none());
+}
+
+async fn registrations(storage: &dyn Storage) {
+    with_contest(storage).await;
+    let mut registration = Registration {
+        contest_id: 1,
+        user_id: 3,
+        state: RegistrationState::Pending,
+        created_time: "2022-08-27T00:00:00.000Z".to_string(),
+        updated_time: "2022-08-27T00:00:00.000Z".to_string(),
+    };
+    storage.save_registration(&registration).await.unwrap();
+    registration.user_id = 2;
+    storage.save_registration(&registration).await.unwrap();
+    registration.state = RegistrationState::Approved;
+    registration.updated_time = "2022-08-27T01:00:00.000Z".to_string();
+    storage.save_registration(&registration).await.unwrap();
+    assert_eq!(
+        storage.get_registration(1, 2).await.unwrap().unwrap(),
+        registration
+    );
+    let pending = storage
+        .list_registrations(1, Some(RegistrationState::Pending))
+        .await
+        .unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].user_id, 3);
+    assert_eq!(storage.list_registrations(1, None).await.unwrap().len(), 2);
+    assert!(storage.delete_registration(1, 3).await.unwrap());
+    assert!(!storage.delete_registration(1, 3).await.unwrap());
+    assert!(storage.get_registration(1, 3).await.unwrap().is_none());
+}
+
+async fn jobs(storage: &dyn Storage) {
+    with_jobs(storage).await;
+    let stored = storage.get_job(0).await.unwrap().unwrap();
+    assert_eq!(stored.submission.source_code, "// job 0");
+    assert_eq!(stored.cases.len(), 3);
+    assert!(storage.get_job(9).await.unwrap().is_none());
+
+    let all = JobsFilter::default();
+    assert_eq!(ids(&storage.list_jobs(&all).await.unwrap()), [0, 1, 2, 3]);
+    assert_eq!(storage.count_jobs(&all).await.unwrap(), 4);
+    let rust = JobsFilter {
+        user_id: Some(1),
+        language: Some("Rust".to_string()),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&rust).await.unwrap()), [0, 2, 3]);
+    let between = JobsFilter {
+        from: Some("2022-08-27T02:05:01.000Z".to_string()),
+        to: Some("2022-08-27T02:05:02.000Z".to_string()),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&between).await.unwrap()), [1, 2]);
+    let contest_jobs = JobsFilter {
+        contest_id: Some(1),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&contest_jobs).await.unwrap()), [3]);
+    let team_jobs = JobsFilter {
+        team_id: Some(1),
+        ..JobsFilter::default()
+    };
+    let stored = storage.list_jobs(&team_jobs).await.unwrap();
+    assert_eq!(ids(&stored), [3]);
+    assert_eq!(stored[0].submission.team_id, Some(1));
+
+    assert_eq!(storage.count_submissions(1, 1, 0).await.unwrap(), 1);
+    assert_eq!(storage.count_submissions(1, 1, 1).await.unwrap(), 1);
+    assert_eq!(storage.count_submissions(2, 1, 0).await.unwrap(), 0);
+    assert_eq!(storage.count_team_submissions(1, 1, 1).await.unwrap(), 1);
+    assert_eq!(storage.count_team_submissions(1, 0, 1).await.unwrap(), 0);
+}
+
+async fn job_pages(storage: &dyn Storage) {
+    with_jobs(storage).await;
+    let page = JobsFilter {
+        limit: Some(3),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&page).await.unwrap()), [0, 1, 2]);
+    let page = JobsFilter {
+        limit: Some(3),
+        cursor: Some(2),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&page).await.unwrap()), [3]);
+    let page = JobsFilter {
+        limit: Some(2),
+        cursor: Some(2),
+        order: Some(SortOrder::desc),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&page).await.unwrap()), [1, 0]);
+}
+
+async fn judging(storage: &dyn Storage) {
+    with_jobs(storage).await;
+    storage
+        .update_job(0, &JobUpdate::state("Running"))
+        .await
+        .unwrap();
+    storage
+        .update_case(0, 1, &CaseUpdate::result("Running"))
+        .await
+        .unwrap();
+    let accepted = CaseUpdate {
+        result: Some("Accepted".to_string()),
+        time: Some(1200),
+        info: Some("ok".to_string()),
+    };
+    storage.update_case(0, 1, &accepted).await.unwrap();
+    let finished = JobUpdate {
+        state: Some("Finished".to_string()),
+        result: Some("Accepted".to_string()),
+        score: Some(100.0),
+    };
+    storage.update_job(0, &finished).await.unwrap();
+    let stored = storage.get_job(0).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.state.as_str(), stored.result.as_str(), stored.score),
+        ("Finished", "Accepted", 100.0)
+    );
+    assert_eq!(
+        (stored.cases[1].result.as_str(), stored.cases[1].time),
+        ("Accepted", 1200)
+    );
+    assert_eq!(stored.cases[1].info, "ok");
+    assert_eq!(stored.cases[2].result, "Waiting");
+    let done = JobsFilter {
+        state: Some("Finished".to_string()),
+        ..JobsFilter::default()
+    };
+    assert_eq!(ids(&storage.list_jobs(&done).await.unwrap()), [0]);
+
+    storage
+        .reset_job(0, "2022-08-27T03:00:00.000Z")
+        .await
+        .unwrap();
+    let stored = storage.get_job(0).await.unwrap().unwrap();
+    assert_eq!(
+        (stored.state.as_str(), stored.result.as_str(), stored.score),
+        ("Queueing", "Waiting", 0.0)
+    );
+    assert_eq!(stored.updated_time, "2022-08-27T03:00:00.000Z");
+    assert!(stored
+        .cases
+        .iter()
+        .all(|case| case.result == "Waiting" && case.time == 0));
+}
+
+async fn statistics(storage: &dyn Storage) {
+    with_jobs(storage).await;
+    let finished = JobUpdate {
+        state: Some("Finished".to_string()),
+        result: Some("Accepted".to_string()),
+        score: Some(100.0),
+    };
+    storage.update_job(0, &finished).await.unwrap();
+    let statistics = storage.user_statistics(1).await.unwrap();
+    assert_eq!((statistics.submissions, statistics.accepted), (3, 1));
+    assert_eq!(
+        (
+            statistics.solved_problem_ids,
+            statistics.attempted_problem_ids
+        ),
+        (vec![0], vec![1])
+    );
+    assert_eq!(statistics.results.get("Accepted"), Some(&1));
+    assert_eq!(statistics.results.get("Waiting"), Some(&2));
+    assert_eq!(statistics.languages.get("Rust"), Some(&3));
+    let contests = storage.user_contests(1).await.unwrap();
+    assert_eq!(contests.len(), 1);
+    assert_eq!(
+        (
+            contests[0].contest_id,
+            contests[0].team_id,
+            contests[0].rank
+        ),
+        (1, Some(1), None)
+    );
+    assert_eq!(contests[0].contest_name, "monthly");
+    assert!(storage.user_contests(2).await.unwrap().is_empty());
+    assert_eq!(storage.user_statistics(3).await.unwrap().submissions, 0);
+}
+
+async fn problems(storage: &dyn Storage) {
+    migrations::migrate(storage).await.unwrap();
+    storage.save_problem(4, "{\"id\":4}").await.unwrap();
+    storage.save_problem(2, "{\"id\":2}").await.unwrap();
+    storage
+        .save_problem(4, "{\"id\":4,\"name\":\"sum\"}")
+        .await
+        .unwrap();
+    let mut problems = storage.list_problems().await.unwrap();
+    problems.sort();
+    assert_eq!(problems, ["{\"id\":2}", "{\"id\":4,\"name\":\"sum\"}"]);
+    assert!(storage.delete_problem(2).await.unwrap());
+    assert!(!storage.delete_problem(2).await.unwrap());
+    assert_eq!(storage.list_problems().await.unwrap().len(), 1);
+}
+
+async fn anonymization(storage: &dyn Storage) {
+    with_jobs(storage).await;
+    storage
+        .set_password_hash(1, "$argon2id$hash")
+        .await
+        .unwrap();
+    let anonymize = AuditEntry {
+        time: "2022-08-28T00:00:00.000Z".to_string(),
+        actor_id: 0,
+        action: "anonymize".to_string(),
+        user_id: 1,
+        ..AuditEntry::default()
+    };
+    let mut job_ids = storage
+        .anonymize_user("anonymous-1", &anonymize)
+        .await
+        .unwrap();
+    job_ids.sort();
+    assert_eq!(job_ids.len(), 3);
+    let user = storage.get_user(1).await.unwrap().unwrap();
+    assert_eq!(user.name, "anonymous-1");
+    assert!(user.deactivated_time.is_some());
+    assert!(storage.get_password_hash(1).await.unwrap().is_none());
+    for job_id in &job_ids {
+        let stored = storage.get_job(*job_id).await.unwrap().unwrap();
+        assert_eq!(stored.submission.source_code, "");
+        assert_eq!(stored.submission.user_id, 1);
+    }
+    assert_eq!(storage.user_statistics(1).await.unwrap().submissions, 3);
+    // The anonymization stored its own audit entry.
+    assert_eq!(
+        storage.list_audit(10).await.unwrap(),
+        [AuditEntry {
+            id: 1,
+            detail: "3 job(s)".to_string(),
+            ..anonymize
+        }]
+    );
+}
+
+async fn audit_log(storage: &dyn Storage) {
+    migrations::migrate(storage).await.unwrap();
+    let entry = AuditEntry {
+        time: "2022-08-28T00:00:00.000Z".to_string(),
+        actor_id: 0,
+        action: "deactivate".to_string(),
+        user_id: 2,
+        ..AuditEntry::default()
+    };
+    assert_eq!(storage.insert_audit(&entry).await.unwrap(), 1);
+    let entry = AuditEntry {
+        action: "activate".to_string(),
+        ..entry
+    };
+    assert_eq!(storage.insert_audit(&entry).await.unwrap(), 2);
+    let entries = storage.list_audit(10).await.unwrap();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| (entry.id, entry.action.as_str()))
+            .collect::<Vec<_>>(),
+        [(2, "activate"), (1, "deactivate")]
+    );
+    assert_eq!(storage.list_audit(1).await.unwrap().len(), 1);
+}
+
+async fn bulk_import(storage: &dyn Storage) {
+    with_users(storage).await;
+    let rows = import::parse(
+        "name,display_name,group,password\n\
+         erin, Erin E. ,term-1,erinpass1\n\
+         \n\
+         frank,,,\n\
+         dave,,term-1,short\n\
+         frank\n",
+    )
+    .unwrap();
+    assert_eq!(
+        rows.iter()
+            .map(|row| (row.line, row.name.as_str()))
+            .collect::<Vec<_>>(),
+        [(2, "erin"), (4, "frank"), (5, "dave"), (6, "frank")]
+    );
+    assert_eq!(rows[0].display_name.as_deref(), Some("Erin E."));
+    assert_eq!(rows[1].group, None);
+    assert!(import::parse("a,b,c,d,e\n").is_err());
+    let report = import::import(storage, &rows, false).await.unwrap();
+    assert_eq!((report.created, report.errors), (0, 2));
+    assert!(report.rows[0].error.is_none());
+    assert!(report.rows[2]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("already exists"));
+    assert!(report.rows[3]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("more than once"));
+    assert!(storage.get_user_by_name("erin").await.unwrap().is_none());
+    let report = import::import(storage, &rows[..2], true).await.unwrap();
+    assert_eq!(
+        (report.dry_run, report.created, report.errors),
+        (true, 0, 0)
+    );
+    assert!(storage.get_user_by_name("erin").await.unwrap().is_none());
+    let report = import::import(storage, &rows[..2], false).await.unwrap();
+    assert_eq!((report.created, report.errors), (2, 0));
+    assert_eq!(report.rows[0].password, None);
+    assert_eq!(report.rows[1].password.as_ref().unwrap().len(), 16);
+    let erin = storage.get_user_by_name("erin").await.unwrap().unwrap();
+    assert_eq!(Some(erin.id), report.rows[0].user_id);
+    assert_eq!(erin.display_name.as_deref(), Some("Erin E."));
+    assert!(storage.get_password_hash(erin.id).await.unwrap().is_some());
+    let group = storage.get_group_by_name("term-1").await.unwrap().unwrap();
+    assert_eq!(Some(group.id), report.rows[0].group_id);
+    assert_eq!(group.member_ids, [erin.id]);
+    // The whole import is undone when one user cannot be created.
+    let users = |names: &[&str]| -> Vec<NewUser> {
+        names
+            .iter()
+            .map(|name| NewUser {
+                name: name.to_string(),
+                display_name: None,
+                group: Some("term-2".to_string()),
+                password_hash: "$argon2id$hash".to_string(),
+            })
+            .collect()
+    };
+    assert!(storage
+        .import_users(&users(&["gina", "erin"]))
+        .await
+        .is_err());
+    assert!(storage.get_user_by_name("gina").await.unwrap().is_none());
+    assert!(storage.get_group_by_name("term-2").await.unwrap().is_none());
+    let ids = storage
+        .import_users(&users(&["gina", "hank"]))
+        .await
+        .unwrap();
+    assert_eq!(ids[0].1, ids[1].1);
+}
+
+/// Cases on the PostgreSQL backend share one database, so they take turns.
+static POSTGRES: Mutex<()> = Mutex::const_new(());
+
+async fn open_sqlite(case: &str) -> (Arc<dyn Storage>, std::path::PathBuf) {
+    let path =
+        std::env::temp_dir().join(format!("mroj-storage-{}-{}.db", std::process::id(), case));
+    let config = config::Storage {
+        database_path: path.to_str().unwrap().to_string(),
+        ..config::Storage::default()
+    };
+    (storage::open(&config, true).await.unwrap(), path)
+}
+
+async fn open_postgres() -> Arc<dyn Storage> {
+    let url = std::env::var("MROJ_TEST_POSTGRES_URL")
+        .expect("MROJ_TEST_POSTGRES_URL must point to a database the tests may wipe");
+    let config = config::Storage {
+        backend: Backend::Postgres,
+        database_url: Some(url),
+        ..config::Storage::default()
+    };
+    storage::open(&config, true).await.unwrap()
+}
+
+/// A test of every case for each backend.
+macro_rules! backends {
+    ($($case:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    let (storage, path) = super::open_sqlite(stringify!($case)).await;
+                    super::$case(storage.as_ref()).await;
+                    let _ = std::fs::remove_file(&path);
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                #[ignore = "needs MROJ_TEST_POSTGRES_URL"]
+                async fn $case() {
+                    let _turn = super::POSTGRES.lock().await;
+                    let storage = super::open_postgres().await;
+                    super::$case(storage.as_ref()).await;
+                }
+            )*
+        }
+    };
+}
+
+backends!(
+    schema,
+    users,
+    sessions,
+    api_keys,
+    teams,
+    groups,
+    contests,
+    registrations,
+    jobs,
+    job_pages,
+    judging,
+    statistics,
+    problems,
+    anonymization,
+    audit_log,
+    bulk_import,
+);
@@ -0,0 +1,170 @@
+//! The contests on a user profile are ranked like the ranklist of each contest, and
+//! anonymization does not take a name that is in use.
+
+use actix_web::web::Data;
+use oj::config::Problem;
+use oj::contests::{self, SerdeContest};
+use oj::handler::jobs::RankFilter;
+use oj::runner::{SerdeJob, SerdeSubmission};
+use oj::storage::{self, Storage};
+use oj::teams::SerdeTeam;
+use oj::users;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn job(id: u32, user_id: u32, team_id: Option<u32>, problem_id: u32, score: f32) -> SerdeJob {
+    SerdeJob {
+        id,
+        created_time: format!("2022-08-27T02:05:{:02}.000Z", id),
+        updated_time: format!("2022-08-27T02:05:{:02}.000Z", id),
+        submission: SerdeSubmission {
+            source_code: format!("// job {}", id),
+            language: "Rust".to_string(),
+            user_id,
+            contest_id: 1,
+            problem_id,
+            team_id,
+        },
+        state: "Finished".to_string(),
+        result: "Accepted".to_string(),
+        score,
+        cases: vec![],
+    }
+}
+
+#[actix_web::test]
+async fn profile_ranks_match_the_ranklist() {
+    let dir = std::env::temp_dir().join(format!("mroj-users-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = oj::config::Storage {
+        database_path: dir.join("data.db").to_str().unwrap().to_string(),
+        ..oj::config::Storage::default()
+    };
+    let storage = storage::open(&config, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    let storage: Data<dyn Storage> = Data::from(storage);
+    let prob_map: Data<Arc<Mutex<HashMap<u32, Problem>>>> =
+        Data::new(Arc::new(Mutex::new(HashMap::new())));
+
+    for name in ["root", "alice", "bob", "carol", "dave"] {
+        storage.insert_user(name).await.unwrap();
+    }
+    let team_id = storage
+        .insert_team(&SerdeTeam {
+            id: 0,
+            name: "pair".to_string(),
+            member_ids: vec![3, 4],
+        })
+        .await
+        .unwrap();
+    let contest_id = storage
+        .insert_contest(&SerdeContest {
+            name: "monthly".to_string(),
+            from: "2022-08-27T00:00:00.000Z".to_string(),
+            to: "2022-08-28T00:00:00.000Z".to_string(),
+            problem_ids: vec![0, 1],
+            user_ids: vec![1, 2],
+            team_ids: vec![team_id],
+            ..SerdeContest::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(contest_id, 1);
+    // Bob has the best score but is deactivated, so he is not on the ranklist.
+    let jobs = [
+        job(0, 1, None, 0, 50.0),
+        job(1, 1, None, 0, 40.0),
+        job(2, 2, None, 0, 100.0),
+        job(3, 3, Some(team_id), 0, 100.0),
+        job(4, 4, Some(team_id), 1, 50.0),
+    ];
+    for job in &jobs {
+        storage.insert_job(job).await.unwrap();
+    }
+    storage
+        .set_deactivated(2, Some("2022-08-27T12:00:00.000Z"))
+        .await
+        .unwrap();
+
+    let ranklist = contests::get_ranklist(
+        storage.clone(),
+        prob_map.clone(),
+        RankFilter::default(),
+        contest_id,
+    )
+    .await
+    .unwrap();
+    for user_id in [1, 3, 4] {
+        let profile = users::get_profile(storage.clone(), prob_map.clone(), user_id)
+            .await
+            .unwrap();
+        assert_eq!(profile.contests.len(), 1);
+        let record = &profile.contests[0];
+        let row = ranklist
+            .iter()
+            .find(|row| match record.team_id {
+                Some(id) => row.team.as_ref().map(|team| team.id) == Some(id),
+                None => row.user.as_ref().map(|user| user.id) == Some(user_id),
+            })
+            .unwrap();
+        assert_eq!(record.rank, Some(row.rank));
+        assert_eq!(record.score, row.scores.iter().sum::<f32>());
+    }
+    let alice = users::get_profile(storage.clone(), prob_map.clone(), 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        (alice.contests[0].score, alice.contests[0].rank),
+        (50.0, Some(2))
+    );
+    let dave = users::get_profile(storage.clone(), prob_map.clone(), 4)
+        .await
+        .unwrap();
+    assert_eq!(
+        (
+            dave.contests[0].team_id,
+            dave.contests[0].score,
+            dave.contests[0].rank
+        ),
+        (Some(team_id), 150.0, Some(1))
+    );
+    let bob = users::get_profile(storage, prob_map, 2).await.unwrap();
+    assert_eq!((bob.contests[0].score, bob.contests[0].rank), (0.0, None));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn anonymize_refuses_a_taken_name() {
+    let dir = std::env::temp_dir().join(format!("mroj-anonymize-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = oj::config::Storage {
+        database_path: dir.join("data.db").to_str().unwrap().to_string(),
+        ..oj::config::Storage::default()
+    };
+    let storage = storage::open(&config, true).await.unwrap();
+    oj::migrations::migrate(storage.as_ref()).await.unwrap();
+    let storage: Data<dyn Storage> = Data::from(storage);
+    for name in ["root", "alice", "anonymous-1"] {
+        storage.insert_user(name).await.unwrap();
+    }
+
+    let res = users::anonymize(storage.clone(), 0, 1).await.unwrap_err();
+    let body: serde_json::Value =
+        serde_json::from_slice(&actix_web::body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body["code"], 2);
+    let alice = storage.get_user(1).await.unwrap().unwrap();
+    assert_eq!(alice.name, "alice");
+    assert!(storage.list_audit(10).await.unwrap().is_empty());
+
+    let (user, job_ids) = users::anonymize(storage.clone(), 0, 2).await.unwrap();
+    assert_eq!((user.name.as_str(), job_ids.len()), ("anonymous-2", 0));
+    let entries = storage.list_audit(10).await.unwrap();
+    assert_eq!(
+        (entries[0].action.as_str(), entries[0].detail.as_str()),
+        ("anonymize", "0 job(s)")
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}